@@ -1,5 +1,11 @@
-use crate::board::{Board, ClipBoard, CHUNK_LEN};
-use crate::rule::{Rule, State};
+use crate::board::{Board, Boundary, ClipBoard, CHUNK_LEN};
+use crate::generations::Generations;
+use crate::history::History;
+use crate::pattern::{self, PatternFormat};
+use crate::platform;
+use crate::recorder::{AnimationFormat, Recorder};
+use crate::rule::{Rule, RuleUi, StateUi};
+use crate::statistics::Statistics;
 use crate::world::World;
 
 use anyhow::anyhow;
@@ -7,8 +13,6 @@ use anyhow::Context as _;
 use rand::SeedableRng;
 use serde::{Deserialize, Serialize};
 
-use wasm_bindgen::JsCast;
-
 /// An application to manage a cell automaton.
 ///
 /// Several application can run at the same time but only the focused app will
@@ -29,6 +33,12 @@ pub struct App<W: World> {
     pub(crate) rng: rand::rngs::StdRng,
     pub(crate) err: Option<String>,
     pub(crate) cursor_is_on_sidepanel: bool, // at the last frame
+    pub(crate) file_path: String,            // file name on web, file path on native
+    pub(crate) png_cell_width: u32,          // pixels per cell of exported images
+    pub(crate) recorder: Recorder,
+    pub(crate) statistics: Statistics,
+    pub(crate) history: History<<<W as World>::Rule as Rule>::CellState>,
+    pub(crate) generations: Generations<<<W as World>::Rule as Rule>::CellState>,
 
     pub(crate) clipboard: Option<ClipBoard<<<W as World>::Rule as Rule>::CellState>>,
     pub(crate) secondary_start: Option<(usize, usize)>,
@@ -62,6 +72,12 @@ impl<W: World> Default for App<W> {
             rng: rand::rngs::StdRng::seed_from_u64(123456789),
             err: None,
             cursor_is_on_sidepanel: false,
+            file_path: "world.json".to_string(),
+            png_cell_width: 8,
+            recorder: Recorder::default(),
+            statistics: Statistics::default(),
+            history: History::default(),
+            generations: Generations::default(),
             clipboard: None,
             secondary_start: None,
             secondary_curr: None,
@@ -82,7 +98,7 @@ impl Clicked {
 
 impl<W> App<W>
 where
-    for<'de> W: World + Deserialize<'de>,
+    for<'de> W: World + Serialize + Deserialize<'de>,
 {
     pub fn new(rule: <W as World>::Rule) -> Self {
         Self {
@@ -136,29 +152,327 @@ where
         if dropped_files.is_empty() {
             return Ok(());
         }
-        if let Some(file) = dropped_files.iter().find(|f| f.name.ends_with(".json")) {
-            if let Some(bytes) = &file.bytes {
-                let content = std::str::from_utf8(bytes)
-                    .context(format!("Couldn't read file content as utf8 -> {}", file.name))?
-                    .to_owned();
-                self.world = serde_json::from_str(&content)
-                    .context(format!("Couldn't load file content as board -> {}", file.name))?;
-                Ok(())
-            } else {
-                Err(anyhow!("file {} could not read", file.name))
-            }
+        if let Some(file) =
+            dropped_files.iter().find(|f| platform::dropped_file_name(f).ends_with(".json"))
+        {
+            let name = platform::dropped_file_name(file);
+            let bytes = platform::dropped_file_content(file)?;
+            self.load_world(&name, &bytes)
+        } else if let Some((file, format)) = dropped_files.iter().find_map(|f| {
+            PatternFormat::from_filename(&platform::dropped_file_name(f)).map(|fmt| (f, fmt))
+        }) {
+            let name = platform::dropped_file_name(file);
+            let bytes = platform::dropped_file_content(file)?;
+            self.load_pattern(&name, format, &bytes)
         } else {
             Err(anyhow!(
-                "only json deserializaion is supported. file \"{:?}\" ignored",
-                dropped_files.into_iter().map(|f| f.name).collect::<Vec<String>>()
+                "only json deserializaion and pattern files are supported. file \"{:?}\" ignored",
+                dropped_files.iter().map(platform::dropped_file_name).collect::<Vec<String>>()
             ))
         }
     }
+
+    /// Replace the current world by the serialized one. The rule will be kept.
+    fn load_world(&mut self, name: &str, bytes: &[u8]) -> anyhow::Result<()> {
+        let content = std::str::from_utf8(bytes)
+            .context(format!("Couldn't read file content as utf8 -> {}", name))?;
+        let mut world: W = serde_json::from_str(content)
+            .context(format!("Couldn't load file content as board -> {}", name))?;
+
+        // rule is not serialized
+        *world.rule_mut() = std::mem::take(self.world.rule_mut());
+        self.world = world;
+        self.history.clear();
+        self.generations.clear();
+        self.statistics.clear();
+        self.statistics.record(&self.world);
+        Ok(())
+    }
+
+    /// Save the current world as `self.file_path`.
+    fn save_world(&self) -> anyhow::Result<()> {
+        let serialized =
+            serde_json::to_string(&self.world).context("Failed to serialize the world")?;
+        platform::save_file(&self.file_path, "application/json", serialized.as_bytes())
+    }
+
+    /// Load a pattern file into the clipboard.
+    fn load_pattern(
+        &mut self,
+        name: &str,
+        format: PatternFormat,
+        bytes: &[u8],
+    ) -> anyhow::Result<()> {
+        let content = std::str::from_utf8(bytes)
+            .context(format!("Couldn't read file content as utf8 -> {}", name))?;
+        let cb = self
+            .world
+            .rule()
+            .load_pattern(format, content)
+            .context(format!("Couldn't load file content as pattern -> {}", name))?;
+        self.clipboard = Some(cb);
+
+        // the pattern is loaded anyway, but it may behave differently
+        let rule = self.world.rule().pattern_rule();
+        if let (Some(theirs), Some(ours)) = (pattern::rule_of(format, content), rule) {
+            if !pattern::is_same_rule(&theirs, &ours) {
+                self.err = Some(format!(
+                    "the pattern is written for the rule {}, not {} of this world -> {}",
+                    theirs, ours, name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Save the selected region, or the whole board if nothing is selected, as a pattern file.
+    /// The extension of `self.file_path` is replaced by the one of the format.
+    fn save_pattern(&self, format: PatternFormat) -> anyhow::Result<()> {
+        let (start, end) = self
+            .selected_region
+            .unwrap_or(((0, 0), (self.world.board().width() - 1, self.world.board().height() - 1)));
+        let cb = self.copy_region(start, end);
+        let content = self.world.rule().save_pattern(format, &cb)?;
+
+        platform::save_file(
+            &self.file_path_with_extension(format.extension()),
+            "text/plain",
+            content.as_bytes(),
+        )
+    }
+
+    /// Save the whole board as a PNG image. The extension of `self.file_path` is replaced.
+    fn save_png(&self) -> anyhow::Result<()>
+    where
+        <W as World>::Rule: RuleUi,
+    {
+        let img = self.world.render(self.png_cell_width)?;
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(img)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .context("Failed to encode the board as PNG")?;
+        platform::save_file(&self.file_path_with_extension("png"), "image/png", &png)
+    }
+
+    /// The number of chunks prepended to the board, to locate history entries.
+    fn chunk_offset(&self) -> (isize, isize) {
+        let (x, y) = self.world.board().chunk_origin();
+        (-x, -y)
+    }
+
+    /// Take a snapshot of the board before an operation that can be undone.
+    fn begin_edit(&mut self) {
+        self.history.set_enabled(self.world.supports_history());
+        self.history
+            .begin(self.world.board(), self.chunk_offset(), self.world.generation());
+    }
+    /// Record the changes after `begin_edit`. Statistics are also updated.
+    fn commit_edit(&mut self) -> anyhow::Result<()> {
+        if !self.history.is_pending() {
+            return Ok(());
+        }
+        let default = self.world.rule().default_state()?;
+        let generation = self.world.generation();
+        self.history
+            .commit(self.world.board(), self.chunk_offset(), generation, &default);
+        self.statistics.record(&self.world);
+        Ok(())
+    }
+
+    fn undo(&mut self) -> anyhow::Result<()> {
+        self.commit_edit()?;
+        self.running = false;
+        let mut generation = self.world.generation();
+        let offset = self.chunk_offset();
+        self.history.undo(self.world.board_mut(), offset, &mut generation);
+        self.world.set_generation(generation);
+        self.statistics.record(&self.world);
+        Ok(())
+    }
+    fn redo(&mut self) -> anyhow::Result<()> {
+        self.commit_edit()?;
+        self.running = false;
+        let mut generation = self.world.generation();
+        let offset = self.chunk_offset();
+        self.history.redo(self.world.board_mut(), offset, &mut generation);
+        self.world.set_generation(generation);
+        self.statistics.record(&self.world);
+        Ok(())
+    }
+
+    /// Advance the world by one step. If recording, the frame is captured.
+    fn step(&mut self) -> anyhow::Result<()>
+    where
+        <W as World>::Rule: RuleUi,
+    {
+        let origin = self.world.board().chunk_origin();
+        if !self.world.supports_history() {
+            // undo, redo and step back are disabled
+            self.commit_edit()?;
+            self.world.update()?;
+            self.statistics.record(&self.world);
+        } else if self.world.rule().iteration_per_step() == 1 {
+            // the board knows the chunks changed by the step and their states
+            // before it, so the step is recorded without a snapshot
+            self.commit_edit()?;
+            let generation = self.world.generation();
+            self.generations.push(self.world.board(), self.chunk_offset(), generation);
+            self.world.update()?;
+            // an infinite board may grow in the negative direction
+            self.follow_origin(origin);
+            self.history.commit_step(self.world.board(), self.chunk_offset(), generation);
+            self.statistics.record(&self.world);
+        } else {
+            // the board keeps only the states before the last iteration. the
+            // step is compared with the generation pushed before it, which
+            // shares the chunks with the previous ones, instead of a snapshot
+            self.commit_edit()?;
+            let generation = self.world.generation();
+            self.generations.push(self.world.board(), self.chunk_offset(), generation);
+            let shared = !self.generations.is_empty(); // nothing is kept at depth 0
+            if !shared {
+                self.begin_edit();
+            }
+            self.world.update()?;
+            self.follow_origin(origin);
+            if shared {
+                let default = self.world.rule().default_state()?;
+                let (offset, generations) = (self.chunk_offset(), &self.generations);
+                self.history.commit_from(
+                    self.world.board(),
+                    offset,
+                    generation,
+                    self.world.generation(),
+                    &default,
+                    |pos| generations.latest_chunk(pos),
+                );
+                self.statistics.record(&self.world);
+            } else {
+                self.commit_edit()?;
+            }
+        }
+        let (world, cell_width) = (&self.world, self.png_cell_width);
+        self.recorder.on_step(|| world.render(cell_width))
+    }
+
+    /// Show another layer of a 3D world. History and statistics are of the
+    /// previous layer, so they are cleared.
+    fn set_current_layer(&mut self, z: usize) -> anyhow::Result<()> {
+        self.commit_edit()?;
+        self.world.set_current_layer(z);
+        self.history.clear();
+        self.generations.clear();
+        self.statistics.clear();
+        self.statistics.record(&self.world);
+        Ok(())
+    }
+
+    /// Follow the cells after `dx` x `dy` chunks are prepended to the board.
+    /// Keep looking at the same cells after the chunk origin of the board is
+    /// moved from `(x0, y0)` by prepending or removing chunks.
+    fn follow_origin(&mut self, (x0, y0): (isize, isize)) {
+        let (x1, y1) = self.world.board().chunk_origin();
+        if (x0, y0) == (x1, y1) {
+            return;
+        }
+        let board = self.world.board();
+        self.origin.x += (x0 - x1) as f32 * board.chunk_width_px(self.grid_width);
+        self.origin.y += (y0 - y1) as f32 * board.chunk_height_px(self.grid_width);
+
+        let (dx, dy) = ((x0 - x1) * CHUNK_LEN as isize, (y0 - y1) * CHUNK_LEN as isize);
+        let shift = |(x, y): (usize, usize)| {
+            let (x, y) = (x as isize + dx, y as isize + dy);
+            (0 <= x && 0 <= y).then(|| (x as usize, y as usize))
+        };
+        self.inspector = self.inspector.and_then(shift);
+        self.secondary_start = self.secondary_start.and_then(shift);
+        self.secondary_curr = self.secondary_curr.and_then(shift);
+        self.selected_region = self.selected_region.and_then(|(s, e)| Some((shift(s)?, shift(e)?)));
+    }
+
+    /// Remove the chunks at the border that contain only the default state.
+    /// The board size is fixed, otherwise it is expanded again to fill the view.
+    fn auto_crop(&mut self) -> anyhow::Result<()> {
+        let origin = self.world.board().chunk_origin();
+        self.world.auto_crop()?;
+        self.after_crop(origin);
+        Ok(())
+    }
+    /// Remove the chunks that do not overlap with the selected region.
+    fn crop_to_selection(&mut self) {
+        if let Some(((sx, sy), (ex, ey))) = self.selected_region {
+            let origin = self.world.board().chunk_origin();
+            let board = self.world.board();
+            let ex = (ex / CHUNK_LEN + 1).min(board.n_chunks_x());
+            let ey = (ey / CHUNK_LEN + 1).min(board.n_chunks_y());
+            self.world.crop(sx / CHUNK_LEN..ex, sy / CHUNK_LEN..ey);
+            self.after_crop(origin);
+        }
+    }
+    fn after_crop(&mut self, origin: (isize, isize)) {
+        self.follow_origin(origin);
+        self.fix_board_size = true;
+        let board = self.world.board();
+        let inside = |(x, y): (usize, usize)| board.has_cell(x, y).then(|| (x, y));
+        self.inspector = self.inspector.and_then(inside);
+        self.selected_region =
+            self.selected_region.and_then(|(s, e)| Some((inside(s)?, inside(e)?)));
+    }
+
+    /// Stop recording and save the animation. The extension of `self.file_path` is replaced.
+    fn save_recording(&mut self) -> anyhow::Result<()> {
+        let format = self.recorder.format;
+        let animation = self.recorder.stop()?;
+        platform::save_file(
+            &self.file_path_with_extension(format.extension()),
+            format.mime(),
+            &animation,
+        )
+    }
+
+    /// Go back to the previous generation. It can be undone as an edit.
+    fn step_back(&mut self) -> anyhow::Result<()> {
+        let default = self.world.rule().default_state()?;
+        let offset = self.chunk_offset();
+        self.begin_edit();
+        if let Some(generation) = self.generations.pop(self.world.board_mut(), offset, &default) {
+            self.world.set_generation(generation);
+        }
+        self.commit_edit()
+    }
+
+    fn file_path_with_extension(&self, ext: &str) -> String {
+        std::path::Path::new(&self.file_path)
+            .with_extension(ext)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Copy cells in a rectangular region `[start, end]` into a clipboard.
+    fn copy_region(
+        &self,
+        (sx, sy): (usize, usize),
+        (ex, ey): (usize, usize),
+    ) -> ClipBoard<<<W as World>::Rule as Rule>::CellState> {
+        let mut cb =
+            ClipBoard::<<<W as World>::Rule as Rule>::CellState>::new(ex - sx + 1, ey - sy + 1);
+        for j in 0..cb.height() {
+            for i in 0..cb.width() {
+                if self.world.board().has_cell(sx + i, sy + j) {
+                    *cb.cell_at_mut(i, j) =
+                        Some(self.world.board().cell_at(sx + i, sy + j).clone());
+                }
+            }
+        }
+        cb
+    }
 }
 
 impl<W> eframe::App for App<W>
 where
     for<'de> W: World + Serialize + Deserialize<'de>,
+    <W as World>::Rule: RuleUi,
+    <<W as World>::Rule as Rule>::CellState: StateUi,
 {
     /// Called by the frame work to save state before shutdown.
     fn save(&mut self, _storage: &mut dyn eframe::Storage) {
@@ -167,11 +481,58 @@ where
 
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         if self.running {
-            if let Err(e) = self.world.update() {
+            if let Err(e) = self.step() {
+                // do not repeat a step that fails, e.g. a script exceeding its limits
+                self.running = false;
                 self.err = Some(format!("{:?}", e));
             }
         }
 
+        let (undo, redo) = {
+            let mut input_state = ctx.input_mut();
+            let command = egui::Modifiers::COMMAND;
+            let shift = egui::Modifiers::SHIFT;
+
+            // check redo first; otherwise cmd+shift+z is consumed as undo
+            let r = input_state.consume_key(command | shift, egui::Key::Z);
+            let u = input_state.consume_key(command, egui::Key::Z);
+            (u, r)
+        };
+        if undo {
+            if let Err(e) = self.undo() {
+                self.err = Some(format!("{:?}", e));
+            }
+        }
+        if redo {
+            if let Err(e) = self.redo() {
+                self.err = Some(format!("{:?}", e));
+            }
+        }
+
+        // page up/down moves between the layers of a 3D world
+        if 1 < self.world.depth() {
+            let (up, down) = {
+                let mut input_state = ctx.input_mut();
+                let none = egui::Modifiers::NONE;
+                let u = input_state.consume_key(none, egui::Key::PageUp);
+                let d = input_state.consume_key(none, egui::Key::PageDown);
+                (u, d)
+            };
+            let layer = self.world.current_layer();
+            let next = if up {
+                Some((layer + 1).min(self.world.depth() - 1))
+            } else if down {
+                Some(layer.saturating_sub(1))
+            } else {
+                None
+            };
+            if let Some(z) = next.filter(|z| *z != layer) {
+                if let Err(e) = self.set_current_layer(z) {
+                    self.err = Some(format!("{:?}", e));
+                }
+            }
+        }
+
         let sidepanel_response = egui::SidePanel::left("side_panel")
             .show(ctx, |ui| {
                 ui.push_id(0, |ui| {
@@ -216,67 +577,140 @@ where
 
                 ui.separator(); // -------------------------------------------------
 
+                let no_history = if self.world.supports_history() {
+                    "nothing to restore"
+                } else {
+                    "not supported in this world"
+                };
                 ui.horizontal_wrapped(|ui| {
                     ui.toggle_value(&mut self.running, "Run");
 
+                    let back = ui
+                        .add_enabled(!self.generations.is_empty(), egui::Button::new("Step back"))
+                        .on_disabled_hover_text(no_history);
+                    if back.clicked() {
+                        self.running = false;
+                        if let Err(e) = self.step_back() {
+                            self.err = Some(format!("{:?}", e));
+                        }
+                    }
                     if ui.button("Step").clicked() {
-                        if let Err(e) = self.world.update() {
+                        if let Err(e) = self.step() {
                             self.err = Some(format!("{:?}", e));
                         }
                         ui.ctx().request_repaint();
                     }
                     if ui.button("Reset").clicked() {
-                        if let Err(e) = self.world.clear() {
+                        self.begin_edit();
+                        if let Err(e) = self.world.clear().and_then(|_| self.commit_edit()) {
                             self.err = Some(format!("{:?}", e));
                         }
                     }
                     if ui.button("Randomize").clicked() {
-                        if let Err(e) = self.world.randomize(&mut self.rng) {
+                        self.begin_edit();
+                        let randomized = self.world.randomize(&mut self.rng);
+                        if let Err(e) = randomized.and_then(|_| self.commit_edit()) {
                             self.err = Some(format!("{:?}", e));
                         }
                     }
                 });
+                ui.horizontal_wrapped(|ui| {
+                    let undo = ui
+                        .add_enabled(self.history.can_undo(), egui::Button::new("Undo"))
+                        .on_disabled_hover_text(no_history);
+                    if undo.clicked() {
+                        if let Err(e) = self.undo() {
+                            self.err = Some(format!("{:?}", e));
+                        }
+                    }
+                    let redo = ui
+                        .add_enabled(self.history.can_redo(), egui::Button::new("Redo"))
+                        .on_disabled_hover_text(no_history);
+                    if redo.clicked() {
+                        if let Err(e) = self.redo() {
+                            self.err = Some(format!("{:?}", e));
+                        }
+                    }
+                });
+                let mut depth = self.generations.depth();
+                let stored = self.generations.len();
+                let depth_slider = egui::Slider::new(&mut depth, 0..=1024)
+                    .text(format!("generations to step back ({} stored)", stored));
+                if ui.add_enabled(self.world.supports_history(), depth_slider).changed() {
+                    self.generations.set_depth(depth);
+                }
 
                 ui.separator(); // -------------------------------------------------
 
-                if ui.button("serialize").clicked() {
-                    let serialized = serde_json::to_string(&self.world)
-                        .expect("TODO: show error message")
-                        .into_bytes();
-
-                    let uint8arr = js_sys::Uint8Array::new_with_length(serialized.len() as u32);
-                    uint8arr.copy_from(&serialized);
-
-                    let array = js_sys::Array::new();
-                    array.push(&uint8arr.buffer());
-
-                    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(
-                        &array,
-                        web_sys::BlobPropertyBag::new().type_("application/json"),
-                    )
-                    .expect("TODO: show error message");
-
-                    let url = web_sys::Url::create_object_url_with_blob(&blob)
-                        .expect("TODO: show error message");
-
-                    let document = web_sys::window()
-                        .expect("TODO: show error message")
-                        .document()
-                        .expect("TODO: show error message");
-                    let downloadable =
-                        document.create_element("a").expect("TODO: show error message");
-
-                    downloadable
-                        .set_attribute("href", &url)
-                        .expect("TODO: show error message");
-                    downloadable
-                        .set_attribute("download", "world.json")
-                        .expect("TODO: show error message");
-                    downloadable
-                        .dyn_into::<web_sys::HtmlElement>()
-                        .expect("TODO: show error message")
-                        .click();
-                }
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("file");
+                    ui.text_edit_singleline(&mut self.file_path);
+                });
+                ui.horizontal_wrapped(|ui| {
+                    if ui.button("serialize").clicked() {
+                        if let Err(e) = self.save_world() {
+                            self.err = Some(format!("{:?}", e));
+                        }
+                    }
+                    // on web, a file is loaded by drag-and-drop
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if ui.button("load").clicked() {
+                        let path = self.file_path.clone();
+                        let loaded = platform::load_file(&path)
+                            .and_then(|bytes| self.load_world(&path, &bytes));
+                        if let Err(e) = loaded {
+                            self.err = Some(format!("{:?}", e));
+                        }
+                    }
+                });
+                ui.horizontal_wrapped(|ui| {
+                    for format in self.world.rule().pattern_formats() {
+                        let label = format!("export .{}", format.extension());
+                        if ui.button(label).clicked() {
+                            if let Err(e) = self.save_pattern(format) {
+                                self.err = Some(format!("{:?}", e));
+                            }
+                        }
+                    }
+                });
+                ui.horizontal_wrapped(|ui| {
+                    if ui.button("export PNG").clicked() {
+                        if let Err(e) = self.save_png() {
+                            self.err = Some(format!("{:?}", e));
+                        }
+                    }
+                    ui.add(egui::Slider::new(&mut self.png_cell_width, 1..=32).text("px/cell"));
+                });
+                ui.horizontal_wrapped(|ui| {
+                    if self.recorder.is_recording() {
+                        if ui.button("stop recording").clicked() {
+                            if let Err(e) = self.save_recording() {
+                                self.err = Some(format!("{:?}", e));
+                            }
+                        }
+                        if ui.button("cancel").clicked() {
+                            self.recorder.cancel();
+                        }
+                        let full = if self.recorder.is_full() { " (full)" } else { "" };
+                        ui.label(format!("{} frames{}", self.recorder.num_frames(), full));
+                    } else if ui.button("start recording").clicked() {
+                        match self.world.render(self.png_cell_width) {
+                            Ok(first) => self.recorder.start(first),
+                            Err(e) => self.err = Some(format!("{:?}", e)),
+                        }
+                    }
+                });
+                ui.horizontal_wrapped(|ui| {
+                    ui.radio_value(&mut self.recorder.format, AnimationFormat::Gif, "GIF");
+                    ui.radio_value(&mut self.recorder.format, AnimationFormat::Apng, "APNG");
+                });
+                ui.add(
+                    egui::Slider::new(&mut self.recorder.interval, 1..=100)
+                        .text("record every k steps"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut self.recorder.delay_ms, 10..=1000).text("delay [ms]"),
+                );
 
                 ui.separator(); // -------------------------------------------------
 
@@ -289,15 +723,52 @@ where
                     );
                     ui.checkbox(&mut self.fix_grid_size, "Fix Grid Size");
                 });
-                ui.checkbox(&mut self.fix_board_size, "Fix Board Size");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.fix_board_size, "Fix Board Size");
+                    if ui.button("Auto Crop").clicked() {
+                        if let Err(e) = self.auto_crop() {
+                            self.err = Some(format!("{:?}", e));
+                        }
+                    }
+                    let selected = self.selected_region.is_some();
+                    if ui.add_enabled(selected, egui::Button::new("Crop to Selection")).clicked() {
+                        self.crop_to_selection();
+                    }
+                });
 
                 ui.label("On browser, PC trackpad does not work. Instead, change click mode.");
                 ui.radio_value(&mut self.click_mode, ClickMode::Normal, "Normal mode");
                 ui.radio_value(&mut self.click_mode, ClickMode::Grab, "Grab mode");
                 ui.radio_value(&mut self.click_mode, ClickMode::Inspect, "Inspect mode");
 
+                if let Some(mut boundary) = self.world.boundary() {
+                    ui.label("boundary condition:");
+                    ui.horizontal_wrapped(|ui| {
+                        for b in Boundary::all() {
+                            ui.radio_value(&mut boundary, b, b.name());
+                        }
+                    });
+                    if self.world.boundary() != Some(boundary) {
+                        if let Err(e) = self.world.set_boundary(boundary) {
+                            self.err = Some(format!("{:?}", e));
+                        }
+                    }
+                }
+
                 ui.separator();
                 ui.label("status:");
+                ui.label(format!("generation: {}", self.world.generation()));
+                if 1 < self.world.depth() {
+                    let mut layer = self.world.current_layer();
+                    let slider =
+                        egui::Slider::new(&mut layer, 0..=self.world.depth() - 1).text("layer");
+                    if ui.add(slider).changed() {
+                        if let Err(e) = self.set_current_layer(layer) {
+                            self.err = Some(format!("{:?}", e));
+                        }
+                    }
+                }
+                self.world.ui(ui);
                 ui.label(format!(
                     "current cells: {}x{}",
                     self.world.board().width(),
@@ -312,6 +783,19 @@ where
 
                 ui.separator(); // -------------------------------------------------
 
+                egui::CollapsingHeader::new("statistics").show(ui, |ui| {
+                    if ui.checkbox(&mut self.statistics.enabled, "count cells each step").changed()
+                    {
+                        self.statistics.clear();
+                        self.statistics.record(&self.world);
+                    }
+                    if self.statistics.enabled {
+                        self.statistics.ui(ui);
+                    }
+                });
+
+                ui.separator(); // -------------------------------------------------
+
                 for (name, clip) in self.world.rule().library().into_iter() {
                     if ui.button(name).clicked() {
                         self.clipboard = Some(clip)
@@ -320,8 +804,10 @@ where
 
                 // we can only know the cursor hovers on sidepanel after drawing
                 // sidepanel, so we use the status of the last frame
-                if let Err(e) = self.world.rule_mut().ui(ui, ctx, self.cursor_is_on_sidepanel) {
-                    self.err = Some(format!("{:?}", e));
+                match self.world.rule_mut().ui(ui, ctx, self.cursor_is_on_sidepanel) {
+                    Ok(true) => self.world.board_mut().mark_all_changed(),
+                    Ok(false) => {}
+                    Err(e) => self.err = Some(format!("{:?}", e)),
                 }
             })
             .response;
@@ -490,6 +976,11 @@ where
                 } else if primary.is_none() && secondary.is_none() {
                     self.cell_modifying = None;
 
+                    // a stroke of cell edits ends here
+                    if let Err(e) = self.commit_edit() {
+                        self.err = Some(format!("{:?}", e));
+                    }
+
                     if let Some((sx, sy)) = self.secondary_start {
                         // set selected region
                         let (ex, ey) = self
@@ -578,31 +1069,23 @@ where
 
                     // copy region to clipboard
                     if copy || cut {
-                        let mut cb = ClipBoard::<<<W as World>::Rule as Rule>::CellState>::new(
-                            ex - sx + 1,
-                            ey - sy + 1,
-                        );
-                        for j in 0..cb.height() {
-                            for i in 0..cb.width() {
-                                if self.world.board().has_cell(sx + i, sy + j) {
-                                    *cb.cell_at_mut(i, j) =
-                                        Some(self.world.board().cell_at(sx + i, sy + j).clone());
-                                }
-                            }
-                        }
                         // overwrite
-                        self.clipboard = Some(cb);
+                        self.clipboard = Some(self.copy_region((sx, sy), (ex, ey)));
                     }
 
                     // clear selected region
                     if cut || del {
                         match self.world.rule().default_state() {
                             Ok(st) => {
+                                self.begin_edit();
                                 for j in sy..=ey {
                                     for i in sx..=ex {
                                         *self.world.board_mut().cell_at_mut(i, j) = st.clone();
                                     }
                                 }
+                                if let Err(e) = self.commit_edit() {
+                                    self.err = Some(format!("{:?}", e));
+                                }
                             }
                             Err(e) => {
                                 self.err = Some(format!("{:?}", e));
@@ -610,7 +1093,8 @@ where
                         }
                     }
                 } else if let Some((ix, iy)) = primary {
-                    // draw cell using `cell_modifying`
+                    // draw cell using `cell_modifying`. the whole stroke is undone at once
+                    self.begin_edit();
 
                     if let Some(next) = &self.cell_modifying {
                         *self.world.board_mut().cell_at_mut(ix, iy) = next.clone();
@@ -678,10 +1162,12 @@ where
                         input_state.consume_key(egui::Modifiers::COMMAND, egui::Key::V)
                     };
                     if self.clipboard.is_some() && paste {
-                        let cb = self.clipboard.as_ref().expect("already checked");
+                        // expansion requires `&mut self`. put it back after pasting.
+                        let cb = self.clipboard.take().expect("already checked");
                         let mut ofs_x = (cursor_x as isize) - (cb.width() as isize) / 2;
                         let mut ofs_y = (cursor_y as isize) - (cb.height() as isize) / 2;
 
+                        self.begin_edit();
                         if let Ok(st) = self.world.rule().default_state() {
                             // check if clipboard sticks out of the board
                             if ofs_x < 0 {
@@ -725,11 +1211,15 @@ where
                             if let Err(e) = self.world.board_mut().paste_clipboard(
                                 ofs_x as usize,
                                 ofs_y as usize,
-                                cb,
+                                &cb,
                             ) {
                                 self.err = Some(format!("{:?}", e));
                             }
                         }
+                        if let Err(e) = self.commit_edit() {
+                            self.err = Some(format!("{:?}", e));
+                        }
+                        self.clipboard = Some(cb);
                     }
                 }
             }
@@ -0,0 +1,255 @@
+//! Bit-packed chunks of `LifeGameState` and a bit-parallel update of life-like rules.
+//!
+//! A row of a chunk is packed into a `u16`, bit `i` being the cell at `x = i`.
+//! Boards of `LifeGameState` store their chunks in this form, and the cells
+//! are unpacked only when they are read or written one by one.
+//!
+//! The numbers of live neighbors of the 16 cells in a row are counted at once
+//! by adding the shifted rows of the neighbors to 4-bit counters sliced into
+//! bit planes. `update_life` takes this path under the periodic, fixed and
+//! infinite boundaries, for neighborhoods within the adjacent cells and with
+//! less than 16 neighbors, e.g. Moore, von Neumann and hexagonal ones. Other
+//! boundaries flip the rows and other neighborhoods overflow the counters, so
+//! they fall back to `world::update_parallel` that counts cell by cell.
+
+use crate::board::{Board, Boundary, CellMut, ChunkCells, CHUNK_LEN};
+use crate::lifegame::LifeGameState;
+use crate::rule::{Neighbors, Rule};
+use crate::world;
+
+// a row is packed into u16
+const _: () = assert!(CHUNK_LEN == 16);
+
+/// A chunk of `LifeGameState` packed into bits.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct BitChunk {
+    rows: [u16; CHUNK_LEN],
+}
+
+impl ChunkCells<LifeGameState> for BitChunk {
+    fn filled(state: LifeGameState) -> Self {
+        match state {
+            LifeGameState::Alive => Self { rows: [!0; CHUNK_LEN] },
+            LifeGameState::Dead => Self::default(),
+        }
+    }
+    fn get(&self, idx: usize) -> &LifeGameState {
+        if self.rows[idx / CHUNK_LEN] & (1 << (idx % CHUNK_LEN)) != 0 {
+            &LifeGameState::Alive
+        } else {
+            &LifeGameState::Dead
+        }
+    }
+    fn set(&mut self, idx: usize, state: LifeGameState) {
+        let bit = 1 << (idx % CHUNK_LEN);
+        match state {
+            LifeGameState::Alive => self.rows[idx / CHUNK_LEN] |= bit,
+            LifeGameState::Dead => self.rows[idx / CHUNK_LEN] &= !bit,
+        }
+    }
+    fn get_mut(&mut self, idx: usize) -> CellMut<'_, LifeGameState> {
+        let state = *self.get(idx);
+        CellMut::Packed { cells: self, idx, state }
+    }
+}
+
+// Relative positions of the neighbors of a cell in an even row and an odd row.
+// Neighborhoods depend only on the parity of the row (hexagonal grids shift
+// odd rows). None if the neighborhood does not fit in the bit-parallel update.
+fn offsets<N: Neighbors>(neighborhood: &N) -> Option<[Vec<(isize, isize)>; 2]> {
+    if 1 < neighborhood.range() || 16 <= neighborhood.num_neighbors() {
+        return None; // 16 or more does not fit in the 4-bit counters
+    }
+    Some([neighborhood.offsets(0).to_vec(), neighborhood.offsets(1).to_vec()])
+}
+
+/// Whether `update_life` takes the bit-parallel path for the boundary and
+/// the neighborhood, instead of falling back to `world::update_parallel`.
+pub fn is_bit_parallel<N: Neighbors>(boundary: Boundary, neighborhood: &N) -> bool {
+    matches!(boundary, Boundary::Periodic | Boundary::Fixed | Boundary::Infinite)
+        && offsets(neighborhood).is_some()
+}
+
+/// Write the next states of a two-state outer-totalistic rule into the buffer.
+/// `survive` and `birth` are bit masks of the numbers of live neighbors.
+///
+/// Chunks are updated in parallel as in `world::update_parallel`, which is
+/// used instead if `is_bit_parallel` is false.
+pub fn update_life<R, B>(
+    rule: &R,
+    board: &mut B,
+    generation: u64,
+    survive: u16,
+    birth: u16,
+) -> anyhow::Result<()>
+where
+    R: Rule<CellState = LifeGameState> + Sync,
+    R::Neighborhood: Neighbors,
+    B: Board<LifeGameState>,
+{
+    let boundary = board.boundary();
+    let neighborhood = rule.neighborhood();
+    let offsets = match offsets(&neighborhood) {
+        Some(offsets) if is_bit_parallel(boundary, &neighborhood) => offsets,
+        _ => return world::update_parallel(rule, board, generation),
+    };
+
+    // chunks out of the board under the fixed and infinite boundaries
+    let fixed = BitChunk::filled(rule.boundary_state()?);
+    let (nx, ny) = (board.n_chunks_x(), board.n_chunks_y());
+    let chunk_at = |board: &B, x: usize, y: usize, dx: isize, dy: isize| -> BitChunk {
+        match boundary.resolve(x as isize + dx, y as isize + dy, nx, ny) {
+            Some((x, y)) => *board.chunk_at(x, y).cells(),
+            None => fixed,
+        }
+    };
+
+    board.fill_buffer_chunks(|board, cx, cy, chunk| {
+        let around: [[BitChunk; 3]; 3] =
+            [-1, 0, 1].map(|dy| [-1, 0, 1].map(|dx| chunk_at(board, cx, cy, dx, dy)));
+        // the rows from -1 to CHUNK_LEN, each extended by the cells at the
+        // both sides. bit `i+1` is the cell at `x = i`.
+        let mut ext = [0_u32; CHUNK_LEN + 2];
+        for (k, e) in ext.iter_mut().enumerate() {
+            let (dy, j) = match k {
+                0 => (-1, CHUNK_LEN - 1),
+                k if k == CHUNK_LEN + 1 => (1, 0),
+                k => (0, k - 1),
+            };
+            let [left, center, right] = around[(dy + 1) as usize].map(|c| c.rows[j] as u32);
+            *e = (left >> (CHUNK_LEN - 1)) | (center << 1) | ((right & 1) << (CHUNK_LEN + 1));
+        }
+
+        let mut next = BitChunk::default();
+        for (j, row) in next.rows.iter_mut().enumerate() {
+            let offsets = &offsets[j % 2];
+
+            let mut planes = [0_u16; 4]; // bit-sliced counters
+            for (dx, dy) in offsets.iter() {
+                let mut carry = (ext[(j as isize + 1 + dy) as usize] >> (1 + dx)) as u16;
+                for plane in planes.iter_mut() {
+                    let c = *plane & carry;
+                    *plane ^= carry;
+                    carry = c;
+                }
+            }
+
+            let alive = around[1][1].rows[j];
+            for n in 0..=offsets.len() {
+                let becomes_alive = match (survive & (1 << n) != 0, birth & (1 << n) != 0) {
+                    (true, true) => !0,
+                    (true, false) => alive,
+                    (false, true) => !alive,
+                    (false, false) => continue,
+                };
+                let mut has_n = !0;
+                for (b, plane) in planes.iter().enumerate() {
+                    has_n &= if n & (1 << b) != 0 { *plane } else { !*plane };
+                }
+                *row |= has_n & becomes_alive;
+            }
+        }
+        *chunk.cells_mut() = next;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifegame::{HighLifeRule, LifeGameRule, LifeLikeGameRule};
+    use crate::rule::{
+        ConfigurableNeighborhood, HexGridNeighborhood, MooreNeighborhood, VonNeumannNeighborhood,
+    };
+    use crate::test_util::random_board;
+
+    fn assert_same_as_update_parallel<R>(rule: R, boundaries: &[Boundary])
+    where
+        R: Rule<CellState = LifeGameState> + Sync,
+        R::Neighborhood: Neighbors,
+    {
+        for &boundary in boundaries {
+            let mut bits = random_board(3, 2, boundary);
+            let mut cells = random_board(3, 2, boundary);
+            for generation in 0..24 {
+                // as `World2D::update` does
+                bits.begin_update(rule.skip_stable_chunks());
+                rule.update_board(&mut bits, generation).unwrap();
+                bits.swap_buffer();
+
+                cells.begin_update(false);
+                world::update_parallel(&rule, &mut cells, generation).unwrap();
+                cells.swap_buffer();
+
+                assert_eq!(bits.chunk_origin(), cells.chunk_origin());
+                assert_eq!((bits.width(), bits.height()), (cells.width(), cells.height()));
+                for y in 0..cells.height() {
+                    for x in 0..cells.width() {
+                        assert_eq!(
+                            bits.cell_at(x, y),
+                            cells.cell_at(x, y),
+                            "({}, {}) in {} under {:?}",
+                            x,
+                            y,
+                            generation,
+                            boundary
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    const FAST: [Boundary; 3] = [Boundary::Periodic, Boundary::Fixed, Boundary::Infinite];
+    const FLIPPING: [Boundary; 3] =
+        [Boundary::Reflective, Boundary::KleinBottle, Boundary::CrossSurface];
+
+    #[test]
+    fn life_same_as_update_parallel() {
+        assert_same_as_update_parallel(LifeGameRule::<MooreNeighborhood>::default(), &FAST);
+        assert_same_as_update_parallel(LifeGameRule::<VonNeumannNeighborhood>::default(), &FAST);
+        // odd rows have different offsets
+        assert_same_as_update_parallel(LifeGameRule::<HexGridNeighborhood>::default(), &FAST);
+    }
+
+    #[test]
+    fn highlife_same_as_update_parallel() {
+        assert_same_as_update_parallel(HighLifeRule::<MooreNeighborhood>::default(), &FAST);
+    }
+
+    #[test]
+    fn flipping_boundaries_fall_back() {
+        let moore = MooreNeighborhood::default();
+        for boundary in FLIPPING {
+            assert!(!is_bit_parallel(boundary, &moore), "{:?}", boundary);
+        }
+        assert_same_as_update_parallel(LifeGameRule::<MooreNeighborhood>::default(), &FLIPPING);
+    }
+
+    #[test]
+    fn wide_neighborhood_falls_back() {
+        // 24 neighbors at range 2
+        let rule = LifeLikeGameRule::new(vec![2, 3], vec![3])
+            .with_neighborhood(ConfigurableNeighborhood::moore(2));
+        for boundary in FAST {
+            assert!(!is_bit_parallel(boundary, &rule.neighborhood()), "{:?}", boundary);
+        }
+        assert_same_as_update_parallel(rule, &FAST);
+    }
+
+    #[test]
+    fn cells_are_read_and_written_through_bits() {
+        let board = random_board(3, 2, Boundary::Periodic);
+        let chunk = board.chunk_at(1, 1);
+        let mut copy = BitChunk::default();
+        for idx in 0..CHUNK_LEN * CHUNK_LEN {
+            copy.set(idx, *chunk.cells().get(idx));
+        }
+        assert_eq!(copy, *chunk.cells());
+
+        *copy.get_mut(17) = LifeGameState::Alive;
+        assert_eq!(copy.rows[1] & 2, 2);
+        *copy.get_mut(17) = LifeGameState::Dead;
+        assert_eq!(copy.rows[1] & 2, 0);
+    }
+}
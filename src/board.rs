@@ -44,49 +44,246 @@
 use crate::rule::{Rule, State};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
 use thiserror::Error;
 
+#[cfg(feature = "gui")]
+use crate::rule::RuleUi;
+
 pub(crate) const CHUNK_LEN: usize = 16;
 pub(crate) const CHUNK_SIZE: usize = CHUNK_LEN * CHUNK_LEN;
 
+/// Boundary condition of a board. It decides which cell is read when a
+/// neighborhood reaches out of the board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Boundary {
+    /// The board wraps around like a torus.
+    Periodic,
+    /// Cells out of the board are in the constant state `Rule::boundary_state`.
+    Fixed,
+    /// Cells out of the board mirror the cells at the edge.
+    Reflective,
+    /// The board wraps around, but a cell crossing the top or bottom edge
+    /// is flipped horizontally.
+    KleinBottle,
+    /// The board wraps around, and a cell crossing an edge is flipped along
+    /// the edge (the real projective plane).
+    CrossSurface,
+    /// The board is a window onto an infinite plane and grows as the cells
+    /// approach its edge. Only the chunks that are not in the default state
+    /// are allocated. The default state should be stable under the rule,
+    /// otherwise (e.g. Life-like rules with B0) the board grows every step.
+    Infinite,
+}
+
+impl std::default::Default for Boundary {
+    fn default() -> Self {
+        Boundary::Periodic
+    }
+}
+
+impl Boundary {
+    pub fn all() -> [Self; 6] {
+        [
+            Self::Periodic,
+            Self::Fixed,
+            Self::Reflective,
+            Self::KleinBottle,
+            Self::CrossSurface,
+            Self::Infinite,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Periodic => "periodic",
+            Self::Fixed => "fixed",
+            Self::Reflective => "reflective",
+            Self::KleinBottle => "klein-bottle",
+            Self::CrossSurface => "cross-surface",
+            Self::Infinite => "infinite",
+        }
+    }
+
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Self::all().into_iter().find(|b| b.name() == name).ok_or_else(|| {
+            let names: Vec<_> = Self::all().iter().map(|b| b.name()).collect();
+            anyhow::anyhow!("unknown boundary \"{}\", expected one of {}", name, names.join(", "))
+        })
+    }
+
+    /// Map `(x, y)` onto a `w` x `h` board. None if it is out of the board
+    /// under the fixed boundary, or out of the window of an infinite board.
+    /// Such cells are read as `Rule::boundary_state`.
+    /// Chunk indices map the same way, since a board consists of whole chunks.
+    pub fn resolve(&self, x: isize, y: isize, w: usize, h: usize) -> Option<(usize, usize)> {
+        let (w, h) = (w as isize, h as isize);
+        if 0 <= x && x < w && 0 <= y && y < h {
+            return Some((x as usize, y as usize));
+        }
+        let (x, y) = match self {
+            Self::Periodic => (x.rem_euclid(w), y.rem_euclid(h)),
+            Self::Fixed | Self::Infinite => return None,
+            Self::Reflective => {
+                let fold = |x: isize, w: isize| {
+                    let x = x.rem_euclid(2 * w);
+                    if x < w {
+                        x
+                    } else {
+                        2 * w - 1 - x
+                    }
+                };
+                (fold(x, w), fold(y, h))
+            }
+            Self::KleinBottle => {
+                let flip_x = y.div_euclid(h) % 2 != 0;
+                let (x, y) = (x.rem_euclid(w), y.rem_euclid(h));
+                (if flip_x { w - 1 - x } else { x }, y)
+            }
+            Self::CrossSurface => {
+                let flip_x = y.div_euclid(h) % 2 != 0;
+                let flip_y = x.div_euclid(w) % 2 != 0;
+                let (x, y) = (x.rem_euclid(w), y.rem_euclid(h));
+                (if flip_x { w - 1 - x } else { x }, if flip_y { h - 1 - y } else { y })
+            }
+        };
+        Some((x as usize, y as usize))
+    }
+}
+
+/// How the cells of a chunk are stored, in the row-major order. Most states
+/// are stored as they are in `PlainCells`, while `LifeGameState` is packed
+/// into bits by `bitchunk::BitChunk`.
+pub trait ChunkCells<T: State>: Clone + PartialEq + Send + Sync {
+    fn filled(state: T) -> Self;
+    fn get(&self, idx: usize) -> &T;
+    fn set(&mut self, idx: usize, state: T);
+    fn get_mut(&mut self, idx: usize) -> CellMut<'_, T>;
+}
+
+/// The cells of a chunk stored as they are.
+#[derive(Clone, PartialEq)]
+pub struct PlainCells<T>([T; CHUNK_SIZE]);
+
+impl<T: State + Send + Sync> ChunkCells<T> for PlainCells<T> {
+    fn filled(state: T) -> Self {
+        Self(array_init::array_init(|_| state.clone()))
+    }
+    fn get(&self, idx: usize) -> &T {
+        &self.0[idx]
+    }
+    fn set(&mut self, idx: usize, state: T) {
+        self.0[idx] = state;
+    }
+    fn get_mut(&mut self, idx: usize) -> CellMut<'_, T> {
+        CellMut::Plain(&mut self.0[idx])
+    }
+}
+
+/// A cell borrowed mutably from a chunk. A packed cell is a copy that is
+/// written back when this is dropped.
+pub enum CellMut<'a, T: State> {
+    Plain(&'a mut T),
+    Packed { cells: &'a mut T::Cells, idx: usize, state: T },
+}
+
+impl<'a, T: State> std::ops::Deref for CellMut<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        match self {
+            CellMut::Plain(cell) => cell,
+            CellMut::Packed { state, .. } => state,
+        }
+    }
+}
+impl<'a, T: State> std::ops::DerefMut for CellMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        match self {
+            CellMut::Plain(cell) => cell,
+            CellMut::Packed { state, .. } => state,
+        }
+    }
+}
+impl<'a, T: State> Drop for CellMut<'a, T> {
+    fn drop(&mut self) {
+        if let CellMut::Packed { cells, idx, state } = self {
+            cells.set(*idx, std::mem::take(state));
+        }
+    }
+}
+
 /// A square-shaped Chunk of cells.
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, PartialEq)]
 pub struct Chunk<T: State> {
-    #[serde(with = "serde_arrays")]
-    #[serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>"))]
-    cells: [T; CHUNK_SIZE],
+    cells: T::Cells,
+}
+
+// serialized as the list of cells whatever the storage is
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Chunk")]
+struct ChunkRepr<C> {
+    cells: Vec<C>,
+}
+
+impl<T: State + Serialize> Serialize for Chunk<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let cells: Vec<&T> = (0..CHUNK_SIZE).map(|idx| self.cells.get(idx)).collect();
+        ChunkRepr { cells }.serialize(serializer)
+    }
+}
+impl<'de, T: State + Deserialize<'de>> Deserialize<'de> for Chunk<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = ChunkRepr::<T>::deserialize(deserializer)?;
+        if repr.cells.len() != CHUNK_SIZE {
+            return Err(serde::de::Error::invalid_length(
+                repr.cells.len(),
+                &"256 cells of a chunk",
+            ));
+        }
+        let mut chunk = Self::default();
+        for (idx, cell) in repr.cells.into_iter().enumerate() {
+            chunk.cells.set(idx, cell);
+        }
+        Ok(chunk)
+    }
 }
 
 impl<T: State> std::default::Default for Chunk<T> {
     fn default() -> Self {
-        Self { cells: array_init::array_init(|_| Default::default()) }
+        Self::init(T::default())
     }
 }
 
 impl<T: State> Chunk<T> {
     /// We need to take initial value because the default value depends on rule.
-    fn init(i: T) -> Self {
-        Self { cells: array_init::array_init(|_| i.clone()) }
+    pub(crate) fn init(i: T) -> Self {
+        Self { cells: T::Cells::filled(i) }
     }
 
     /// access to a cell with (x, y) coordinate.
-    fn cell_at(&self, x: usize, y: usize) -> &T {
+    pub(crate) fn cell_at(&self, x: usize, y: usize) -> &T {
         assert!(x < CHUNK_LEN && y < CHUNK_LEN, "x = {}, y = {}", x, y);
-        &self.cells[y * CHUNK_LEN + x]
+        self.cells.get(y * CHUNK_LEN + x)
     }
     /// mut access to a cell with (x, y) coordinate.
-    fn cell_at_mut(&mut self, x: usize, y: usize) -> &mut T {
+    pub(crate) fn cell_at_mut(&mut self, x: usize, y: usize) -> CellMut<'_, T> {
         assert!(x < CHUNK_LEN && y < CHUNK_LEN, "x = {}, y = {}", x, y);
-        &mut self.cells[y * CHUNK_LEN + x]
+        self.cells.get_mut(y * CHUNK_LEN + x)
+    }
+    /// The storage of the cells, e.g. to update packed cells at once.
+    pub(crate) fn cells(&self) -> &T::Cells {
+        &self.cells
+    }
+    pub(crate) fn cells_mut(&mut self) -> &mut T::Cells {
+        &mut self.cells
     }
     /// set the state of all the cells in this chunk as default.
     fn clear<R>(&mut self, rule: &R) -> anyhow::Result<()>
     where
         R: Rule<CellState = T>,
     {
-        for c in self.cells.iter_mut() {
-            *c = rule.default_state()?;
-        }
+        self.cells = T::Cells::filled(rule.default_state()?);
         Ok(())
     }
     /// randomize the state of all the cells in this chunk.
@@ -95,14 +292,68 @@ impl<T: State> Chunk<T> {
         R: Rule<CellState = T>,
         Rn: Rng,
     {
-        for c in self.cells.iter_mut() {
-            *c = rule.randomize(rng)?;
+        for idx in 0..CHUNK_SIZE {
+            self.cells.set(idx, rule.randomize(rng)?);
         }
         Ok(())
     }
 }
 
+type ChunkMap<T> = HashMap<(isize, isize), Chunk<T>>;
+
+/// Chunks of an infinite grid. Only the chunks that differ from `background`
+/// are allocated. They are keyed by the chunk coordinate on the plane, so the
+/// grid can grow in any direction without moving them.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SparseChunks<T: State> {
+    #[serde(with = "chunk_map")]
+    #[serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>"))]
+    chunks: ChunkMap<T>,
+    #[serde(skip)]
+    buffer: ChunkMap<T>,
+    // the chunks changed by the last step, in the states before the step
+    #[serde(skip)]
+    prev: ChunkMap<T>,
+    #[serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>"))]
+    background: Chunk<T>,
+}
+
+// JSON keys must be strings, so the map is serialized as a list of pairs.
+mod chunk_map {
+    use super::{Chunk, ChunkMap};
+    use crate::rule::State;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    pub(super) fn serialize<T, S>(chunks: &ChunkMap<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: State + Serialize,
+        S: Serializer,
+    {
+        // sorted to make the output deterministic
+        serializer.collect_seq(chunks.iter().collect::<BTreeMap<_, _>>())
+    }
+    pub(super) fn deserialize<'de, T, D>(deserializer: D) -> Result<ChunkMap<T>, D::Error>
+    where
+        T: State + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let chunks: Vec<((isize, isize), Chunk<T>)> = Deserialize::deserialize(deserializer)?;
+        Ok(chunks.into_iter().collect())
+    }
+}
+
+/// The buffer of a grid, taken out while it is filled by `Grid::fill_chunks`.
+pub(crate) enum Buffer<T: State> {
+    Dense(Vec<Chunk<T>>),
+    Sparse(Vec<((isize, isize), Chunk<T>)>),
+}
+
 /// A square lattice of chunks.
+///
+/// Under `Boundary::Infinite`, the chunks are stored in a hash map and the
+/// grid is a window onto an infinite plane. The window grows so that there
+/// is always a margin of empty chunks around the allocated ones.
 #[derive(Default, Serialize, Deserialize)]
 pub struct Grid<T: State> {
     pub(crate) num_chunks_x: usize,
@@ -111,6 +362,24 @@ pub struct Grid<T: State> {
     pub(crate) chunks: Vec<Chunk<T>>,
     #[serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>"))]
     pub(crate) buffer: Vec<Chunk<T>>,
+    #[serde(default)]
+    pub(crate) boundary: Boundary,
+    // the coordinate on the plane of the chunk at (0, 0). it decreases when
+    // the grid is expanded in the negative direction.
+    #[serde(default)]
+    origin: (isize, isize),
+    // Some under the infinite boundary. then `chunks` and `buffer` are empty.
+    #[serde(default)]
+    #[serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>"))]
+    sparse: Option<SparseChunks<T>>,
+
+    // chunks changed since the last step, by editing or by the step itself.
+    // empty means all the chunks are changed.
+    #[serde(skip)]
+    dirty: Vec<bool>,
+    // chunks to be updated in the current step. None means all the chunks.
+    #[serde(skip)]
+    active: Option<Vec<bool>>,
 }
 
 impl<T: State> Grid<T> {
@@ -120,6 +389,11 @@ impl<T: State> Grid<T> {
             num_chunks_y: y_chunks,
             chunks: vec![Chunk::init(i.clone()); x_chunks * y_chunks],
             buffer: vec![Chunk::init(i); x_chunks * y_chunks],
+            boundary: Boundary::default(),
+            origin: (0, 0),
+            sparse: None,
+            dirty: Vec::new(),
+            active: None,
         }
     }
 
@@ -144,6 +418,14 @@ impl<T: State> Grid<T> {
     pub(crate) fn chunk_len(&self) -> usize {
         CHUNK_LEN
     }
+    /// The coordinate of the chunk at (0, 0) on the plane. It decreases when
+    /// chunks are prepended, by `expand_x`/`expand_y` or by an infinite grid growing.
+    pub fn chunk_origin(&self) -> (isize, isize) {
+        self.origin
+    }
+    fn key(&self, x: usize, y: usize) -> (isize, isize) {
+        (x as isize + self.origin.0, y as isize + self.origin.1)
+    }
 
     pub(crate) fn chunk_at(&self, x: usize, y: usize) -> &Chunk<T> {
         assert!(
@@ -155,7 +437,244 @@ impl<T: State> Grid<T> {
             self.num_chunks_y
         );
 
-        &self.chunks[y * self.num_chunks_x + x]
+        match &self.sparse {
+            Some(sparse) => sparse.chunks.get(&self.key(x, y)).unwrap_or(&sparse.background),
+            None => &self.chunks[y * self.num_chunks_x + x],
+        }
+    }
+
+    /// Take the buffer out to fill it by `fill_chunks`. Under the infinite
+    /// boundary, only the chunks to be updated are allocated.
+    pub(crate) fn take_buffer(&mut self) -> Buffer<T> {
+        match &mut self.sparse {
+            None => Buffer::Dense(std::mem::take(&mut self.buffer)),
+            Some(sparse) => {
+                sparse.buffer.clear();
+                let mut buffer = Vec::new();
+                for y in 0..self.num_chunks_y {
+                    for x in 0..self.num_chunks_x {
+                        if self.active.as_ref().map_or(true, |a| a[y * self.num_chunks_x + x]) {
+                            let key = (x as isize + self.origin.0, y as isize + self.origin.1);
+                            buffer.push((key, sparse.background.clone()));
+                        }
+                    }
+                }
+                Buffer::Sparse(buffer)
+            }
+        }
+    }
+    pub(crate) fn put_buffer(&mut self, buffer: Buffer<T>) {
+        match (buffer, &mut self.sparse) {
+            (Buffer::Dense(buffer), None) => self.buffer = buffer,
+            (Buffer::Sparse(buffer), Some(sparse)) => sparse.buffer.extend(buffer),
+            _ => unreachable!("the grid is changed while the buffer is taken"),
+        }
+    }
+
+    /// Call `f(x, y, chunk)` for every active chunk in the buffer taken by
+    /// `take_buffer`. The buffer is passed separately so that `f` can read
+    /// the current states while writing it.
+    pub(crate) fn fill_chunks<F>(&self, buffer: &mut Buffer<T>, f: F) -> anyhow::Result<()>
+    where
+        F: Fn(usize, usize, &mut Chunk<T>) -> anyhow::Result<()> + Sync,
+        T: Send,
+    {
+        let num_chunks_x = self.num_chunks_x;
+        let active = self.active.as_deref();
+        let origin = self.origin;
+        match buffer {
+            Buffer::Dense(buffer) => try_for_each_mut(buffer, |idx, chunk| {
+                if active.map_or(false, |active| !active[idx]) {
+                    return Ok(());
+                }
+                f(idx % num_chunks_x, idx / num_chunks_x, chunk)
+            }),
+            Buffer::Sparse(buffer) => try_for_each_mut(buffer, |_, ((x, y), chunk)| {
+                f((*x - origin.0) as usize, (*y - origin.1) as usize, chunk)
+            }),
+        }
+    }
+
+    pub(crate) fn chunk_at_mut(&mut self, x: usize, y: usize) -> &mut Chunk<T> {
+        assert!(
+            x < self.num_chunks_x && y < self.num_chunks_y,
+            "x = {}, width = {}, y = {}, height = {}",
+            x,
+            self.num_chunks_x,
+            y,
+            self.num_chunks_y
+        );
+
+        let idx = y * self.num_chunks_x + x;
+        self.mark_changed(idx);
+        let key = self.key(x, y);
+        match &mut self.sparse {
+            Some(sparse) => sparse.chunks.entry(key).or_insert_with(|| sparse.background.clone()),
+            None => &mut self.chunks[idx],
+        }
+    }
+
+    fn mark_changed(&mut self, idx: usize) {
+        if let Some(dirty) = self.dirty.get_mut(idx) {
+            *dirty = true;
+        }
+    }
+    pub(crate) fn mark_all_changed(&mut self) {
+        self.dirty.clear();
+    }
+    /// Whether the chunk may have changed since the last step, by editing or
+    /// by the step itself. None if unknown, e.g. after the board is resized or
+    /// a step that updated all the chunks without comparing them.
+    pub(crate) fn is_changed_chunk(&self, x: usize, y: usize) -> Option<bool> {
+        if self.dirty.len() != self.num_chunks_x * self.num_chunks_y {
+            return None;
+        }
+        Some(self.dirty[y * self.num_chunks_x + x])
+    }
+    /// The chunk in the state before the last step. It is valid only until
+    /// the board is changed after the step.
+    pub(crate) fn prev_chunk_at(&self, x: usize, y: usize) -> &Chunk<T> {
+        match &self.sparse {
+            Some(sparse) => sparse.prev.get(&self.key(x, y)).unwrap_or_else(|| self.chunk_at(x, y)),
+            // the swapped chunks left the previous states in the buffer
+            None if self.is_changed_chunk(x, y) == Some(false) => self.chunk_at(x, y),
+            None => &self.buffer[y * self.num_chunks_x + x],
+        }
+    }
+
+    pub fn boundary(&self) -> Boundary {
+        self.boundary
+    }
+    /// Change the boundary condition. Switching to or from the infinite
+    /// boundary converts the storage, and `background` is the state of the
+    /// cells not allocated.
+    pub fn set_boundary(&mut self, boundary: Boundary, background: T) {
+        if self.boundary == boundary {
+            return;
+        }
+        let n = self.num_chunks_x * self.num_chunks_y;
+        match (self.sparse.take(), boundary) {
+            (None, Boundary::Infinite) => {
+                let background = Chunk::init(background);
+                let mut chunks = HashMap::new();
+                for (idx, chunk) in std::mem::take(&mut self.chunks).into_iter().enumerate() {
+                    if chunk != background {
+                        chunks.insert(
+                            self.key(idx % self.num_chunks_x, idx / self.num_chunks_x),
+                            chunk,
+                        );
+                    }
+                }
+                self.buffer.clear();
+                self.sparse = Some(SparseChunks {
+                    chunks,
+                    buffer: HashMap::new(),
+                    prev: HashMap::new(),
+                    background,
+                });
+            }
+            (Some(mut sparse), b) if b != Boundary::Infinite => {
+                self.chunks = (0..n)
+                    .map(|idx| {
+                        let key = self.key(idx % self.num_chunks_x, idx / self.num_chunks_x);
+                        sparse.chunks.remove(&key).unwrap_or_else(|| sparse.background.clone())
+                    })
+                    .collect();
+                self.buffer = self.chunks.clone();
+            }
+            (sparse, _) => self.sparse = sparse,
+        }
+        self.boundary = boundary;
+        self.mark_all_changed();
+    }
+
+    // Keep a margin of a chunk around the chunks of an infinite grid that
+    // differ from the background, so that nothing reaches the edge in a step.
+    fn grow(&mut self) {
+        let sparse = match &mut self.sparse {
+            Some(sparse) => sparse,
+            None => return,
+        };
+        let background = &sparse.background;
+        sparse.chunks.retain(|_, chunk| chunk != background);
+
+        let (mut x0, mut y0) = self.origin;
+        let mut x1 = x0 + self.num_chunks_x as isize;
+        let mut y1 = y0 + self.num_chunks_y as isize;
+        for &(x, y) in sparse.chunks.keys() {
+            x0 = x0.min(x - 1);
+            y0 = y0.min(y - 1);
+            x1 = x1.max(x + 2);
+            y1 = y1.max(y + 2);
+        }
+        if (x0, y0) == self.origin
+            && x1 - x0 == self.num_chunks_x as isize
+            && y1 - y0 == self.num_chunks_y as isize
+        {
+            return;
+        }
+
+        // the new chunks are not changed
+        let (nx, ny) = ((x1 - x0) as usize, (y1 - y0) as usize);
+        if !self.dirty.is_empty() {
+            let mut dirty = vec![false; nx * ny];
+            for (idx, d) in self.dirty.iter().enumerate() {
+                let (x, y) = self.key(idx % self.num_chunks_x, idx / self.num_chunks_x);
+                dirty[(y - y0) as usize * nx + (x - x0) as usize] = *d;
+            }
+            self.dirty = dirty;
+        }
+        self.origin = (x0, y0);
+        self.num_chunks_x = nx;
+        self.num_chunks_y = ny;
+    }
+
+    /// Decide which chunks to update in the next step. If `skip_stable` is
+    /// true, only the chunks changed since the last step and their neighbors
+    /// are updated. Since the range of a neighborhood is at most `CHUNK_LEN`
+    /// (see `rule::MAX_RANGE`), a change reaches at most the 8 neighboring
+    /// chunks in a step.
+    pub(crate) fn begin_update(&mut self, skip_stable: bool) {
+        self.grow();
+        let nx = self.num_chunks_x;
+        let ny = self.num_chunks_y;
+        if !skip_stable {
+            self.active = None;
+            return;
+        }
+        if self.dirty.len() != nx * ny {
+            // update all, but compare them to find the stable chunks
+            self.active = Some(vec![true; nx * ny]);
+            return;
+        }
+        let mut active = vec![false; nx * ny];
+        for y in 0..ny {
+            for x in 0..nx {
+                if !self.dirty[y * nx + x] {
+                    continue;
+                }
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        let (x, y) = (x as isize + dx, y as isize + dy);
+                        if let Some((x, y)) = self.boundary.resolve(x, y, nx, ny) {
+                            active[y * nx + x] = true;
+                        }
+                    }
+                }
+            }
+        }
+        self.active = Some(active);
+    }
+    /// Update only the given chunks in the next step, instead of the ones
+    /// found by `begin_update`. None means all the chunks.
+    pub(crate) fn set_active_chunks(&mut self, active: Option<Vec<bool>>) {
+        self.active = active;
+    }
+    pub(crate) fn is_active_chunk(&self, x: usize, y: usize) -> bool {
+        match &self.active {
+            Some(active) => active[y * self.num_chunks_x + x],
+            None => true,
+        }
     }
 
     pub fn has_cell(&self, x: usize, y: usize) -> bool {
@@ -176,9 +695,9 @@ impl<T: State> Grid<T> {
         let clx = x % CHUNK_LEN;
         let chy = y / CHUNK_LEN;
         let cly = y % CHUNK_LEN;
-        self.chunks[chy * self.num_chunks_x + chx].cell_at(clx, cly)
+        self.chunk_at(chx, chy).cell_at(clx, cly)
     }
-    pub fn cell_at_mut(&mut self, x: usize, y: usize) -> &mut T {
+    pub fn cell_at_mut(&mut self, x: usize, y: usize) -> CellMut<'_, T> {
         assert!(
             x < self.num_chunks_x * CHUNK_LEN && y < self.num_chunks_y * CHUNK_LEN,
             "x = {}, num_chunks_x = {}, y = {}, num_chunks_y = {}",
@@ -192,10 +711,10 @@ impl<T: State> Grid<T> {
         let clx = x % CHUNK_LEN;
         let chy = y / CHUNK_LEN;
         let cly = y % CHUNK_LEN;
-        self.chunks[chy * self.num_chunks_x + chx].cell_at_mut(clx, cly)
+        self.chunk_at_mut(chx, chy).cell_at_mut(clx, cly)
     }
 
-    pub(crate) fn bufcell_at_mut(&mut self, x: usize, y: usize) -> &mut T {
+    pub(crate) fn bufcell_at_mut(&mut self, x: usize, y: usize) -> CellMut<'_, T> {
         assert!(
             x < self.width() && y < self.height(),
             "x = {}, width = {}, y = {}, height = {}",
@@ -209,10 +728,49 @@ impl<T: State> Grid<T> {
         let clx = x % CHUNK_LEN;
         let chy = y / CHUNK_LEN;
         let cly = y % CHUNK_LEN;
-        self.buffer[chy * self.num_chunks_x + chx].cell_at_mut(clx, cly)
-    }
+        let key = self.key(chx, chy);
+        let chunk = match &mut self.sparse {
+            Some(sparse) => sparse.buffer.entry(key).or_insert_with(|| sparse.background.clone()),
+            None => &mut self.buffer[chy * self.num_chunks_x + chx],
+        };
+        chunk.cell_at_mut(clx, cly)
+    }
+    /// Make the buffer the current states. If the update skipped stable
+    /// chunks, only the updated chunks are swapped and compared to find the
+    /// chunks to be updated in the next step.
     pub fn swap_buffer(&mut self) {
-        std::mem::swap(&mut self.chunks, &mut self.buffer);
+        if let Some(sparse) = &mut self.sparse {
+            // chunks back to the background are deallocated in `grow`
+            let mut dirty = vec![false; self.num_chunks_x * self.num_chunks_y];
+            sparse.prev.clear();
+            for (key, chunk) in sparse.buffer.drain() {
+                let idx = (key.1 - self.origin.1) as usize * self.num_chunks_x
+                    + (key.0 - self.origin.0) as usize;
+                let prev = sparse.chunks.insert(key, chunk);
+                let prev = prev.unwrap_or_else(|| sparse.background.clone());
+                dirty[idx] = prev != sparse.chunks[&key];
+                if dirty[idx] {
+                    sparse.prev.insert(key, prev);
+                }
+            }
+            self.active = None;
+            self.dirty = dirty;
+            return;
+        }
+        match self.active.take() {
+            Some(active) => {
+                let mut dirty = vec![false; active.len()];
+                for (idx, _) in active.iter().enumerate().filter(|(_, a)| **a) {
+                    dirty[idx] = self.chunks[idx] != self.buffer[idx];
+                    std::mem::swap(&mut self.chunks[idx], &mut self.buffer[idx]);
+                }
+                self.dirty = dirty;
+            }
+            None => {
+                std::mem::swap(&mut self.chunks, &mut self.buffer);
+                self.dirty.clear();
+            }
+        }
     }
 
     pub fn expand_x(&mut self, n: isize, init: T) {
@@ -221,8 +779,17 @@ impl<T: State> Grid<T> {
         }
 
         let na = n.unsigned_abs();
+        if n < 0 {
+            self.origin.0 -= na as isize;
+        }
+        if self.sparse.is_some() {
+            // chunks not allocated are the background
+            self.num_chunks_x += na;
+            self.mark_all_changed();
+            return;
+        }
         let mut new_chunks = Vec::new();
-        new_chunks.resize((self.num_chunks_x + na) * self.num_chunks_y, Chunk::init(init.clone()));
+        new_chunks.resize((self.num_chunks_x + na) * self.num_chunks_y, Chunk::init(init));
 
         let x_ofs = if 0 <= n { 0 } else { na };
         for j in 0..self.num_chunks_y {
@@ -232,9 +799,9 @@ impl<T: State> Grid<T> {
             }
         }
         self.chunks = new_chunks;
-        self.buffer
-            .resize((self.num_chunks_x + na) * self.num_chunks_y, Chunk::init(init));
+        self.buffer = self.chunks.clone();
         self.num_chunks_x += na;
+        self.mark_all_changed();
     }
     pub fn expand_y(&mut self, n: isize, init: T) {
         if n == 0 {
@@ -242,8 +809,17 @@ impl<T: State> Grid<T> {
         }
 
         let na = n.unsigned_abs();
+        if n < 0 {
+            self.origin.1 -= na as isize;
+        }
+        if self.sparse.is_some() {
+            // chunks not allocated are the background
+            self.num_chunks_y += na;
+            self.mark_all_changed();
+            return;
+        }
         let mut new_chunks = Vec::new();
-        new_chunks.resize(self.num_chunks_x * (self.num_chunks_y + na), Chunk::init(init.clone()));
+        new_chunks.resize(self.num_chunks_x * (self.num_chunks_y + na), Chunk::init(init));
 
         let y_ofs = if 0 <= n { 0 } else { na };
         for j in 0..self.num_chunks_y {
@@ -253,15 +829,79 @@ impl<T: State> Grid<T> {
             }
         }
         self.chunks = new_chunks;
-        self.buffer
-            .resize(self.num_chunks_x * (self.num_chunks_y + na), Chunk::init(init));
+        self.buffer = self.chunks.clone();
         self.num_chunks_y += na;
+        self.mark_all_changed();
+    }
+
+    /// Remove `|n|` chunks from the end (n > 0) or the beginning (n < 0) of
+    /// the x axis. At least a chunk is left.
+    pub fn shrink_x(&mut self, n: isize) {
+        let (nx, ny) = (self.num_chunks_x, self.num_chunks_y);
+        let na = n.unsigned_abs().min(nx - 1);
+        if n < 0 {
+            self.crop(na..nx, 0..ny);
+        } else {
+            self.crop(0..nx - na, 0..ny);
+        }
+    }
+    pub fn shrink_y(&mut self, n: isize) {
+        let (nx, ny) = (self.num_chunks_x, self.num_chunks_y);
+        let na = n.unsigned_abs().min(ny - 1);
+        if n < 0 {
+            self.crop(0..nx, na..ny);
+        } else {
+            self.crop(0..nx, 0..ny - na);
+        }
+    }
+    /// Keep only the chunks in the ranges of chunk indices. The ranges must not be empty.
+    pub fn crop(&mut self, x: Range<usize>, y: Range<usize>) {
+        assert!(
+            !x.is_empty()
+                && x.end <= self.num_chunks_x
+                && !y.is_empty()
+                && y.end <= self.num_chunks_y,
+            "x = {:?}, num_chunks_x = {}, y = {:?}, num_chunks_y = {}",
+            x,
+            self.num_chunks_x,
+            y,
+            self.num_chunks_y
+        );
+
+        self.origin.0 += x.start as isize;
+        self.origin.1 += y.start as isize;
+        let (nx, ny) = (x.len(), y.len());
+        match &mut self.sparse {
+            Some(sparse) => {
+                let (x0, y0) = self.origin;
+                let (x1, y1) = (x0 + nx as isize, y0 + ny as isize);
+                sparse.chunks.retain(|&(x, y), _| x0 <= x && x < x1 && y0 <= y && y < y1);
+            }
+            None => {
+                let mut chunks = Vec::with_capacity(nx * ny);
+                for j in y {
+                    for i in x.clone() {
+                        chunks.push(std::mem::take(&mut self.chunks[j * self.num_chunks_x + i]));
+                    }
+                }
+                self.chunks = chunks;
+                self.buffer = self.chunks.clone();
+            }
+        }
+        self.num_chunks_x = nx;
+        self.num_chunks_y = ny;
+        self.mark_all_changed();
     }
 
     pub fn clear<R>(&mut self, rule: &R) -> anyhow::Result<()>
     where
         R: Rule<CellState = T>,
     {
+        self.mark_all_changed();
+        if let Some(sparse) = &mut self.sparse {
+            sparse.chunks.clear();
+            sparse.background.clear(rule)?;
+        }
         for ch in self.chunks.iter_mut() {
             ch.clear(rule)?;
         }
@@ -272,13 +912,34 @@ impl<T: State> Grid<T> {
         R: Rule<CellState = T>,
         Rn: Rng,
     {
-        for ch in self.chunks.iter_mut() {
-            ch.randomize(rule, rng)?;
+        self.mark_all_changed();
+        for y in 0..self.num_chunks_y {
+            for x in 0..self.num_chunks_x {
+                self.chunk_at_mut(x, y).randomize(rule, rng)?;
+            }
         }
         Ok(())
     }
 }
 
+// `f(idx, item)` for each item, in parallel on native targets.
+pub(crate) fn try_for_each_mut<C, F>(items: &mut [C], f: F) -> anyhow::Result<()>
+where
+    C: Send,
+    F: Fn(usize, &mut C) -> anyhow::Result<()> + Sync,
+{
+    // wasm has no threads
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use rayon::prelude::*;
+        items.par_iter_mut().enumerate().try_for_each(|(idx, item)| f(idx, item))
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        items.iter_mut().enumerate().try_for_each(|(idx, item)| f(idx, item))
+    }
+}
+
 /// We have (currently) two different boards, `SquareGrid` and `HexGrid`.
 /// To use both with the same Rule, we need to make interface the same.
 ///
@@ -300,16 +961,96 @@ pub trait Board<T: State> {
     fn height_px(&self, cell_height: f32) -> f32;
 
     fn chunk_at(&self, x: usize, y: usize) -> &Chunk<T>;
+    fn chunk_at_mut(&mut self, x: usize, y: usize) -> &mut Chunk<T>;
 
     fn has_cell(&self, x: usize, y: usize) -> bool;
     fn cell_at(&self, x: usize, y: usize) -> &T;
-    fn cell_at_mut(&mut self, x: usize, y: usize) -> &mut T;
+    fn cell_at_mut(&mut self, x: usize, y: usize) -> CellMut<'_, T>;
 
-    fn bufcell_at_mut(&mut self, x: usize, y: usize) -> &mut T;
+    fn boundary(&self) -> Boundary;
+    /// Change the boundary condition. All the chunks are updated in the next step.
+    /// `background` is the state of the cells not allocated on an infinite board.
+    fn set_boundary(&mut self, boundary: Boundary, background: T);
+    /// The coordinate of the chunk at (0, 0) on the plane. It decreases when
+    /// chunks are prepended, by expansion or by an infinite board growing.
+    fn chunk_origin(&self) -> (isize, isize);
+
+    /// The cell at `(x, y)` that can be out of the board, mapped by the
+    /// boundary condition. None if it reads the fixed boundary state.
+    fn neighbor_at(&self, x: isize, y: isize) -> Option<&T> {
+        self.boundary()
+            .resolve(x, y, self.width(), self.height())
+            .map(|(x, y)| self.cell_at(x, y))
+    }
+
+    fn bufcell_at_mut(&mut self, x: usize, y: usize) -> CellMut<'_, T>;
     fn swap_buffer(&mut self);
 
+    /// Called before each step. If `skip_stable` is true, chunks that did not
+    /// change in the last step and whose neighbors did not change either are
+    /// skipped in the step.
+    fn begin_update(&mut self, skip_stable: bool);
+    /// Whether a chunk should be updated in the current step.
+    fn is_active_chunk(&self, x: usize, y: usize) -> bool;
+    /// Update all the chunks in the next step, e.g. after the rule is changed.
+    fn mark_all_changed(&mut self);
+    /// Whether a chunk may have changed since the last step, by editing or by
+    /// the step itself. None if it is unknown.
+    fn is_changed_chunk(&self, x: usize, y: usize) -> Option<bool>;
+    /// A chunk in the state before the last step. Valid right after the step.
+    fn prev_chunk_at(&self, x: usize, y: usize) -> &Chunk<T>;
+
+    /// Call `f(self, x, y, chunk)` for every active chunk in the buffer, where
+    /// `(x, y)` is the index of the chunk. On native, chunks are processed in
+    /// parallel. `f` reads the current states, not the buffer.
+    fn fill_buffer_chunks<F>(&mut self, f: F) -> anyhow::Result<()>
+    where
+        F: Fn(&Self, usize, usize, &mut Chunk<T>) -> anyhow::Result<()> + Sync,
+        T: Send + Sync;
+
+    /// Set every cell in the active chunks of the buffer to `f(self, x, y)`.
+    fn fill_buffer<F>(&mut self, f: F) -> anyhow::Result<()>
+    where
+        F: Fn(&Self, usize, usize) -> anyhow::Result<T> + Sync,
+        T: Send + Sync,
+    {
+        self.fill_buffer_chunks(|board, cx, cy, chunk| {
+            for j in 0..CHUNK_LEN {
+                for i in 0..CHUNK_LEN {
+                    *chunk.cell_at_mut(i, j) = f(board, cx * CHUNK_LEN + i, cy * CHUNK_LEN + j)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
     fn expand_x(&mut self, n: isize, init: T);
     fn expand_y(&mut self, n: isize, init: T);
+    fn shrink_x(&mut self, n: isize);
+    fn shrink_y(&mut self, n: isize);
+    /// Keep only the chunks in the ranges of chunk indices.
+    fn crop(&mut self, x: Range<usize>, y: Range<usize>);
+
+    /// The smallest ranges of chunk indices that contain all the cells not in
+    /// the `empty` state. None if all the cells are empty.
+    fn occupied_chunks(&self, empty: &T) -> Option<(Range<usize>, Range<usize>)> {
+        let empty = Chunk::init(empty.clone());
+        let mut occupied: Option<(Range<usize>, Range<usize>)> = None;
+        for y in 0..self.n_chunks_y() {
+            for x in 0..self.n_chunks_x() {
+                if *self.chunk_at(x, y) == empty {
+                    continue;
+                }
+                occupied = Some(match occupied {
+                    None => (x..x + 1, y..y + 1),
+                    Some((xs, ys)) => {
+                        (xs.start.min(x)..xs.end.max(x + 1), ys.start.min(y)..ys.end.max(y + 1))
+                    }
+                });
+            }
+        }
+        occupied
+    }
 
     fn clear<R: Rule<CellState = T>>(&mut self, rule: &R) -> anyhow::Result<()>;
     fn randomize<R, Rn>(&mut self, rule: &R, rng: &mut Rn) -> anyhow::Result<()>
@@ -319,6 +1060,7 @@ pub trait Board<T: State> {
 
     /// it takes a cell coordinate `(x, y)` and returns the center position of
     /// the corresponding cell.
+    #[cfg(feature = "gui")]
     fn location(
         &self,
         x: usize,
@@ -332,7 +1074,8 @@ pub trait Board<T: State> {
     fn clicked(&self, x: f32, y: f32, cell_width: f32) -> Option<(usize, usize)>;
 
     /// visualize the board.
-    fn paint<R: Rule<CellState = T>>(
+    #[cfg(feature = "gui")]
+    fn paint<R: RuleUi<CellState = T>>(
         &self,
         painter: &egui::Painter,
         origin: egui::Pos2,
@@ -341,8 +1084,9 @@ pub trait Board<T: State> {
         alpha: f32,
     ) -> anyhow::Result<()>;
 
+    #[cfg(feature = "gui")]
     #[allow(clippy::too_many_arguments)]
-    fn paint_clipboard<R: Rule<CellState = T>>(
+    fn paint_clipboard<R: RuleUi<CellState = T>>(
         &self,
         painter: &egui::Painter,
         origin: egui::Pos2,
@@ -355,6 +1099,15 @@ pub trait Board<T: State> {
         alpha: f32,
     ) -> anyhow::Result<()>;
 
+    /// rasterize the whole board, not only the visible region.
+    /// `cell_width` is the width of a cell in pixels.
+    #[cfg(feature = "gui")]
+    fn render<R: RuleUi<CellState = T>>(
+        &self,
+        rule: &R,
+        cell_width: u32,
+    ) -> anyhow::Result<image::RgbaImage>;
+
     fn paste_clipboard(
         &mut self,
         xofs: usize,
@@ -369,6 +1122,14 @@ pub struct SquareGrid<T: State> {
     #[serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>"))]
     grid: Grid<T>,
 }
+impl<T: State> SquareGrid<T> {
+    pub fn grid(&self) -> &Grid<T> {
+        &self.grid
+    }
+    pub fn grid_mut(&mut self) -> &mut Grid<T> {
+        &mut self.grid
+    }
+}
 impl<T: State> Board<T> for SquareGrid<T> {
     fn init(x_chunks: usize, y_chunks: usize, ini: T) -> Self {
         Self { grid: Grid::init(x_chunks, y_chunks, ini) }
@@ -411,6 +1172,9 @@ impl<T: State> Board<T> for SquareGrid<T> {
     fn chunk_at(&self, x: usize, y: usize) -> &Chunk<T> {
         self.grid.chunk_at(x, y)
     }
+    fn chunk_at_mut(&mut self, x: usize, y: usize) -> &mut Chunk<T> {
+        self.grid.chunk_at_mut(x, y)
+    }
 
     fn has_cell(&self, x: usize, y: usize) -> bool {
         self.grid.has_cell(x, y)
@@ -418,16 +1182,51 @@ impl<T: State> Board<T> for SquareGrid<T> {
     fn cell_at(&self, x: usize, y: usize) -> &T {
         self.grid.cell_at(x, y)
     }
-    fn cell_at_mut(&mut self, x: usize, y: usize) -> &mut T {
+    fn cell_at_mut(&mut self, x: usize, y: usize) -> CellMut<'_, T> {
         self.grid.cell_at_mut(x, y)
     }
 
-    fn bufcell_at_mut(&mut self, x: usize, y: usize) -> &mut T {
+    fn boundary(&self) -> Boundary {
+        self.grid.boundary()
+    }
+    fn set_boundary(&mut self, boundary: Boundary, background: T) {
+        self.grid.set_boundary(boundary, background);
+    }
+    fn chunk_origin(&self) -> (isize, isize) {
+        self.grid.chunk_origin()
+    }
+
+    fn bufcell_at_mut(&mut self, x: usize, y: usize) -> CellMut<'_, T> {
         self.grid.bufcell_at_mut(x, y)
     }
     fn swap_buffer(&mut self) {
         self.grid.swap_buffer();
     }
+    fn begin_update(&mut self, skip_stable: bool) {
+        self.grid.begin_update(skip_stable);
+    }
+    fn is_active_chunk(&self, x: usize, y: usize) -> bool {
+        self.grid.is_active_chunk(x, y)
+    }
+    fn mark_all_changed(&mut self) {
+        self.grid.mark_all_changed();
+    }
+    fn is_changed_chunk(&self, x: usize, y: usize) -> Option<bool> {
+        self.grid.is_changed_chunk(x, y)
+    }
+    fn prev_chunk_at(&self, x: usize, y: usize) -> &Chunk<T> {
+        self.grid.prev_chunk_at(x, y)
+    }
+    fn fill_buffer_chunks<F>(&mut self, f: F) -> anyhow::Result<()>
+    where
+        F: Fn(&Self, usize, usize, &mut Chunk<T>) -> anyhow::Result<()> + Sync,
+        T: Send + Sync,
+    {
+        let mut buffer = self.grid.take_buffer();
+        let result = self.grid.fill_chunks(&mut buffer, |x, y, chunk| f(self, x, y, chunk));
+        self.grid.put_buffer(buffer);
+        result
+    }
 
     fn expand_x(&mut self, n: isize, init: T) {
         self.grid.expand_x(n, init)
@@ -435,6 +1234,15 @@ impl<T: State> Board<T> for SquareGrid<T> {
     fn expand_y(&mut self, n: isize, init: T) {
         self.grid.expand_y(n, init)
     }
+    fn shrink_x(&mut self, n: isize) {
+        self.grid.shrink_x(n)
+    }
+    fn shrink_y(&mut self, n: isize) {
+        self.grid.shrink_y(n)
+    }
+    fn crop(&mut self, x: Range<usize>, y: Range<usize>) {
+        self.grid.crop(x, y)
+    }
 
     fn clear<R: Rule<CellState = T>>(&mut self, rule: &R) -> anyhow::Result<()> {
         self.grid.clear(rule)
@@ -447,6 +1255,7 @@ impl<T: State> Board<T> for SquareGrid<T> {
         self.grid.randomize(rule, rng)
     }
 
+    #[cfg(feature = "gui")]
     fn location(
         &self,
         x: usize,
@@ -476,7 +1285,8 @@ impl<T: State> Board<T> for SquareGrid<T> {
         }
     }
 
-    fn paint<R: Rule<CellState = T>>(
+    #[cfg(feature = "gui")]
+    fn paint<R: RuleUi<CellState = T>>(
         &self,
         painter: &egui::Painter,
         origin: egui::Pos2,
@@ -530,8 +1340,9 @@ impl<T: State> Board<T> for SquareGrid<T> {
         Ok(())
     }
 
+    #[cfg(feature = "gui")]
     #[allow(clippy::too_many_arguments)]
-    fn paint_clipboard<R: Rule<CellState = T>>(
+    fn paint_clipboard<R: RuleUi<CellState = T>>(
         &self,
         painter: &egui::Painter,
         origin: egui::Pos2,
@@ -597,6 +1408,33 @@ impl<T: State> Board<T> for SquareGrid<T> {
     ) -> anyhow::Result<()> {
         self.grid.paste_clipboard(xofs, yofs, cb)
     }
+
+    #[cfg(feature = "gui")]
+    fn render<R: RuleUi<CellState = T>>(
+        &self,
+        rule: &R,
+        cell_width: u32,
+    ) -> anyhow::Result<image::RgbaImage> {
+        let w = cell_width;
+        let (img_w, img_h) =
+            render_size(self.grid.width() as f64 * w as f64, self.grid.height() as f64 * w as f64)?;
+        let mut img = image::RgbaImage::from_pixel(img_w, img_h, to_rgba(rule.background()));
+
+        // leave a gap between cells as `paint` does
+        let ofs = if cell_width <= 16 { 0 } else { 1 };
+        for j in 0..self.grid.height() {
+            for i in 0..self.grid.width() {
+                let color = to_rgba(rule.color(self.grid.cell_at(i, j))?);
+                let (i, j) = (i as u32, j as u32);
+                for y in (j * w + ofs)..((j + 1) * w - ofs) {
+                    for x in (i * w + ofs)..((i + 1) * w - ofs) {
+                        img.put_pixel(x, y, color);
+                    }
+                }
+            }
+        }
+        Ok(img)
+    }
 }
 
 /// Hex grid wraps a `Grid` and implement vis/UI functions.
@@ -647,6 +1485,9 @@ impl<T: State> Board<T> for HexGrid<T> {
     fn chunk_at(&self, x: usize, y: usize) -> &Chunk<T> {
         self.grid.chunk_at(x, y)
     }
+    fn chunk_at_mut(&mut self, x: usize, y: usize) -> &mut Chunk<T> {
+        self.grid.chunk_at_mut(x, y)
+    }
 
     fn has_cell(&self, x: usize, y: usize) -> bool {
         self.grid.has_cell(x, y)
@@ -654,16 +1495,51 @@ impl<T: State> Board<T> for HexGrid<T> {
     fn cell_at(&self, x: usize, y: usize) -> &T {
         self.grid.cell_at(x, y)
     }
-    fn cell_at_mut(&mut self, x: usize, y: usize) -> &mut T {
+    fn cell_at_mut(&mut self, x: usize, y: usize) -> CellMut<'_, T> {
         self.grid.cell_at_mut(x, y)
     }
 
-    fn bufcell_at_mut(&mut self, x: usize, y: usize) -> &mut T {
+    fn boundary(&self) -> Boundary {
+        self.grid.boundary()
+    }
+    fn set_boundary(&mut self, boundary: Boundary, background: T) {
+        self.grid.set_boundary(boundary, background);
+    }
+    fn chunk_origin(&self) -> (isize, isize) {
+        self.grid.chunk_origin()
+    }
+
+    fn bufcell_at_mut(&mut self, x: usize, y: usize) -> CellMut<'_, T> {
         self.grid.bufcell_at_mut(x, y)
     }
     fn swap_buffer(&mut self) {
         self.grid.swap_buffer();
     }
+    fn begin_update(&mut self, skip_stable: bool) {
+        self.grid.begin_update(skip_stable);
+    }
+    fn is_active_chunk(&self, x: usize, y: usize) -> bool {
+        self.grid.is_active_chunk(x, y)
+    }
+    fn mark_all_changed(&mut self) {
+        self.grid.mark_all_changed();
+    }
+    fn is_changed_chunk(&self, x: usize, y: usize) -> Option<bool> {
+        self.grid.is_changed_chunk(x, y)
+    }
+    fn prev_chunk_at(&self, x: usize, y: usize) -> &Chunk<T> {
+        self.grid.prev_chunk_at(x, y)
+    }
+    fn fill_buffer_chunks<F>(&mut self, f: F) -> anyhow::Result<()>
+    where
+        F: Fn(&Self, usize, usize, &mut Chunk<T>) -> anyhow::Result<()> + Sync,
+        T: Send + Sync,
+    {
+        let mut buffer = self.grid.take_buffer();
+        let result = self.grid.fill_chunks(&mut buffer, |x, y, chunk| f(self, x, y, chunk));
+        self.grid.put_buffer(buffer);
+        result
+    }
 
     fn expand_x(&mut self, n: isize, init: T) {
         self.grid.expand_x(n, init)
@@ -671,6 +1547,15 @@ impl<T: State> Board<T> for HexGrid<T> {
     fn expand_y(&mut self, n: isize, init: T) {
         self.grid.expand_y(n, init)
     }
+    fn shrink_x(&mut self, n: isize) {
+        self.grid.shrink_x(n)
+    }
+    fn shrink_y(&mut self, n: isize) {
+        self.grid.shrink_y(n)
+    }
+    fn crop(&mut self, x: Range<usize>, y: Range<usize>) {
+        self.grid.crop(x, y)
+    }
 
     fn clear<R: Rule<CellState = T>>(&mut self, rule: &R) -> anyhow::Result<()> {
         self.grid.clear(rule)
@@ -683,6 +1568,7 @@ impl<T: State> Board<T> for HexGrid<T> {
         self.grid.randomize(rule, rng)
     }
 
+    #[cfg(feature = "gui")]
     fn location(
         &self,
         x: usize,
@@ -721,7 +1607,8 @@ impl<T: State> Board<T> for HexGrid<T> {
         }
     }
 
-    fn paint<R: Rule<CellState = T>>(
+    #[cfg(feature = "gui")]
+    fn paint<R: RuleUi<CellState = T>>(
         &self,
         painter: &egui::Painter,
         origin: egui::Pos2,
@@ -771,8 +1658,9 @@ impl<T: State> Board<T> for HexGrid<T> {
         Ok(())
     }
 
+    #[cfg(feature = "gui")]
     #[allow(clippy::too_many_arguments)]
-    fn paint_clipboard<R: Rule<CellState = T>>(
+    fn paint_clipboard<R: RuleUi<CellState = T>>(
         &self,
         painter: &egui::Painter,
         origin: egui::Pos2,
@@ -834,6 +1722,70 @@ impl<T: State> Board<T> for HexGrid<T> {
     ) -> anyhow::Result<()> {
         self.grid.paste_clipboard(xofs, yofs, cb)
     }
+
+    #[cfg(feature = "gui")]
+    fn render<R: RuleUi<CellState = T>>(
+        &self,
+        rule: &R,
+        cell_width: u32,
+    ) -> anyhow::Result<image::RgbaImage> {
+        let diameter = cell_width as f32;
+        let r = diameter * 0.5;
+        let sqrt3 = 3.0_f32.sqrt();
+
+        // odd rows are shifted by r
+        let (img_w, img_h) = render_size(
+            (self.grid.width() as f32 * diameter + r).ceil() as f64,
+            ((self.grid.height() as f32 - 1.0) * r * sqrt3 + diameter).ceil() as f64,
+        )?;
+        let mut img = image::RgbaImage::from_pixel(img_w, img_h, to_rgba(rule.background()));
+
+        // draw circles
+        for j in 0..self.grid.height() {
+            let cy = r + j as f32 * r * sqrt3;
+            let xofs = if j % 2 == 0 { r } else { diameter };
+
+            for i in 0..self.grid.width() {
+                let cx = xofs + (i as f32) * diameter;
+                let color = to_rgba(rule.color(self.grid.cell_at(i, j))?);
+
+                let x_range = ((cx - r).floor() as u32)..((cx + r).ceil() as u32).min(img_w);
+                let y_range = ((cy - r).floor() as u32)..((cy + r).ceil() as u32).min(img_h);
+                for y in y_range {
+                    for x in x_range.clone() {
+                        let dx = x as f32 + 0.5 - cx;
+                        let dy = y as f32 + 0.5 - cy;
+                        if dx * dx + dy * dy <= r * r {
+                            img.put_pixel(x, y, color);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(img)
+    }
+}
+
+/// the number of pixels `render` allocates at most (1 GiB in RGBA)
+#[cfg(feature = "gui")]
+const MAX_RENDER_PIXELS: f64 = (1u64 << 28) as f64;
+
+#[cfg(feature = "gui")]
+fn render_size(width: f64, height: f64) -> anyhow::Result<(u32, u32)> {
+    if MAX_RENDER_PIXELS < width * height || f64::from(u32::MAX) < width.max(height) {
+        return Err(anyhow::anyhow!(
+            "the image ({}x{} pixels) exceeds {} pixels. use a smaller cell width",
+            width,
+            height,
+            MAX_RENDER_PIXELS
+        ));
+    }
+    Ok((width as u32, height as u32))
+}
+
+#[cfg(feature = "gui")]
+fn to_rgba(color: egui::Color32) -> image::Rgba<u8> {
+    image::Rgba(color.to_array())
 }
 
 /// A small piece of board to copy-paste a region in a board
@@ -859,7 +1811,7 @@ impl<T: State> ClipBoard<T> {
         }
     }
 
-    fn cell_at(&self, x: usize, y: usize) -> &Option<T> {
+    pub fn cell_at(&self, x: usize, y: usize) -> &Option<T> {
         assert!(x < self.x && y < self.y, "x({}) < {} && y({}) < {}", x, self.x, y, self.y);
         &self.cells[x + y * self.x]
     }
@@ -917,3 +1869,119 @@ impl<T: State> Grid<T> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_out_of_board() {
+        let (w, h) = (4, 3);
+        let cases = [
+            (
+                Boundary::Periodic,
+                [(-1, 0), (4, -1), (1, -1), (-1, -1)],
+                [(3, 0), (0, 2), (1, 2), (3, 2)],
+            ),
+            (
+                Boundary::Reflective,
+                [(-1, 0), (4, 2), (-2, 3), (-1, -1)],
+                [(0, 0), (3, 2), (1, 2), (0, 0)],
+            ),
+            (
+                Boundary::KleinBottle,
+                [(-1, 1), (4, 0), (1, -1), (-1, 3)],
+                [(3, 1), (0, 0), (2, 2), (0, 0)],
+            ),
+            (
+                Boundary::CrossSurface,
+                [(-1, 1), (4, 0), (0, -1), (-1, -1)],
+                [(3, 1), (0, 2), (3, 2), (0, 0)],
+            ),
+        ];
+        for (boundary, points, expected) in cases {
+            for ((x, y), e) in points.into_iter().zip(expected) {
+                assert_eq!(boundary.resolve(x, y, w, h), Some(e), "{:?} ({}, {})", boundary, x, y);
+            }
+        }
+        for boundary in [Boundary::Fixed, Boundary::Infinite] {
+            assert_eq!(boundary.resolve(-1, 0, w, h), None);
+            assert_eq!(boundary.resolve(0, 3, w, h), None);
+        }
+        for boundary in Boundary::all() {
+            assert_eq!(boundary.resolve(3, 2, w, h), Some((3, 2)));
+        }
+    }
+
+    // if a cell is next to another across an edge, the other is also next to it
+    #[test]
+    fn neighbors_across_edges_are_mutual() {
+        let (w, h) = (4, 3);
+        let offsets = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+        for boundary in Boundary::all() {
+            for y in 0..h as isize {
+                for x in 0..w as isize {
+                    for (dx, dy) in offsets {
+                        let (nx, ny) = match boundary.resolve(x + dx, y + dy, w, h) {
+                            Some((nx, ny)) => (nx as isize, ny as isize),
+                            None => continue,
+                        };
+                        let back = offsets.iter().any(|(ex, ey)| {
+                            boundary.resolve(nx + ex, ny + ey, w, h)
+                                == Some((x as usize, y as usize))
+                        });
+                        assert!(back, "{:?} ({}, {}) + ({}, {})", boundary, x, y, dx, dy);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_boundary_reads_out_of_board_as_none() {
+        use crate::lifegame::LifeGameState::{Alive, Dead};
+
+        let mut board = SquareGrid::init(1, 1, Dead);
+        *board.cell_at_mut(0, 0) = Alive;
+        board.set_boundary(Boundary::Periodic, Dead);
+        assert_eq!(board.neighbor_at(-1, -1), Some(&Dead));
+        assert_eq!(board.neighbor_at(CHUNK_LEN as isize, CHUNK_LEN as isize), Some(&Alive));
+        board.set_boundary(Boundary::Fixed, Dead);
+        assert_eq!(board.neighbor_at(CHUNK_LEN as isize, CHUNK_LEN as isize), None);
+    }
+
+    #[test]
+    fn packed_chunk_is_serialized_as_cells() {
+        use crate::lifegame::LifeGameState::{self, Alive};
+
+        let mut chunk = Chunk::<LifeGameState>::default();
+        *chunk.cell_at_mut(3, 1) = Alive;
+        let json = serde_json::to_string(&chunk).unwrap();
+        assert!(json.starts_with(r#"{"cells":["#), "{}", json);
+        let back: Chunk<LifeGameState> = serde_json::from_str(&json).unwrap();
+        assert!(back == chunk);
+        assert_eq!(back.cell_at(3, 1), &Alive);
+
+        let short = r#"{"cells":["Dead"]}"#;
+        assert!(serde_json::from_str::<Chunk<LifeGameState>>(short).is_err());
+    }
+
+    #[cfg(feature = "gui")]
+    #[test]
+    fn render_rejects_huge_images() {
+        use crate::lifegame::{LifeGameRule, LifeGameState::Dead};
+        use crate::rule::MooreNeighborhood;
+
+        let rule = LifeGameRule::<MooreNeighborhood>::default();
+        let square = SquareGrid::init(2, 1, Dead);
+        let img = square.render(&rule, 3).unwrap();
+        assert_eq!(img.dimensions(), (2 * CHUNK_LEN as u32 * 3, CHUNK_LEN as u32 * 3));
+        assert!(square.render(&rule, 1 << 12).is_err());
+        assert!(square.render(&rule, u32::MAX).is_err());
+
+        let hex = HexGrid::init(2, 1, Dead);
+        assert!(hex.render(&rule, 3).is_ok());
+        assert!(hex.render(&rule, 1 << 12).is_err());
+        assert!(hex.render(&rule, u32::MAX).is_err());
+    }
+}
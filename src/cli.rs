@@ -0,0 +1,361 @@
+#![warn(clippy::all, rust_2018_idioms)]
+
+//! Run a cellular automaton without opening a window.
+//!
+//! It loads a world from a JSON file (the same format as the "serialize"
+//! button of the app writes), updates it N steps, and writes it back to JSON.
+
+use miniascape::board::{Boundary, HexGrid, SquareGrid};
+use miniascape::hashlife::{HashLifeWorld, LifeRule};
+use miniascape::rule::{
+    ConfigurableNeighborhood, CubicNeighborhood, CubicNeighborhoodKind, HexGridNeighborhood,
+    MooreNeighborhood, NeighborhoodShape, VonNeumannNeighborhood, MAX_RANGE,
+};
+use miniascape::world::{World, World2D};
+use miniascape::world3d::World3D;
+
+use miniascape::gray_scott::{GrayScottRule, GrayScottState};
+use miniascape::life3d::Life3DRule;
+use miniascape::lifegame::{HighLifeRule, LifeGameRule, LifeGameState, LifeLikeGameRule};
+use miniascape::rock_paper_scissors::{RockPaperScissorsRule, RockPaperScissorsState};
+use miniascape::wireworld::{WireWorldRule, WireWorldState};
+
+use anyhow::{anyhow, Context as _};
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+const USAGE: &str = "\
+usage: miniascape_cli --rule <rule> --steps <n> [options]
+
+rules:
+    lifegame
+    highlife
+    lifelike:<survive>/<birth>      (e.g. lifelike:23/3, or lifelike:34-58/34-45 with
+                                     a larger neighborhood)
+    hexlife:<survive>/<birth>       (e.g. hexlife:23/3)
+    wireworld
+    gray-scott[:<key>=<value>,...]  (keys: Du, Dv, f, k, n)
+    rock-paper-scissors[:moore|neumann|hex]
+    life3d:<rule>[:6|18|26]         3D life in Bays' notation {El}{Eu}{Fl}{Fu} (e.g. life3d:4555,
+                                     life3d:5766). the number of neighbors defaults to 26
+
+options:
+    --input <file.json>    world to start from. if omitted, a new world is created
+    --output <file.json>   where the resulting world is written. defaults to stdout
+    --steps <n>            the number of steps (`World::update` calls)
+    --size <x>x<y>[x<z>]   the number of chunks of a new world (default: 4x3). a 3D
+                           world has z * 16 layers (default: 1)
+    --randomize            randomize a new world before running
+    --seed <n>             seed of the random number generator (default: 123456789)
+    --boundary <b>         boundary condition, one of periodic, fixed, reflective,
+                           klein-bottle, cross-surface and infinite (default: periodic,
+                           or the one saved in the input)
+    --neighborhood <n>     neighborhood of lifelike and rock-paper-scissors on a square
+                           grid. <shape>:<range> where shape is one of moore, von-neumann
+                           and circular (range <= 16), or custom:<file> with a mask like
+                           `.#.` / `#.#` / `.#.` in rows (`#` is a neighbor)
+    --auto-crop            remove the chunks at the border that contain only the default
+                           state before writing the result
+    --hashlife <k>         run by HashLife, advancing 2^k generations per step. the plane
+                           is infinite, not periodic. only for lifegame, highlife and lifelike
+";
+
+struct Options {
+    rule: String,
+    input: Option<String>,
+    output: Option<String>,
+    steps: u64,
+    size: (usize, usize, usize),
+    randomize: bool,
+    seed: u64,
+    boundary: Option<Boundary>,
+    neighborhood: Option<ConfigurableNeighborhood>,
+    auto_crop: bool,
+    hashlife: Option<u32>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        let mut rule = None;
+        let mut input = None;
+        let mut output = None;
+        let mut steps = None;
+        let mut size = (4, 3, 1);
+        let mut randomize = false;
+        let mut seed = 123456789;
+        let mut boundary = None;
+        let mut neighborhood = None;
+        let mut auto_crop = false;
+        let mut hashlife = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow!("{} requires a value", arg));
+            match arg.as_str() {
+                "--rule" => rule = Some(value()?),
+                "--input" => input = Some(value()?),
+                "--output" => output = Some(value()?),
+                "--steps" => steps = Some(value()?.parse().context("--steps")?),
+                "--size" => {
+                    let v = value()?;
+                    let ns = v
+                        .split('x')
+                        .map(|n| n.parse().context("--size"))
+                        .collect::<anyhow::Result<Vec<usize>>>()?;
+                    size = match ns[..] {
+                        [x, y] => (x, y, 1),
+                        [x, y, z] => (x, y, z),
+                        _ => return Err(anyhow!("--size should be <x>x<y> or <x>x<y>x<z>")),
+                    };
+                    if ns.contains(&0) {
+                        return Err(anyhow!("--size should not contain 0: {}", v));
+                    }
+                }
+                "--randomize" => randomize = true,
+                "--seed" => seed = value()?.parse().context("--seed")?,
+                "--boundary" => boundary = Some(Boundary::from_name(&value()?)?),
+                "--neighborhood" => neighborhood = Some(parse_neighborhood(&value()?)?),
+                "--auto-crop" => auto_crop = true,
+                "--hashlife" => hashlife = Some(value()?.parse().context("--hashlife")?),
+                _ => return Err(anyhow!("unknown argument: {}", arg)),
+            }
+        }
+
+        Ok(Self {
+            rule: rule.ok_or_else(|| anyhow!("--rule is required"))?,
+            input,
+            output,
+            steps: steps.ok_or_else(|| anyhow!("--steps is required"))?,
+            size,
+            randomize,
+            seed,
+            boundary,
+            neighborhood,
+            auto_crop,
+            hashlife,
+        })
+    }
+}
+
+fn parse_neighborhood(spec: &str) -> anyhow::Result<ConfigurableNeighborhood> {
+    let (shape, param) = spec
+        .split_once(':')
+        .ok_or_else(|| anyhow!("--neighborhood should be <shape>:<range> or custom:<file>"))?;
+    match NeighborhoodShape::from_name(shape)? {
+        NeighborhoodShape::Custom => {
+            let mask = std::fs::read_to_string(param)
+                .context(format!("Couldn't read file content -> {}", param))?;
+            ConfigurableNeighborhood::from_mask(&mask)
+                .context(format!("Couldn't load file content as mask -> {}", param))
+        }
+        shape => {
+            let range = param.parse().context("--neighborhood")?;
+            if MAX_RANGE < range {
+                return Err(anyhow!("the range of a neighborhood should be at most {}", MAX_RANGE));
+            }
+            Ok(ConfigurableNeighborhood::new(shape, range))
+        }
+    }
+}
+
+fn run<W>(rule: W::Rule, opts: &Options) -> anyhow::Result<()>
+where
+    for<'de> W: World + Serialize + Deserialize<'de>,
+{
+    simulate::<W>(prepare(rule, opts)?, opts)
+}
+
+fn run_hashlife<R: LifeRule>(rule: R, k: u32, opts: &Options) -> anyhow::Result<()> {
+    let mut world: HashLifeWorld<R> = prepare(rule, opts)?;
+    world.set_step_log2(k);
+    simulate(world, opts)
+}
+
+fn prepare<W>(rule: W::Rule, opts: &Options) -> anyhow::Result<W>
+where
+    for<'de> W: World + Serialize + Deserialize<'de>,
+{
+    let mut world = if let Some(input) = &opts.input {
+        let content = std::fs::read_to_string(input)
+            .context(format!("Couldn't read file content -> {}", input))?;
+        let mut world: W = serde_json::from_str(&content)
+            .context(format!("Couldn't load file content as board -> {}", input))?;
+        // rule is not serialized
+        *world.rule_mut() = rule;
+        world
+    } else {
+        let (x_chunks, y_chunks, z_chunks) = opts.size;
+        let mut world = W::new(rule, x_chunks, y_chunks, z_chunks);
+        if opts.randomize {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(opts.seed);
+            world.randomize(&mut rng)?;
+        }
+        world
+    };
+    if let Some(boundary) = opts.boundary {
+        world.set_boundary(boundary)?;
+    }
+    Ok(world)
+}
+
+fn simulate<W>(mut world: W, opts: &Options) -> anyhow::Result<()>
+where
+    for<'de> W: World + Serialize + Deserialize<'de>,
+{
+    for step in 0..opts.steps {
+        world.update().context(format!("Failed to update the world at step {}", step))?;
+    }
+    if opts.auto_crop {
+        world.auto_crop()?;
+    }
+
+    let serialized = serde_json::to_string(&world)?;
+    if let Some(output) = &opts.output {
+        std::fs::write(output, serialized).context(format!("Couldn't write file -> {}", output))?;
+    } else {
+        println!("{}", serialized);
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|a| a == "--help" || a == "-h") {
+        eprint!("{}", USAGE);
+        return Ok(());
+    }
+    let opts = Options::parse(args.into_iter())?;
+
+    let (name, param) = opts.rule.split_once(':').unwrap_or((opts.rule.as_str(), ""));
+    if opts.hashlife.is_some() && !matches!(name, "lifegame" | "highlife" | "lifelike") {
+        return Err(anyhow!("--hashlife is not available for {}", name));
+    }
+    if opts.hashlife.is_some() && opts.boundary.is_some() {
+        return Err(anyhow!("--boundary is not available with --hashlife"));
+    }
+    if opts.neighborhood.is_some() && !matches!(name, "lifelike" | "rock-paper-scissors") {
+        return Err(anyhow!("--neighborhood is not available for {}", name));
+    }
+    if opts.hashlife.is_some() && opts.neighborhood.is_some() {
+        return Err(anyhow!("--neighborhood is not available with --hashlife"));
+    }
+    if opts.size.2 != 1 && name != "life3d" {
+        return Err(anyhow!("{} is a 2D rule. --size should be <x>x<y>", name));
+    }
+    match name {
+        "lifegame" => match opts.hashlife {
+            Some(k) => run_hashlife(LifeGameRule::<MooreNeighborhood>::default(), k, &opts),
+            None => run::<World2D<LifeGameRule<MooreNeighborhood>, SquareGrid<LifeGameState>>>(
+                LifeGameRule::default(),
+                &opts,
+            ),
+        },
+        "highlife" => match opts.hashlife {
+            Some(k) => run_hashlife(HighLifeRule::<MooreNeighborhood>::default(), k, &opts),
+            None => run::<World2D<HighLifeRule<MooreNeighborhood>, SquareGrid<LifeGameState>>>(
+                HighLifeRule::default(),
+                &opts,
+            ),
+        },
+        "lifelike" => {
+            if !LifeLikeGameRule::<MooreNeighborhood>::is_valid_rule(param) {
+                return Err(anyhow!("invalid life-like rule: {}", param));
+            }
+            if let Some(neighborhood) = &opts.neighborhood {
+                let rule =
+                    LifeLikeGameRule::from_rule(param).with_neighborhood(neighborhood.clone());
+                return run::<
+                    World2D<LifeLikeGameRule<ConfigurableNeighborhood>, SquareGrid<LifeGameState>>,
+                >(rule, &opts);
+            }
+            let rule = LifeLikeGameRule::<MooreNeighborhood>::from_rule(param);
+            match opts.hashlife {
+                Some(k) => run_hashlife(rule, k, &opts),
+                None => run::<
+                    World2D<LifeLikeGameRule<MooreNeighborhood>, SquareGrid<LifeGameState>>,
+                >(rule, &opts),
+            }
+        }
+        "hexlife" => {
+            if !LifeLikeGameRule::<HexGridNeighborhood>::is_valid_rule(param) {
+                return Err(anyhow!("invalid life-like rule: {}", param));
+            }
+            run::<World2D<LifeLikeGameRule<HexGridNeighborhood>, HexGrid<LifeGameState>>>(
+                LifeLikeGameRule::from_rule(param),
+                &opts,
+            )
+        }
+        "wireworld" => run::<World2D<WireWorldRule, SquareGrid<WireWorldState>>>(
+            WireWorldRule::default(),
+            &opts,
+        ),
+        "gray-scott" => run::<World2D<GrayScottRule, SquareGrid<GrayScottState>>>(
+            GrayScottRule::from_params(param)?,
+            &opts,
+        ),
+        "rock-paper-scissors" => match (param, &opts.neighborhood) {
+            ("", Some(neighborhood)) => run::<
+                World2D<
+                    RockPaperScissorsRule<ConfigurableNeighborhood>,
+                    SquareGrid<RockPaperScissorsState>,
+                >,
+            >(
+                RockPaperScissorsRule::default().with_neighborhood(neighborhood.clone()),
+                &opts,
+            ),
+            (_, Some(_)) => Err(anyhow!("--neighborhood is not available for {}", opts.rule)),
+            ("" | "moore", None) => run::<
+                World2D<
+                    RockPaperScissorsRule<MooreNeighborhood>,
+                    SquareGrid<RockPaperScissorsState>,
+                >,
+            >(RockPaperScissorsRule::default(), &opts),
+            ("neumann", None) => run::<
+                World2D<
+                    RockPaperScissorsRule<VonNeumannNeighborhood>,
+                    SquareGrid<RockPaperScissorsState>,
+                >,
+            >(RockPaperScissorsRule::default(), &opts),
+            ("hex", None) => run::<
+                World2D<
+                    RockPaperScissorsRule<HexGridNeighborhood>,
+                    HexGrid<RockPaperScissorsState>,
+                >,
+            >(RockPaperScissorsRule::default(), &opts),
+            _ => Err(anyhow!("unknown neighborhood: {}", param)),
+        },
+        "life3d" => {
+            let (rule, neighbors) = param.split_once(':').unwrap_or((param, "26"));
+            let kind = match neighbors {
+                "6" => CubicNeighborhoodKind::VonNeumann,
+                "18" => CubicNeighborhoodKind::Edges,
+                "26" => CubicNeighborhoodKind::Moore,
+                _ => return Err(anyhow!("the number of neighbors should be 6, 18 or 26")),
+            };
+            let rule = Life3DRule::from_rule(rule)
+                .ok_or_else(|| anyhow!("invalid 3D life rule: {}", rule))?
+                .with_neighborhood(CubicNeighborhood::new(kind));
+            run::<World3D<Life3DRule>>(rule, &opts)
+        }
+        _ => Err(anyhow!("unknown rule: {}\n\n{}", opts.rule, USAGE)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Options> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn size_rejects_zero() {
+        let base = ["--rule", "lifegame", "--steps", "1", "--size"];
+        for size in ["0x0", "0x3", "4x0", "4x3x0"] {
+            let args: Vec<&str> = base.iter().copied().chain([size]).collect();
+            assert!(parse(&args).is_err(), "{}", size);
+        }
+        let args: Vec<&str> = base.iter().copied().chain(["4x3x2"]).collect();
+        assert_eq!(parse(&args).unwrap().size, (4, 3, 2));
+    }
+}
@@ -1,9 +1,19 @@
-use crate::rule::{Neighbors, Rule, State};
+use crate::board::{Board, PlainCells, CHUNK_LEN};
+#[cfg(feature = "gui")]
+use crate::platform;
+use crate::rule::{Neighbors, Rule, State, UpdateContext};
+#[cfg(feature = "gui")]
+use crate::rule::{NeighborsUi, RuleUi, StateUi};
+use crate::world;
+use rand::rngs::StdRng;
 use rand::Rng;
 use rhai::packages::Package;
 use rhai::{Dynamic, Engine, Scope, AST};
 use rhai_rand::RandomPackage;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Context as _;
 use thiserror::Error;
@@ -13,7 +23,52 @@ pub struct DynamicState {
     value: rhai::Dynamic,
 }
 
+// rhai::Dynamic does not implement PartialEq.
+impl PartialEq for DynamicState {
+    fn eq(&self, other: &Self) -> bool {
+        if self.value.type_id() != other.value.type_id() {
+            return false;
+        }
+        if let (Ok(a), Ok(b)) = (self.value.as_bool(), other.value.as_bool()) {
+            a == b
+        } else if let (Ok(a), Ok(b)) = (self.value.as_int(), other.value.as_int()) {
+            a == b
+        } else if let (Ok(a), Ok(b)) = (self.value.as_float(), other.value.as_float()) {
+            a == b
+        } else {
+            // compound values like arrays and maps are compared by their representation
+            self.value.to_string() == other.value.to_string()
+        }
+    }
+}
+
 impl State for DynamicState {
+    type Cells = PlainCells<Self>;
+}
+
+// `UpdateContext` in rhai scripts, with its own random number generator
+#[derive(Clone)]
+struct ScriptContext {
+    ctx: UpdateContext,
+    rng: Option<StdRng>, // seeded on the first use
+}
+
+fn register_context(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<ScriptContext>("Context")
+        .register_get("x", |c: &mut ScriptContext| c.ctx.x as rhai::INT)
+        .register_get("y", |c: &mut ScriptContext| c.ctx.y as rhai::INT)
+        .register_get("width", |c: &mut ScriptContext| c.ctx.width as rhai::INT)
+        .register_get("height", |c: &mut ScriptContext| c.ctx.height as rhai::INT)
+        .register_get("generation", |c: &mut ScriptContext| c.ctx.generation as rhai::INT)
+        .register_fn("rand_float", |c: &mut ScriptContext| {
+            let ctx = c.ctx;
+            c.rng.get_or_insert_with(|| ctx.rng()).gen::<rhai::FLOAT>()
+        });
+}
+
+#[cfg(feature = "gui")]
+impl StateUi for DynamicState {
     fn inspect(&mut self, ui: &mut egui::Ui, buf: &mut String) {
         ui.label(format!("Dynamic value: {:?}", self.value));
 
@@ -42,152 +97,545 @@ impl State for DynamicState {
     }
 }
 
-pub struct DynamicRule<N> {
-    neighbors: std::marker::PhantomData<N>,
+const DEFAULT_MAX_OPERATIONS: u64 = 1_000_000;
+const DEFAULT_MAX_CALL_LEVELS: usize = 64;
+const DEFAULT_MAX_STRING_SIZE: usize = 1 << 20;
+const DEFAULT_MAX_STEP_TIME: Duration = Duration::from_secs(5);
+
+/// Limits on each call of a function in a script, and on the time of a step.
+/// A call or a step that exceeds them, e.g. by an infinite loop, fails instead
+/// of freezing the app. `None` means unlimited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScriptLimits {
+    /// The number of operations, e.g. statements, expressions and iterations.
+    pub max_operations: Option<u64>,
+    /// The depth of nested function calls. If `None`, the default of rhai is
+    /// used because a deep recursion overflows the stack.
+    pub max_call_levels: Option<usize>,
+    /// The length of a string in bytes.
+    pub max_string_size: Option<usize>,
+    /// The wall-clock time to update the whole board once.
+    pub max_step_time: Option<Duration>,
+}
 
-    engine: Engine,
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            max_operations: Some(DEFAULT_MAX_OPERATIONS),
+            max_call_levels: Some(DEFAULT_MAX_CALL_LEVELS),
+            max_string_size: Some(DEFAULT_MAX_STRING_SIZE),
+            max_step_time: Some(DEFAULT_MAX_STEP_TIME),
+        }
+    }
+}
 
-    update_fn_str: String,
-    update_fn: AST,
-    open_update_fn: bool,
-    open_update_fn_compilation_result: Option<anyhow::Error>,
+impl ScriptLimits {
+    fn apply(&self, engine: &mut Engine) {
+        // 0 means unlimited in rhai
+        engine.set_max_operations(self.max_operations.unwrap_or(0));
+        engine.set_max_call_levels(
+            self.max_call_levels.unwrap_or_else(|| Engine::new().max_call_levels()),
+        );
+        engine.set_max_string_size(self.max_string_size.unwrap_or(0));
+    }
+}
+
+// the deadline of the step being updated, shared with the progress callback
+// of the engine so that a long call also stops at it.
+#[derive(Clone)]
+struct Deadline {
+    clock: instant::Instant,
+    millis: Arc<AtomicU64>, // since `clock`, or `u64::MAX` if not updating
+}
 
-    clear_fn_str: String,
-    clear_fn: AST,
-    open_clear_fn: bool,
-    open_clear_fn_compilation_result: Option<anyhow::Error>,
+impl Deadline {
+    // the number of operations between the checks in a call
+    const CHECK_INTERVAL: u64 = 1024;
 
-    randomize_fn_str: String,
-    randomize_fn: AST,
-    open_randomize_fn: bool,
-    open_randomize_fn_compilation_result: Option<anyhow::Error>,
+    fn new() -> Self {
+        Self { clock: instant::Instant::now(), millis: Arc::new(AtomicU64::new(u64::MAX)) }
+    }
 
-    next_fn_str: String,
-    next_fn: AST,
-    open_next_fn: bool,
-    open_next_fn_compilation_result: Option<anyhow::Error>,
+    fn start(&self, limit: Option<Duration>) {
+        let millis = limit.map_or(u64::MAX, |l| (self.clock.elapsed() + l).as_millis() as u64);
+        self.millis.store(millis, Ordering::Relaxed);
+    }
 
-    color_fn_str: String,
-    color_fn: AST,
-    open_color_fn: bool,
-    open_color_fn_compilation_result: Option<anyhow::Error>,
+    fn finish(&self) {
+        self.millis.store(u64::MAX, Ordering::Relaxed);
+    }
 
-    background: egui::Color32,
+    fn is_over(&self) -> bool {
+        let millis = self.millis.load(Ordering::Relaxed);
+        millis != u64::MAX && millis < self.clock.elapsed().as_millis() as u64
+    }
 }
 
-impl<N: Neighbors> Default for DynamicRule<N> {
-    fn default() -> Self {
-        let mut engine = Engine::new();
+pub struct DynamicRule<N> {
+    neighborhood: N,
 
-        // we need a random number generator
-        let random = RandomPackage::new();
-        engine.register_global_module(random.as_shared_module());
+    engine: Engine,
+    limits: ScriptLimits,
+    deadline: Deadline,
+
+    // the script is the source of truth of all the functions of the rule
+    script: String,
+    ast: AST,
+    update_arity: usize,      // the number of parameters of `fn update`
+    params: Vec<ScriptParam>, // declared by `fn params()`
+    globals: Scope<'static>,  // the parameters and the top-level constants
+
+    #[cfg(feature = "gui")]
+    script_buf: String, // being edited, not compiled yet
+    #[cfg(feature = "gui")]
+    open_script: bool,
+    #[cfg(feature = "gui")]
+    compilation_result: Option<anyhow::Error>,
+
+    #[cfg(feature = "gui")]
+    background: egui::Color32,
+}
 
-        let randomize_fn_str = r#"
-fn randomize() {
-    return if rand_float() < 0.3 { true } else { false };
+const DEFAULT_SCRIPT: &str = r#"// the state of a dead cell
+const DEAD = false;
+
+// the parameters shown in the side panel
+fn params() {
+    [
+        #{ name: "DENSITY", kind: "float", min: 0.0, max: 1.0, value: 0.3 },
+        #{ name: "ALIVE_COLOR", kind: "color", value: [0.1, 1.0, 0.1] },
+    ]
 }
-"#
-        .to_string();
-        let randomize_fn = engine
-            .compile(&randomize_fn_str)
-            .expect("default randomize script should compile successfully");
 
-        // rand module becomes unstable when optimization level == full
-        engine.set_optimization_level(rhai::OptimizationLevel::Full);
+fn count_alive(neighbors) {
+    let alive = 0;
+    for v in neighbors {
+        if v {
+            alive += 1;
+        }
+    }
+    alive
+}
 
-        let update_fn_str = r#"
 fn update(self, neighbors) {
-    let alive = neighbors
-        .reduce(|sum, v| {
-            if v {sum + 1} else {sum}
-        }, 0);
-
+    let alive = count_alive(neighbors);
     if !self {
-        return if alive == 3 {
-            true
-        } else {
-            false
-        };
+        alive == 3
     } else {
-        return if alive == 2 ||
-                  alive == 3 {
-            true
-        } else {
-            false
-        };
+        alive == 2 || alive == 3
     }
-}"#
-        .to_string();
-        let update_fn = engine
-            .compile(&update_fn_str)
-            .expect("default update script should compile successfully");
+}
 
-        let clear_fn_str = r#"
 fn clear() {
-    return false;
-}"#
-        .to_string();
-        let clear_fn = engine
-            .compile(&clear_fn_str)
-            .expect("default clear script should compile successfully");
-
-        let next_fn_str = r#"
+    DEAD
+}
+
+fn randomize() {
+    rand_float() < DENSITY
+}
+
 fn next(self) {
-    return !self;
-}"#
-        .to_string();
-        let next_fn = engine
-            .compile(&next_fn_str)
-            .expect("default next script should compile successfully");
-
-        let color_fn_str = r#"
+    !self
+}
+
 fn color(self) {
-    return if self {
-        [0.1, 1.0, 0.1]
+    if self {
+        ALIVE_COLOR
     } else {
         [0.1, 0.1, 0.1]
-    };
-}"#
-        .to_string();
-        let color_fn = engine
-            .compile(&color_fn_str)
-            .expect("default color script should compile successfully");
+    }
+}
+"#;
+
+// the functions a script should define, and the numbers of parameters
+// they can take. `fn update` can omit the trailing parameters.
+const SCRIPT_FUNCTIONS: [(&str, &str, &[usize]); 5] = [
+    ("update", "fn update(self, neighbors[, offsets[, ctx]])", &[2, 3, 4]),
+    ("clear", "fn clear()", &[0]),
+    ("randomize", "fn randomize()", &[0]),
+    ("next", "fn next(self)", &[1]),
+    ("color", "fn color(self)", &[1]),
+];
+
+/// Compile a script and check that it defines all the functions of a rule.
+/// Returns the AST and the number of parameters of `fn update`.
+///
+/// Other functions and top-level constants can be defined and used from any
+/// of the functions.
+pub fn compile_script(engine: &Engine, script: &str) -> anyhow::Result<(AST, usize)> {
+    let ast = engine.compile(script).context("failed to compile the script")?;
+
+    let mut errors = Vec::new();
+    for (name, signature, arities) in SCRIPT_FUNCTIONS {
+        let found: Vec<usize> = ast
+            .iter_functions()
+            .filter(|f| f.name == name)
+            .map(|f| f.params.len())
+            .collect();
+        if found.is_empty() {
+            errors.push(format!("{} is not defined", signature));
+        } else if !found.iter().any(|n| arities.contains(n)) {
+            let found: Vec<String> = found.iter().map(|n| n.to_string()).collect();
+            errors.push(format!(
+                "{} is defined with {} parameter(s)",
+                signature,
+                found.join(" or ")
+            ));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(DynamicRuleError::MissingFunction(errors.join("\n")).into());
+    }
+
+    let update_arity = ast
+        .iter_functions()
+        .filter(|f| f.name == "update")
+        .map(|f| f.params.len())
+        .filter(|n| SCRIPT_FUNCTIONS[0].2.contains(n))
+        .max()
+        .expect("checked above");
+    Ok((ast, update_arity))
+}
+
+// the value of a parameter and its range
+#[derive(Clone, Debug, PartialEq)]
+enum ParamValue {
+    Int { value: rhai::INT, min: rhai::INT, max: rhai::INT },
+    Float { value: rhai::FLOAT, min: rhai::FLOAT, max: rhai::FLOAT },
+    Bool(bool),
+    Color([rhai::FLOAT; 3]),
+}
+
+impl ParamValue {
+    fn to_dynamic(&self) -> Dynamic {
+        match self {
+            ParamValue::Int { value, .. } => Dynamic::from(*value),
+            ParamValue::Float { value, .. } => Dynamic::from(*value),
+            ParamValue::Bool(value) => Dynamic::from(*value),
+            ParamValue::Color(rgb) => {
+                Dynamic::from_array(rgb.iter().map(|x| Dynamic::from(*x)).collect())
+            }
+        }
+    }
+}
+
+// a parameter declared by `fn params()`, e.g.
+// `#{ name: "RATE", kind: "float", min: 0.0, max: 1.0, value: 0.5 }`.
+#[derive(Clone, Debug)]
+struct ScriptParam {
+    name: String,
+    declared: ParamValue,
+    value: ParamValue, // changed in the side panel
+}
+
+impl ScriptParam {
+    fn parse(decl: &Dynamic) -> Result<Self, String> {
+        let map = decl
+            .read_lock::<rhai::Map>()
+            .ok_or_else(|| format!("{} is not an object map", decl))?;
+        let field =
+            |key: &str| map.get(key).cloned().ok_or_else(|| format!("`{}` is missing", key));
+        let int = |key: &str| {
+            field(key)?
+                .as_int()
+                .map_err(|t| format!("`{}` should be an int, not {}", key, t))
+        };
+        // an int is also accepted as a float
+        let float = |v: &Dynamic| v.as_float().ok().or_else(|| v.as_int().ok().map(|i| i as _));
+        let float_field = |key: &str| {
+            let v = field(key)?;
+            float(&v).ok_or_else(|| format!("`{}` should be a float, not {}", key, v.type_name()))
+        };
+
+        let name = field("name")?.into_string().map_err(|t| format!("`name` is {}", t))?;
+        let mut chars = name.chars();
+        let is_ident = chars.next().map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_ident {
+            return Err(format!("`{}` is not a valid name of a variable", name));
+        }
+
+        let kind = field("kind")?.into_string().map_err(|t| format!("`kind` is {}", t))?;
+        let declared = match kind.as_str() {
+            "int" => {
+                let (min, max) = (int("min")?, int("max")?);
+                ParamValue::Int { value: int("value")?.clamp(min, max.max(min)), min, max }
+            }
+            "float" => {
+                let (min, max) = (float_field("min")?, float_field("max")?);
+                ParamValue::Float {
+                    value: float_field("value")?.clamp(min, max.max(min)),
+                    min,
+                    max,
+                }
+            }
+            "bool" => ParamValue::Bool(
+                field("value")?
+                    .as_bool()
+                    .map_err(|t| format!("`value` should be a bool, not {}", t))?,
+            ),
+            "color" => {
+                let rgb: Option<Vec<rhai::FLOAT>> = field("value")?
+                    .try_cast::<rhai::Array>()
+                    .and_then(|xs| xs.iter().map(float).collect());
+                match rgb.as_deref() {
+                    Some(&[r, g, b]) => ParamValue::Color([r, g, b]),
+                    _ => {
+                        return Err("`value` should be an array of 3 floats `[r, g, b]`".to_string())
+                    }
+                }
+            }
+            _ => {
+                let kinds = r#""int", "float", "bool" or "color""#;
+                return Err(format!("unknown kind `{}`, it should be {}", kind, kinds));
+            }
+        };
+        Ok(Self { name, value: declared.clone(), declared })
+    }
+
+    #[cfg(feature = "gui")]
+    fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let name = self.name.as_str();
+        match &mut self.value {
+            ParamValue::Int { value, min, max } => {
+                ui.add(egui::Slider::new(value, *min..=*max).text(name)).changed()
+            }
+            ParamValue::Float { value, min, max } => {
+                ui.add(egui::Slider::new(value, *min..=*max).text(name)).changed()
+            }
+            ParamValue::Bool(value) => ui.checkbox(value, name).changed(),
+            ParamValue::Color(rgb) => {
+                ui.horizontal(|ui| {
+                    let changed =
+                        egui::widgets::color_picker::color_edit_button_rgb(ui, rgb).changed();
+                    ui.label(name);
+                    changed
+                })
+                .inner
+            }
+        }
+    }
+}
+
+// evaluate `fn params()` if the script defines it
+fn script_params(engine: &Engine, ast: &AST) -> anyhow::Result<Vec<ScriptParam>> {
+    if !ast.iter_functions().any(|f| f.name == "params" && f.params.is_empty()) {
+        return Ok(Vec::new());
+    }
+    // the top-level statements are not evaluated since they may read the parameters
+    let (eval_ast, rewind_scope) = (false, true);
+    let decls = engine
+        .call_fn_raw(&mut Scope::new(), ast, eval_ast, rewind_scope, "params", None, [])
+        .map_err(|x| eval_error(x, "fn params()".to_string()))
+        .context("Failed to evaluate params")?
+        .into_array()
+        .map_err(|t| {
+            DynamicRuleError::InvalidParam(format!("`fn params()` returns {}, not an array", t))
+        })?;
+
+    let mut params: Vec<ScriptParam> = Vec::new();
+    let mut errors = Vec::new();
+    for decl in decls.iter() {
+        match ScriptParam::parse(decl) {
+            Ok(p) if params.iter().any(|q| q.name == p.name) => {
+                errors.push(format!("`{}` is declared twice", p.name))
+            }
+            Ok(p) => params.push(p),
+            Err(e) => errors.push(e),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(DynamicRuleError::InvalidParam(errors.join("\n")).into());
+    }
+    Ok(params)
+}
+
+// evaluate the top-level statements once, with the parameters as constants.
+// the functions are called in the resulting scope to read the constants.
+// helpers do not see the scope of their caller, so the constants are also
+// registered as `global` module, to be read as `global::NAME` in any function.
+fn eval_globals(
+    engine: &mut Engine,
+    ast: &AST,
+    params: &[ScriptParam],
+) -> anyhow::Result<Scope<'static>> {
+    let mut scope = Scope::new();
+    for p in params.iter() {
+        scope.push_constant(p.name.as_str(), p.value.to_dynamic());
+    }
+    engine
+        .run_ast_with_scope(&mut scope, ast)
+        .map_err(|x| eval_error(x, "top-level statements".to_string()))
+        .context("Failed to evaluate the top-level statements")?;
+
+    let mut global = rhai::Module::new();
+    for (name, _, value) in scope.iter() {
+        global.set_var(name, value);
+    }
+    engine.register_static_module("global", global.into());
+    Ok(scope)
+}
+
+impl<N: Neighbors> Default for DynamicRule<N> {
+    fn default() -> Self {
+        let mut engine = Engine::new();
+
+        // we need a random number generator
+        let random = RandomPackage::new();
+        engine.register_global_module(random.as_shared_module());
+        register_context(&mut engine);
+
+        // rand module becomes unstable when optimization level == full
+        engine.set_optimization_level(rhai::OptimizationLevel::Simple);
+        // the depths of release builds. debug builds of rhai have lower ones
+        // that reject the nested maps of `fn params()`
+        engine.set_max_expr_depths(64, 32);
+
+        let limits = ScriptLimits::default();
+        limits.apply(&mut engine);
+        let deadline = Deadline::new();
+        let progress = deadline.clone();
+        engine.on_progress(move |ops| {
+            let over = ops % Deadline::CHECK_INTERVAL == 0 && progress.is_over();
+            over.then(|| Dynamic::UNIT)
+        });
+
+        let (ast, update_arity) = compile_script(&engine, DEFAULT_SCRIPT)
+            .expect("default script should compile successfully");
+        let params =
+            script_params(&engine, &ast).expect("default script should declare valid parameters");
+        let globals = eval_globals(&mut engine, &ast, &params)
+            .expect("top-level statements of default script should not fail");
 
         Self {
-            neighbors: std::marker::PhantomData,
+            neighborhood: N::default(),
             engine,
+            limits,
+            deadline,
+
+            script: DEFAULT_SCRIPT.to_string(),
+            ast,
+            update_arity,
+            params,
+            globals,
+
+            #[cfg(feature = "gui")]
+            script_buf: DEFAULT_SCRIPT.to_string(),
+            #[cfg(feature = "gui")]
+            open_script: true,
+            #[cfg(feature = "gui")]
+            compilation_result: None,
+
+            #[cfg(feature = "gui")]
+            background: egui::Color32::from_rgb(0, 0, 0),
+        }
+    }
+}
 
-            update_fn_str,
-            update_fn,
-            open_update_fn: true,
-            open_update_fn_compilation_result: None,
+impl<N> DynamicRule<N> {
+    /// The script that defines the rule.
+    pub fn script(&self) -> &str {
+        &self.script
+    }
 
-            clear_fn_str,
-            clear_fn,
-            open_clear_fn: true,
-            open_clear_fn_compilation_result: None,
+    /// Replace the script. If it fails to compile, lacks a function or declares
+    /// an invalid parameter, the current script is kept. The parameters that
+    /// are declared in the same way keep their current values.
+    pub fn set_script(&mut self, script: &str) -> anyhow::Result<()> {
+        let (ast, update_arity) = compile_script(&self.engine, script)?;
+        let mut params = script_params(&self.engine, &ast)?;
+        for p in params.iter_mut() {
+            if let Some(old) =
+                self.params.iter().find(|q| q.name == p.name && q.declared == p.declared)
+            {
+                p.value = old.value.clone();
+            }
+        }
+        let globals = eval_globals(&mut self.engine, &ast, &params)?;
+        self.script = script.to_string();
+        self.ast = ast;
+        self.update_arity = update_arity;
+        self.params = params;
+        self.globals = globals;
+        Ok(())
+    }
 
-            randomize_fn_str,
-            randomize_fn,
-            open_randomize_fn: true,
-            open_randomize_fn_compilation_result: None,
+    /// The limits on each call of a function in the script and on each step.
+    pub fn limits(&self) -> ScriptLimits {
+        self.limits
+    }
+    pub fn set_limits(&mut self, limits: ScriptLimits) {
+        limits.apply(&mut self.engine);
+        self.limits = limits;
+    }
 
-            next_fn_str,
-            next_fn,
-            open_next_fn: true,
-            open_next_fn_compilation_result: None,
+    // call a function in the script with the parameters and the top-level
+    // constants.
+    fn call_fn(
+        &self,
+        name: &str,
+        args: impl AsMut<[Dynamic]>,
+    ) -> Result<Dynamic, DynamicRuleError> {
+        self.call_fn_in(&mut self.globals.clone(), name, args)
+    }
 
-            color_fn_str,
-            color_fn,
-            open_color_fn: true,
-            open_color_fn_compilation_result: None,
+    // call a function in a scope cloned from `globals`. the scope is rewound
+    // after the call, so that it can be reused for the next call.
+    fn call_fn_in(
+        &self,
+        scope: &mut Scope<'_>,
+        name: &str,
+        args: impl AsMut<[Dynamic]>,
+    ) -> Result<Dynamic, DynamicRuleError> {
+        // the top-level statements are already evaluated in `globals`
+        let (eval_ast, rewind_scope) = (false, true);
+        self.engine
+            .call_fn_raw(scope, &self.ast, eval_ast, rewind_scope, name, None, args)
+            .map_err(|x| eval_error(x, self.script.clone()))
+    }
 
-            background: egui::Color32::from_rgb(0, 0, 0),
+    // call `fn update` with as many arguments as it takes. `scope` and `args`
+    // are reused between the cells, while the arguments are consumed by the call.
+    // `offsets` is evaluated only if `fn update` takes the offsets.
+    fn call_update(
+        &self,
+        scope: &mut Scope<'_>,
+        args: &mut Vec<Dynamic>,
+        center: Dynamic,
+        states: rhai::Array,
+        offsets: impl FnOnce() -> Dynamic,
+        ctx: &UpdateContext,
+    ) -> anyhow::Result<DynamicState> {
+        args.clear();
+        args.push(center);
+        args.push(Dynamic::from_array(states));
+        if 3 <= self.update_arity {
+            args.push(offsets());
         }
+        if 4 <= self.update_arity {
+            args.push(Dynamic::from(ScriptContext { ctx: *ctx, rng: None }));
+        }
+        let value = self
+            .call_fn_in(scope, "update", args.as_mut_slice())
+            .with_context(|| format!("Failed to evaluate update at ({}, {})", ctx.x, ctx.y))?;
+        Ok(DynamicState { value })
     }
 }
 
+// `[[dx, dy], ...]` passed to `fn update`
+fn offsets_array(offsets: impl Iterator<Item = (isize, isize)>) -> Dynamic {
+    Dynamic::from_array(
+        offsets
+            .map(|(dx, dy)| {
+                Dynamic::from_array(vec![
+                    Dynamic::from(dx as rhai::INT),
+                    Dynamic::from(dy as rhai::INT),
+                ])
+            })
+            .collect(),
+    )
+}
+
 #[derive(Error, Debug)]
 pub enum DynamicRuleError {
     #[error("EvalAltResult \"{0}\"\ncaused by the following code: \n{1}")]
@@ -200,12 +648,19 @@ pub enum DynamicRuleError {
 
     #[error("Dropped File Error: {0} about file \"{1}\"")]
     FileError(String, String),
+
+    #[error("the script lacks a function of the rule:\n{0}")]
+    MissingFunction(String),
+
+    #[error("the script declares an invalid parameter:\n{0}")]
+    InvalidParam(String),
 }
 
 // Box<rhai::EvalAltResult> does not satisfy trait bound of anyhow context
 fn eval_error(item: Box<rhai::EvalAltResult>, code: String) -> DynamicRuleError {
     DynamicRuleError::EvalError(format!("{}", item), code)
 }
+#[cfg(feature = "gui")]
 fn cast_error(item: &str, typename: String, code: String) -> DynamicRuleError {
     DynamicRuleError::CastFail(item.to_string(), typename, code)
 }
@@ -214,138 +669,197 @@ impl<N: Neighbors> Rule for DynamicRule<N> {
     type CellState = DynamicState;
     type Neighborhood = N;
 
+    fn neighborhood(&self) -> N {
+        self.neighborhood.clone()
+    }
+
+    fn default_state(&self) -> anyhow::Result<Self::CellState> {
+        let value = self.call_fn("clear", []).context("Failed to evaluate clear")?;
+        Ok(Self::CellState { value })
+    }
+
+    fn randomize<R: Rng>(&self, _rng: &mut R) -> anyhow::Result<Self::CellState> {
+        let value = self.call_fn("randomize", []).context("Failed to evaluate randomize")?;
+        Ok(Self::CellState { value })
+    }
+
+    fn next(&self, st: Self::CellState) -> anyhow::Result<Self::CellState> {
+        let value = self.call_fn("next", [st.value]).context("Failed to evaluate next")?;
+        Ok(Self::CellState { value })
+    }
+
+    fn update(
+        &self,
+        center: Self::CellState,
+        neighbor: impl Iterator<Item = ((isize, isize), Self::CellState)>,
+        ctx: &UpdateContext,
+    ) -> anyhow::Result<Self::CellState> {
+        // `fn update(self, neighbors, offsets, ctx)` also takes `[dx, dy]` of the
+        // neighbors and the context. the trailing ones can be omitted.
+        let mut states = Vec::new();
+        let mut offsets = Vec::new();
+        for (offset, st) in neighbor {
+            states.push(st.value);
+            offsets.push(offset);
+        }
+        self.call_update(
+            &mut self.globals.clone(),
+            &mut Vec::new(),
+            center.value,
+            states,
+            || offsets_array(offsets.into_iter()),
+            ctx,
+        )
+    }
+
+    /// Evaluate the chunks in parallel. The offsets of the neighbors are
+    /// converted into a rhai array once per step, and the scope of the calls
+    /// is prepared once per chunk, not for each cell. The neighbors are read
+    /// from the chunk with its halo, without resolving the boundary for each
+    /// of them. The step fails if it takes longer than
+    /// `ScriptLimits::max_step_time`.
+    fn update_board<B>(&self, board: &mut B, generation: u64) -> anyhow::Result<()>
+    where
+        B: Board<Self::CellState>,
+    {
+        let fixed = self.boundary_state()?;
+        let neighborhood = self.neighborhood.clone();
+        // neighborhoods depend only on the parity of the row
+        let offsets = [0, 1].map(|y| offsets_array(neighborhood.offsets(y).iter().copied()));
+        let (ox, oy) = world::context_origin(board);
+        let (width, height) = (board.width(), board.height());
+
+        // the neighbors as the differences of the indices in the halo
+        let range = neighborhood.range();
+        let side = (CHUNK_LEN + 2 * range) as isize;
+        let steps = [0, 1].map(|y| {
+            let offsets = neighborhood.offsets(y).iter();
+            offsets.map(|(dx, dy)| dy * side + dx).collect::<Vec<isize>>()
+        });
+
+        self.deadline.start(self.limits.max_step_time);
+        let result = board.fill_buffer_chunks(|board, cx, cy, chunk| {
+            if self.deadline.is_over() {
+                return Err(anyhow::anyhow!("stopped before the chunk ({}, {})", cx, cy));
+            }
+            let halo = world::chunk_with_halo(board, cx, cy, range, &fixed);
+            let mut scope = self.globals.clone();
+            let mut args = Vec::with_capacity(self.update_arity);
+            for j in 0..CHUNK_LEN {
+                for i in 0..CHUNK_LEN {
+                    let (x, y) = ((cx * CHUNK_LEN + i) as isize, (cy * CHUNK_LEN + j) as isize);
+                    let ctx = UpdateContext {
+                        x: x + ox,
+                        y: y + oy,
+                        width,
+                        height,
+                        z: 0,
+                        depth: 1,
+                        generation,
+                    };
+                    let center = ((j + range) as isize * side + (i + range) as isize) as usize;
+                    let steps = &steps[y.rem_euclid(2) as usize];
+                    // the call consumes the array, so it is built for each cell
+                    let mut states = rhai::Array::with_capacity(steps.len());
+                    states.extend(
+                        steps.iter().map(|d| halo[(center as isize + d) as usize].value.clone()),
+                    );
+                    *chunk.cell_at_mut(i, j) = self.call_update(
+                        &mut scope,
+                        &mut args,
+                        halo[center].value.clone(),
+                        states,
+                        || offsets[y.rem_euclid(2) as usize].clone(),
+                        &ctx,
+                    )?;
+                }
+            }
+            Ok(())
+        });
+        let timed_out = self.deadline.is_over();
+        self.deadline.finish();
+        match (result, self.limits.max_step_time) {
+            (Err(e), Some(limit)) if timed_out => {
+                Err(e.context(format!("the step exceeded the time limit of {:?}", limit)))
+            }
+            (result, _) => result,
+        }
+    }
+
+    fn skip_stable_chunks(&self) -> bool {
+        false // a script may use random numbers
+    }
+
+    fn category(&self, st: &Self::CellState) -> String {
+        // the value itself, e.g. `0`, `true` or `[1, 2]`, not `DynamicState { .. }`
+        st.value.to_string()
+    }
+}
+
+// a checkbox to enable a limit and its value
+#[cfg(feature = "gui")]
+fn limit_ui<T: egui::emath::Numeric>(
+    ui: &mut egui::Ui,
+    limit: &mut Option<T>,
+    default: T,
+    name: &str,
+) {
+    ui.horizontal(|ui| {
+        let mut enabled = limit.is_some();
+        let mut value = limit.unwrap_or(default);
+        ui.checkbox(&mut enabled, name);
+        ui.add_enabled(
+            enabled,
+            egui::DragValue::new(&mut value).clamp_range(T::from_f64(1.0)..=T::MAX),
+        );
+        *limit = if enabled { Some(value) } else { None };
+    });
+}
+
+#[cfg(feature = "gui")]
+impl<N: NeighborsUi> RuleUi for DynamicRule<N> {
     fn background(&self) -> egui::Color32 {
         self.background
     }
 
     fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
-        let mut scope = Scope::new();
-        let result = self
-            .engine
-            .call_fn_raw(
-                &mut scope,
-                &self.color_fn,
-                false, // eval AST?
-                false, // rollback scope?
-                "color",
-                None,
-                [st.value.clone()],
-            )
-            .map_err(|x| eval_error(x, self.color_fn_str.clone()))
-            .context("Failed to evaluate color")?;
+        let result =
+            self.call_fn("color", [st.value.clone()]).context("Failed to evaluate color")?;
 
         let rgb = result
             .into_array()
-            .map_err(|x| cast_error(x, "array".to_string(), self.color_fn_str.clone()))
+            .map_err(|x| cast_error(x, "array".to_string(), self.script.clone()))
             .context("Failed to convert `fn color` result into an array")?;
 
         let r = (rgb[0]
             .as_float()
-            .map_err(|x| cast_error(x, "float".to_string(), self.color_fn_str.clone()))
+            .map_err(|x| cast_error(x, "float".to_string(), self.script.clone()))
             .context("Failed to convert `fn color` result element")?
             * 256.0)
             .clamp(0.0, 255.0) as u8;
         let g = (rgb[1]
             .as_float()
-            .map_err(|x| cast_error(x, "float".to_string(), self.color_fn_str.clone()))
+            .map_err(|x| cast_error(x, "float".to_string(), self.script.clone()))
             .context("Failed to convert `fn color` result element")?
             * 256.0)
             .clamp(0.0, 255.0) as u8;
         let b = (rgb[2]
             .as_float()
-            .map_err(|x| cast_error(x, "float".to_string(), self.color_fn_str.clone()))
+            .map_err(|x| cast_error(x, "float".to_string(), self.script.clone()))
             .context("Failed to convert `fn color` result element")?
             * 256.0)
             .clamp(0.0, 255.0) as u8;
         Ok(egui::Color32::from_rgb(r, g, b))
     }
 
-    fn default_state(&self) -> anyhow::Result<Self::CellState> {
-        let mut scope = Scope::new();
-        let value = self
-            .engine
-            .call_fn_raw(
-                &mut scope,
-                &self.clear_fn,
-                false, // eval AST?
-                false, // rollback scope?
-                "clear",
-                None,
-                [],
-            )
-            .map_err(|x| eval_error(x, self.clear_fn_str.clone()))
-            .context("Failed to evaluate clear")?;
-
-        Ok(Self::CellState { value })
-    }
-
-    fn randomize<R: Rng>(&self, _rng: &mut R) -> anyhow::Result<Self::CellState> {
-        let mut scope = Scope::new();
-        let value = self
-            .engine
-            .call_fn_raw(
-                &mut scope,
-                &self.randomize_fn,
-                false, // eval AST?
-                false, // rollback scope?
-                "randomize",
-                None,
-                [],
-            )
-            .map_err(|x| eval_error(x, self.randomize_fn_str.clone()))
-            .context("Failed to evaluate randomize")?;
-
-        Ok(Self::CellState { value })
-    }
-
-    fn next(&self, st: Self::CellState) -> anyhow::Result<Self::CellState> {
-        let mut scope = Scope::new();
-        let value = self
-            .engine
-            .call_fn_raw(
-                &mut scope,
-                &self.next_fn,
-                false, // eval AST?
-                false, // rollback scope?
-                "next",
-                None,
-                [st.value],
-            )
-            .map_err(|x| eval_error(x, self.next_fn_str.clone()))
-            .context("Failed to evaluate next")?;
-
-        Ok(Self::CellState { value })
-    }
-
-    fn update(
-        &self,
-        center: Self::CellState,
-        neighbor: impl Iterator<Item = Self::CellState>,
-    ) -> anyhow::Result<Self::CellState> {
-        let mut scope = Scope::new();
-        let value = self
-            .engine
-            .call_fn_raw(
-                &mut scope,
-                &self.update_fn,
-                false, // eval AST?
-                false, // rollback scope?
-                "update",
-                None,
-                [center.value, Dynamic::from_array(neighbor.map(|x| x.value).collect())],
-            )
-            .map_err(|x| eval_error(x, self.update_fn_str.clone()))
-            .context("Failed to evaluate update")?;
-
-        Ok(Self::CellState { value })
-    }
-
     fn ui(
         &mut self,
         ui: &mut egui::Ui,
         ctx: &egui::Context,
         on_side_panel: bool,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<bool> {
+        let mut changed = false;
+        let mut params_changed = false;
         egui::ScrollArea::vertical().show(ui, |ui| {
             ui.label("Background Color");
             egui::widgets::color_picker::color_edit_button_srgba(
@@ -364,174 +878,323 @@ impl<N: Neighbors> Rule for DynamicRule<N> {
 
             ui.separator();
 
-            Self::ui_code_editor(
-                "toggle cell update rule",
-                "cell update rule takes the central cell and its neighbors and \
-                returns the next state of the central cell.",
-                ui,
-                ctx,
-                &mut self.update_fn_str,
-                &mut self.update_fn,
-                &mut self.open_update_fn,
-                &mut self.open_update_fn_compilation_result,
-                |fn_str| {
-                    self.engine.set_optimization_level(rhai::OptimizationLevel::Full);
-                    self.engine.compile(fn_str).context("failed to compile `fn update()`")
-                },
-            );
-            ui.separator();
+            self.neighborhood.ui(ui);
 
-            Self::ui_code_editor(
-                "toggle clear rule",
-                "clear rule returns the default state to clear the board.",
-                ui,
-                ctx,
-                &mut self.clear_fn_str,
-                &mut self.clear_fn,
-                &mut self.open_clear_fn,
-                &mut self.open_clear_fn_compilation_result,
-                |fn_str| {
-                    self.engine.set_optimization_level(rhai::OptimizationLevel::Full);
-                    self.engine.compile(fn_str).context("failed to compile `fn clear()`")
-                },
-            );
-            ui.separator();
+            if !self.params.is_empty() {
+                ui.label("Parameters");
+                for p in self.params.iter_mut() {
+                    params_changed |= p.ui(ui);
+                }
+                ui.separator();
+            }
 
-            Self::ui_code_editor(
-                "toggle randomize rule",
-                "randomize rule randomizes the cell using rhai-rand module.",
-                ui,
-                ctx,
-                &mut self.randomize_fn_str,
-                &mut self.randomize_fn,
-                &mut self.open_randomize_fn,
-                &mut self.open_randomize_fn_compilation_result,
-                |fn_str| {
-                    // rand module becomes unstable when optimization level == full
-                    self.engine.set_optimization_level(rhai::OptimizationLevel::Simple);
-                    self.engine.compile(fn_str).context("failed to compile `fn randomize()`")
-                },
-            );
+            ui.collapsing("Limits", |ui| {
+                ui.label("A call of a function fails if it exceeds the limits.");
+                let mut limits = self.limits;
+                limit_ui(ui, &mut limits.max_operations, DEFAULT_MAX_OPERATIONS, "operations");
+                limit_ui(ui, &mut limits.max_call_levels, DEFAULT_MAX_CALL_LEVELS, "call depth");
+                limit_ui(ui, &mut limits.max_string_size, DEFAULT_MAX_STRING_SIZE, "string size");
+                ui.label("A step fails if it takes longer than the time limit.");
+                let mut seconds = limits.max_step_time.map(|t| t.as_secs_f32());
+                let default = DEFAULT_MAX_STEP_TIME.as_secs_f32();
+                limit_ui(ui, &mut seconds, default, "seconds per step");
+                if seconds != self.limits.max_step_time.map(|t| t.as_secs_f32()) {
+                    limits.max_step_time = seconds.map(Duration::from_secs_f32);
+                }
+                if limits != self.limits {
+                    self.set_limits(limits);
+                }
+            });
             ui.separator();
 
-            Self::ui_code_editor(
-                "toggle next rule",
-                "next rule is to change the cell state when clicked.",
-                ui,
-                ctx,
-                &mut self.next_fn_str,
-                &mut self.next_fn,
-                &mut self.open_next_fn,
-                &mut self.open_next_fn_compilation_result,
-                |fn_str| {
-                    self.engine.set_optimization_level(rhai::OptimizationLevel::Full);
-                    self.engine.compile(fn_str).context("failed to compile `fn next()`")
-                },
+            ui.label(
+                "A script defines the following functions. Other functions and top-level \
+                constants can be defined and used in any of them. The top-level statements \
+                are evaluated once on compile. Helper functions read the constants as \
+                `global::NAME`.",
             );
-            ui.separator();
-
-            Self::ui_code_editor(
-                "toggle color rule",
-                "color rule defines the color depending on the cell state.\
+            ui.label(
+                "`fn update(self, neighbors)` takes the central cell and its neighbors and \
+                returns the next state of the central cell. \
+                `fn update(self, neighbors, offsets, ctx)` also takes the offsets `[dx, dy]` \
+                of the neighbors in the same order, e.g. `[0, -1]` is the upper cell, and \
+                the context `ctx.x`, `ctx.y`, `ctx.width`, `ctx.height`, `ctx.generation` \
+                and `ctx.rand_float()` that is reproducible.",
+            );
+            ui.label("`fn clear()` returns the default state to clear the board.");
+            ui.label("`fn randomize()` returns a random state using rhai-rand module.");
+            ui.label("`fn next(self)` changes the cell state when clicked.");
+            ui.label(
+                "`fn color(self)` defines the color depending on the cell state. \
                 the resulting value is an array of f32 in [0,1] range, in the order of [r, g, b].",
-                ui,
-                ctx,
-                &mut self.color_fn_str,
-                &mut self.color_fn,
-                &mut self.open_color_fn,
-                &mut self.open_color_fn_compilation_result,
-                |fn_str| {
-                    self.engine.set_optimization_level(rhai::OptimizationLevel::Full);
-                    self.engine.compile(fn_str).context("failed to compile `fn color()`")
-                },
             );
+            ui.label(
+                "`fn params()` is optional. It returns an array of parameters like \
+                `#{ name: \"RATE\", kind: \"float\", min: 0.0, max: 1.0, value: 0.5 }` \
+                that can be changed in the side panel. The kind is \"int\", \"float\", \
+                \"bool\" or \"color\" (`value: [r, g, b]` without `min` and `max`). \
+                The parameters and the top-level constants are constants in the functions \
+                above, and `global::NAME` in any function including the helpers.",
+            );
+
+            if ui.button("toggle script").clicked() {
+                self.open_script = !self.open_script;
+            }
+            if self.open_script {
+                if ui.button("compile").clicked() {
+                    let script = self.script_buf.clone();
+                    match self.set_script(&script) {
+                        Ok(()) => self.compilation_result = None,
+                        Err(e) => self.compilation_result = Some(e),
+                    }
+                }
+                if let Some(err) = &self.compilation_result {
+                    ui.label(format!("{:?}", err));
+                }
+                ui.add(
+                    egui::TextEdit::multiline(&mut self.script_buf)
+                        .code_editor()
+                        .desired_width(f32::INFINITY),
+                );
+            }
         });
 
+        // the top-level statements may depend on the parameters
+        if params_changed {
+            self.globals = eval_globals(&mut self.engine, &self.ast, &self.params)?;
+            changed = true;
+        }
+
         // load file content and compile the code if file is dropped on side panel
         if on_side_panel {
             let dropped_files = ctx.input().raw.dropped_files.clone();
-            if !dropped_files.is_empty() && !dropped_files.iter().any(|f| f.name.ends_with(".rhai"))
+            if !dropped_files.is_empty()
+                && !dropped_files.iter().any(|f| platform::dropped_file_name(f).ends_with(".rhai"))
             {
                 return Err(DynamicRuleError::FileError(
                     "source file should ends with `.rhai`. file ignored".to_string(),
-                    dropped_files[0].name.clone(),
+                    platform::dropped_file_name(&dropped_files[0]),
                 )
                 .into());
             }
 
-            if let Some(file) = dropped_files.into_iter().find(|f| f.name.ends_with(".rhai")) {
-                if let Some(bytes) = &file.bytes {
-                    let content = std::str::from_utf8(bytes)
-                        .context(format!("Couldn't read file content as utf8 -> {}", file.name))?
-                        .to_owned();
-
-                    self.update_fn_str = content.clone();
-                    self.clear_fn_str = content.clone();
-                    self.randomize_fn_str = content.clone();
-                    self.next_fn_str = content.clone();
-                    self.color_fn_str = content.clone();
-
-                    self.engine.set_optimization_level(rhai::OptimizationLevel::Simple);
-                    self.randomize_fn = self
-                        .engine
-                        .compile(&content)
-                        .context(format!("failed to compile file content -> {}", file.name))?;
-
-                    self.engine.set_optimization_level(rhai::OptimizationLevel::Full);
-                    let ast = self
-                        .engine
-                        .compile(content)
-                        .context(format!("failed to compile file content -> {}", file.name))?;
-
-                    self.update_fn = ast.clone();
-                    self.clear_fn = ast.clone();
-                    self.next_fn = ast.clone();
-                    self.color_fn = ast;
-                } else {
-                    return Err(DynamicRuleError::FileError(
+            if let Some(file) =
+                dropped_files.iter().find(|f| platform::dropped_file_name(f).ends_with(".rhai"))
+            {
+                let name = platform::dropped_file_name(file);
+                let bytes = platform::dropped_file_content(file).with_context(|| {
+                    DynamicRuleError::FileError(
                         "couldn't read file content".to_string(),
-                        file.name.clone(),
+                        name.clone(),
                     )
-                    .into());
-                }
+                })?;
+                let content = std::str::from_utf8(&bytes)
+                    .context(format!("Couldn't read file content as utf8 -> {}", name))?
+                    .to_owned();
+
+                // the dropped script is shown even if it fails to compile
+                self.script_buf = content.clone();
+                self.compilation_result = None;
+                self.set_script(&content)
+                    .context(format!("failed to compile file content -> {}", name))?;
             }
         }
-        Ok(())
+        // this rule never skips stable chunks, but the colors may have changed
+        Ok(changed)
     }
 }
 
-impl<N> DynamicRule<N> {
-    #[allow(clippy::too_many_arguments)]
-    fn ui_code_editor(
-        button_name: &str,
-        description: &str,
-        ui: &mut egui::Ui,
-        _ctx: &egui::Context,
-        fn_str: &mut String,
-        fn_ast: &mut AST,
-        open_fn: &mut bool,
-        result: &mut Option<anyhow::Error>,
-        compile: impl FnOnce(&String) -> anyhow::Result<AST>,
-    ) {
-        ui.label(description);
-        if ui.button(button_name).clicked() {
-            *open_fn = !*open_fn;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::MooreNeighborhood;
+
+    #[test]
+    fn default_script_declares_params() {
+        let rule = DynamicRule::<MooreNeighborhood>::default();
+        let names: Vec<&str> = rule.params.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["DENSITY", "ALIVE_COLOR"]);
+        assert_eq!(rule.default_state().unwrap().value.as_bool(), Ok(false));
+    }
+
+    fn rule_with(script: &str) -> DynamicRule<MooreNeighborhood> {
+        let mut rule = DynamicRule::default();
+        rule.set_script(script).unwrap();
+        rule
+    }
+
+    const SCRIPT: &str = r#"
+        let n = 0;
+        for i in 0..1000 { n += 1; }
+        const DEAD = n - 1000;
+        fn dead(dead) { dead }
+        fn update(self, neighbors) { neighbors.len() }
+        fn clear() { DEAD }
+        fn randomize() { dead(DEAD) }
+        fn next(self) { self }
+        fn color(self) { [0.0, 0.0, 0.0] }
+    "#;
+
+    #[test]
+    fn top_level_is_evaluated_once() {
+        let mut rule = rule_with(SCRIPT);
+        // the loop at the top level alone exceeds it
+        rule.set_limits(ScriptLimits { max_operations: Some(100), ..ScriptLimits::default() });
+        assert_eq!(rule.default_state().unwrap().value.as_int(), Ok(0));
+        assert_eq!(rule.randomize(&mut rand::thread_rng()).unwrap().value.as_int(), Ok(0));
+    }
+
+    #[test]
+    fn top_level_reads_params() {
+        let script = r#"
+            fn params() { [#{ name: "N", kind: "int", min: 0, max: 9, value: 4 }] }
+            const TWICE = N * 2;
+            fn update(self, neighbors) { self }
+            fn clear() { TWICE }
+            fn randomize() { 0 }
+            fn next(self) { self }
+            fn color(self) { [0.0, 0.0, 0.0] }
+        "#;
+        let mut rule = rule_with(script);
+        assert_eq!(rule.default_state().unwrap().value.as_int(), Ok(8));
+        // the value set in the side panel is kept on recompile
+        rule.params[0].value = ParamValue::Int { value: 5, min: 0, max: 9 };
+        rule.set_script(script).unwrap();
+        assert_eq!(rule.default_state().unwrap().value.as_int(), Ok(10));
+    }
+
+    #[test]
+    fn helpers_read_globals() {
+        let script = r#"
+            fn params() { [#{ name: "N", kind: "int", min: 0, max: 9, value: 4 }] }
+            const BASE = 100;
+            fn offset() { global::BASE + global::N }
+            fn update(self, neighbors) { self }
+            fn clear() { offset() }
+            fn randomize() { 0 }
+            fn next(self) { self }
+            fn color(self) { [0.0, 0.0, 0.0] }
+        "#;
+        let mut rule = rule_with(script);
+        assert_eq!(rule.default_state().unwrap().value.as_int(), Ok(104));
+        rule.params[0].value = ParamValue::Int { value: 5, min: 0, max: 9 };
+        rule.set_script(script).unwrap();
+        assert_eq!(rule.default_state().unwrap().value.as_int(), Ok(105));
+
+        // the constants of the new script replace the old ones
+        rule.set_script(&script.replace("const BASE = 100;", "const BASE = 200;"))
+            .unwrap();
+        assert_eq!(rule.default_state().unwrap().value.as_int(), Ok(205));
+    }
+
+    #[test]
+    fn step_stops_at_time_limit() {
+        use crate::board::SquareGrid;
+
+        let mut rule = rule_with(&DEFAULT_SCRIPT.replace(
+            "fn update(self, neighbors) {",
+            "fn update(self, neighbors) {
+                loop {}",
+        ));
+        rule.set_limits(ScriptLimits {
+            max_operations: None,
+            max_step_time: Some(Duration::from_millis(50)),
+            ..ScriptLimits::default()
+        });
+        let mut board = SquareGrid::init(2, 2, DynamicState::default());
+        board.begin_update(false);
+        let err = rule.update_board(&mut board, 0).unwrap_err();
+        assert!(format!("{:?}", err).contains("time limit"), "{:?}", err);
+        // the deadline applies only to the step
+        assert!(rule.default_state().is_ok());
+    }
 
-        if *open_fn {
-            if ui.button("compile").clicked() {
-                let ast = compile(fn_str);
-                if let Ok(compiled) = ast {
-                    *fn_ast = compiled;
-                    *result = None;
-                } else {
-                    *result = ast.err();
+    #[test]
+    fn update_board_same_as_update_serial() {
+        use crate::board::{Boundary, SquareGrid};
+        use rand::SeedableRng;
+
+        let rule = rule_with(&DEFAULT_SCRIPT.replace(
+            "fn update(self, neighbors) {",
+            "fn update(self, neighbors, offsets, ctx) {
+                if offsets[0] != [-1, -1] || ctx.rand_float() < 0.05 { return !self; }
+                // depends on the order of the neighbors
+                if neighbors[1] != neighbors[6] { return neighbors[3]; }",
+        ));
+        // the halo of a chunk is read across the boundaries
+        let fixed = rule.default_state().unwrap();
+        for boundary in [Boundary::Periodic, Boundary::Fixed, Boundary::Reflective] {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+            let mut boards = [
+                SquareGrid::init(3, 2, DynamicState::default()),
+                SquareGrid::init(3, 2, DynamicState::default()),
+            ];
+            for board in boards.iter_mut() {
+                board.set_boundary(boundary, fixed.clone());
+            }
+            for y in 0..boards[0].height() {
+                for x in 0..boards[0].width() {
+                    let value = Dynamic::from(rng.gen_bool(0.3));
+                    for board in boards.iter_mut() {
+                        *board.cell_at_mut(x, y) = DynamicState { value: value.clone() };
+                    }
+                }
+            }
+            for generation in 0..4 {
+                let [a, b] = &mut boards;
+                a.begin_update(false);
+                rule.update_board(a, generation).unwrap();
+                a.swap_buffer();
+                b.begin_update(false);
+                world::update_serial(&rule, b, generation).unwrap();
+                b.swap_buffer();
+                for y in 0..a.height() {
+                    for x in 0..a.width() {
+                        assert_eq!(
+                            a.cell_at(x, y),
+                            b.cell_at(x, y),
+                            "({}, {}) in {} under {:?}",
+                            x,
+                            y,
+                            generation,
+                            boundary
+                        );
+                    }
                 }
             }
-            if let Some(err) = result {
-                ui.label(format!("{:?}", err));
+        }
+    }
+
+    // a rough measure of `update_board` on a 64x64-chunk board:
+    // `cargo test --release bench_update_board -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_update_board() {
+        use crate::board::SquareGrid;
+        use rand::SeedableRng;
+
+        let plain = rule_with(DEFAULT_SCRIPT);
+        let with_ctx = rule_with(&DEFAULT_SCRIPT.replace(
+            "fn update(self, neighbors) {",
+            "fn update(self, neighbors, offsets, ctx) {
+                if ctx.x == 0 && offsets[0] == [0, 0] { return self; }",
+        ));
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let mut board = SquareGrid::init(64, 64, DynamicState::default());
+        for y in 0..board.height() {
+            for x in 0..board.width() {
+                *board.cell_at_mut(x, y) = DynamicState { value: Dynamic::from(rng.gen_bool(0.3)) };
+            }
+        }
+        for (name, mut rule) in [("update(self, neighbors)", plain), ("with ctx", with_ctx)] {
+            rule.set_limits(ScriptLimits { max_step_time: None, ..ScriptLimits::default() });
+            let start = instant::Instant::now();
+            for generation in 0..3 {
+                board.begin_update(false);
+                rule.update_board(&mut board, generation).unwrap();
+                board.swap_buffer();
             }
-            ui.add(egui::TextEdit::multiline(fn_str).code_editor().desired_width(f32::INFINITY));
+            println!("{}: {:?} per step", name, start.elapsed() / 3);
         }
     }
 }
@@ -0,0 +1,194 @@
+//! A ring buffer of previous generations to step back a simulation.
+//!
+//! Each generation holds all the chunks of the board, but a chunk that is not
+//! changed from the previous generation shares the same allocation. Since most
+//! of the chunks are stable in a typical run, it stays cheap even for deep buffers.
+//! The board tells which chunks are changed since the last step, so the chunks
+//! are not compared unless the board does not know it.
+//!
+//! As in `history`, chunks are located by `offset`, the number of chunks
+//! prepended to the board so far.
+
+use crate::board::{Board, Chunk};
+use crate::rule::State;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+struct Generation<T: State> {
+    generation: u64,
+    offset: (isize, isize),
+    n_chunks_x: usize,
+    n_chunks_y: usize,
+    chunks: Vec<Rc<Chunk<T>>>,
+}
+
+impl<T: State> Generation<T> {
+    // `pos` is a chunk coordinate relative to the original top-left chunk.
+    fn get(&self, (x, y): (isize, isize)) -> Option<&Rc<Chunk<T>>> {
+        let x = x + self.offset.0;
+        let y = y + self.offset.1;
+        if x < 0 || y < 0 || self.n_chunks_x as isize <= x || self.n_chunks_y as isize <= y {
+            None
+        } else {
+            Some(&self.chunks[y as usize * self.n_chunks_x + x as usize])
+        }
+    }
+}
+
+pub(crate) struct Generations<T: State> {
+    buffer: VecDeque<Generation<T>>,
+    depth: usize,
+    // whether the last generation was pushed right before the last step, so
+    // that the changes of the board are counted from it
+    follows_last: bool,
+}
+
+impl<T: State> Default for Generations<T> {
+    fn default() -> Self {
+        Self { buffer: VecDeque::new(), depth: 64, follows_last: false }
+    }
+}
+
+impl<T: State> Generations<T> {
+    pub(crate) fn len(&self) -> usize {
+        self.buffer.len()
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+    pub(crate) fn clear(&mut self) {
+        self.buffer.clear();
+        self.follows_last = false;
+    }
+
+    pub(crate) fn depth(&self) -> usize {
+        self.depth
+    }
+    /// Change the number of generations to be kept. The oldest ones are dropped.
+    pub(crate) fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.depth < self.buffer.len() {
+            self.buffer.pop_front();
+        }
+    }
+
+    /// Store the current board as the latest generation. It should be called
+    /// right before each step.
+    pub(crate) fn push<B: Board<T>>(&mut self, board: &B, offset: (isize, isize), generation: u64) {
+        if self.depth == 0 {
+            return;
+        }
+        let n_chunks_x = board.n_chunks_x();
+        let n_chunks_y = board.n_chunks_y();
+        let mut chunks = Vec::with_capacity(n_chunks_x * n_chunks_y);
+        for y in 0..n_chunks_y {
+            for x in 0..n_chunks_x {
+                let curr = board.chunk_at(x, y);
+                let pos = (x as isize - offset.0, y as isize - offset.1);
+
+                // share the chunk with the last generation if not changed
+                let shared = self.buffer.back().and_then(|last| last.get(pos));
+                let changed = board.is_changed_chunk(x, y).filter(|_| self.follows_last);
+                match (shared, changed) {
+                    (Some(prev), Some(false)) => chunks.push(Rc::clone(prev)),
+                    (Some(prev), None) if **prev == *curr => chunks.push(Rc::clone(prev)),
+                    _ => chunks.push(Rc::new(curr.clone())),
+                }
+            }
+        }
+        self.buffer
+            .push_back(Generation { generation, offset, n_chunks_x, n_chunks_y, chunks });
+        while self.depth < self.buffer.len() {
+            self.buffer.pop_front();
+        }
+        self.follows_last = true;
+    }
+
+    /// A chunk of the latest generation, at `pos` relative to the original
+    /// top-left chunk. None if it was not in the board or nothing is stored.
+    pub(crate) fn latest_chunk(&self, pos: (isize, isize)) -> Option<&Chunk<T>> {
+        self.buffer.back()?.get(pos).map(|chunk| &**chunk)
+    }
+
+    /// Restore the latest generation and remove it from the buffer.
+    /// Chunks added after the generation was stored are filled by `default`.
+    /// Returns the generation number restored, or None if the buffer is empty.
+    pub(crate) fn pop<B: Board<T>>(
+        &mut self,
+        board: &mut B,
+        offset: (isize, isize),
+        default: &T,
+    ) -> Option<u64> {
+        let gen = self.buffer.pop_back()?;
+        self.follows_last = false;
+        for y in 0..board.n_chunks_y() {
+            for x in 0..board.n_chunks_x() {
+                let pos = (x as isize - offset.0, y as isize - offset.1);
+                let chunk = board.chunk_at_mut(x, y);
+                match gen.get(pos) {
+                    Some(prev) if **prev == *chunk => {}
+                    Some(prev) => *chunk = Chunk::clone(prev),
+                    None => *chunk = Chunk::init(default.clone()),
+                }
+            }
+        }
+        Some(gen.generation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Boundary;
+    use crate::lifegame::{LifeGameRule, LifeGameState};
+    use crate::test_util::{alive, offset, Life};
+    use crate::world::World;
+
+    #[test]
+    fn step_back_with_edits_between_steps() {
+        for boundary in [Boundary::Periodic, Boundary::Fixed, Boundary::Infinite] {
+            let mut world = Life::new(LifeGameRule::default(), 3, 3, 1);
+            world.set_boundary(boundary).unwrap();
+            for (x, y) in [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)] {
+                *world.board_mut().cell_at_mut(x + 20, y + 20) = LifeGameState::Alive;
+            }
+            let mut generations = Generations::default();
+            let mut snapshots = Vec::new();
+            for i in 0..30 {
+                if i % 7 == 3 {
+                    // an edit in a chunk far from the glider
+                    *world.board_mut().cell_at_mut(5, 40) = LifeGameState::Alive;
+                }
+                snapshots.push(alive(&world));
+                generations.push(world.board(), offset(&world), world.generation());
+                world.update().unwrap();
+            }
+            while let Some(expected) = snapshots.pop() {
+                let offset = offset(&world);
+                let generation = generations.pop(world.board_mut(), offset, &LifeGameState::Dead);
+                assert_eq!(generation, Some(snapshots.len() as u64));
+                world.set_generation(snapshots.len() as u64);
+                assert_eq!(alive(&world), expected, "{:?}", boundary);
+            }
+            assert!(generations.is_empty());
+        }
+    }
+
+    #[test]
+    fn stable_chunks_are_shared() {
+        let mut world = Life::new(LifeGameRule::default(), 2, 2, 1);
+        // a block never changes
+        for (x, y) in [(3, 3), (4, 3), (3, 4), (4, 4)] {
+            *world.board_mut().cell_at_mut(x, y) = LifeGameState::Alive;
+        }
+        let mut generations = Generations::default();
+        for _ in 0..3 {
+            generations.push(world.board(), offset(&world), world.generation());
+            world.update().unwrap();
+        }
+        let (prev, last) = (&generations.buffer[1], &generations.buffer[2]);
+        for (a, b) in prev.chunks.iter().zip(last.chunks.iter()) {
+            assert!(Rc::ptr_eq(a, b));
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use crate::rule::{Rule, State, VonNeumannNeighborhood};
+use crate::board::{Board, PlainCells};
+use crate::rule::{Rule, State, UpdateContext, VonNeumannNeighborhood};
+#[cfg(feature = "gui")]
+use crate::rule::{RuleUi, StateUi};
+use crate::world;
 use rand::distributions::{Distribution, Uniform};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
@@ -16,6 +20,11 @@ impl std::default::Default for GrayScottState {
 }
 
 impl State for GrayScottState {
+    type Cells = PlainCells<Self>;
+}
+
+#[cfg(feature = "gui")]
+impl StateUi for GrayScottState {
     fn inspect(&mut self, ui: &mut egui::Ui, _buf: &mut String) {
         ui.add(egui::Slider::new(&mut self.u, 0.0..=1.0).text("u"));
         ui.add(egui::Slider::new(&mut self.v, 0.0..=1.0).text("v"));
@@ -32,6 +41,7 @@ impl State for GrayScottState {
 ///
 pub struct GrayScottRule {
     dt: f32,
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
     dx: f32,
     invdx2: f32,
     d_u: f32, // D_u
@@ -40,8 +50,11 @@ pub struct GrayScottRule {
     k: f32,
     n: u32,
 
+    #[cfg(feature = "gui")]
     u_color: egui::Color32,
+    #[cfg(feature = "gui")]
     v_color: egui::Color32,
+    #[cfg(feature = "gui")]
     background: egui::Color32,
 }
 
@@ -56,32 +69,45 @@ impl std::default::Default for GrayScottRule {
             f: 0.09,
             k: 0.06,
             n: 40,
+            #[cfg(feature = "gui")]
             u_color: egui::Color32::from_rgb(16, 0, 255),
+            #[cfg(feature = "gui")]
             v_color: egui::Color32::from_rgb(16, 255, 0),
+            #[cfg(feature = "gui")]
             background: egui::Color32::from_rgb(0, 0, 0),
         }
     }
 }
 
+impl GrayScottRule {
+    /// Construct a rule from comma-separated parameters, e.g. `f=0.04,k=0.06`.
+    ///
+    /// Available parameters are `Du`, `Dv`, `f`, `k` and `n`.
+    /// Parameters not specified take the default values.
+    pub fn from_params(params: &str) -> anyhow::Result<Self> {
+        let mut rule = Self::default();
+        for param in params.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let (key, value) = param
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("parameter should be `key=value`: {}", param))?;
+            let value = value.trim();
+            match key.trim() {
+                "Du" => rule.d_u = value.parse()?,
+                "Dv" => rule.d_v = value.parse()?,
+                "f" => rule.f = value.parse()?,
+                "k" => rule.k = value.parse()?,
+                "n" => rule.n = value.parse()?,
+                _ => return Err(anyhow::anyhow!("unknown Gray-Scott parameter: {}", key)),
+            }
+        }
+        Ok(rule)
+    }
+}
+
 impl Rule for GrayScottRule {
     type CellState = GrayScottState;
     type Neighborhood = VonNeumannNeighborhood;
 
-    fn background(&self) -> egui::Color32 {
-        self.background
-    }
-
-    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
-        let (u_r, u_g, u_b) = (self.u_color.r(), self.u_color.g(), self.u_color.b());
-        let (v_r, v_g, v_b) = (self.v_color.r(), self.v_color.g(), self.v_color.b());
-
-        let r = (st.u * u_r as f32 + st.v * v_r as f32).clamp(0.0, 255.0) as u8;
-        let g = (st.u * u_g as f32 + st.v * v_g as f32).clamp(0.0, 255.0) as u8;
-        let b = (st.u * u_b as f32 + st.v * v_b as f32).clamp(0.0, 255.0) as u8;
-
-        Ok(egui::Color32::from_rgb(r, g, b))
-    }
-
     fn default_state(&self) -> anyhow::Result<Self::CellState> {
         Ok(GrayScottState { u: 0.0, v: 0.0 })
     }
@@ -98,11 +124,13 @@ impl Rule for GrayScottRule {
     fn update(
         &self,
         center: Self::CellState,
-        neighbor: impl Iterator<Item = Self::CellState>,
+        neighbor: impl Iterator<Item = ((isize, isize), Self::CellState)>,
+        _ctx: &UpdateContext,
     ) -> anyhow::Result<Self::CellState> {
         let u0 = center.u;
         let v0 = center.v;
-        let (lu, lv) = neighbor.fold((-4.0 * u0, -4.0 * v0), |acc, c| (acc.0 + c.u, acc.1 + c.v));
+        let (lu, lv) =
+            neighbor.fold((-4.0 * u0, -4.0 * v0), |acc, (_, c)| (acc.0 + c.u, acc.1 + c.v));
 
         let Self { dt, invdx2, d_u, d_v, f, k, .. } = *self;
 
@@ -112,16 +140,51 @@ impl Rule for GrayScottRule {
         Ok(Self::CellState { u, v })
     }
 
+    fn update_board<B>(&self, board: &mut B, generation: u64) -> anyhow::Result<()>
+    where
+        B: Board<Self::CellState>,
+    {
+        world::update_parallel(self, board, generation)
+    }
+
+    fn skip_stable_chunks(&self) -> bool {
+        false // diffusion changes almost all the cells in every step
+    }
+
     fn iteration_per_step(&self) -> u32 {
         self.n
     }
 
+    fn category(&self, st: &Self::CellState) -> String {
+        // the concentration of v in 4 bins
+        let bin = ((st.v * 4.0).floor() as i32).clamp(0, 3) as f32;
+        format!("v in [{:.2}, {:.2})", bin * 0.25, (bin + 1.0) * 0.25)
+    }
+}
+
+#[cfg(feature = "gui")]
+impl RuleUi for GrayScottRule {
+    fn background(&self) -> egui::Color32 {
+        self.background
+    }
+
+    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
+        let (u_r, u_g, u_b) = (self.u_color.r(), self.u_color.g(), self.u_color.b());
+        let (v_r, v_g, v_b) = (self.v_color.r(), self.v_color.g(), self.v_color.b());
+
+        let r = (st.u * u_r as f32 + st.v * v_r as f32).clamp(0.0, 255.0) as u8;
+        let g = (st.u * u_g as f32 + st.v * v_g as f32).clamp(0.0, 255.0) as u8;
+        let b = (st.u * u_b as f32 + st.v * v_b as f32).clamp(0.0, 255.0) as u8;
+
+        Ok(egui::Color32::from_rgb(r, g, b))
+    }
+
     fn ui(
         &mut self,
         ui: &mut egui::Ui,
         _ctx: &egui::Context,
         _on_side_panel: bool,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<bool> {
         ui.hyperlink_to(
             "Reaction-Diffusion system - Wikipedia",
             "https://en.wikipedia.org/wiki/Reaction%E2%80%93diffusion_system",
@@ -131,11 +194,12 @@ impl Rule for GrayScottRule {
         ui.label(format!("dt = {}", self.dt));
         ui.label(format!("dx = {}", self.dx));
 
-        ui.add(egui::Slider::new(&mut self.d_u, 0.0..=0.01).text("Du"));
-        ui.add(egui::Slider::new(&mut self.d_v, 0.0..=0.01).text("Dv"));
+        let mut changed = false;
+        changed |= ui.add(egui::Slider::new(&mut self.d_u, 0.0..=0.01).text("Du")).changed();
+        changed |= ui.add(egui::Slider::new(&mut self.d_v, 0.0..=0.01).text("Dv")).changed();
 
-        ui.add(egui::Slider::new(&mut self.f, 0.0..=0.1).text("f"));
-        ui.add(egui::Slider::new(&mut self.k, 0.0..=0.1).text("k"));
+        changed |= ui.add(egui::Slider::new(&mut self.f, 0.0..=0.1).text("f")).changed();
+        changed |= ui.add(egui::Slider::new(&mut self.k, 0.0..=0.1).text("k")).changed();
 
         ui.add(
             egui::Slider::new(&mut self.n, 0..=100).text("how many time integrations per frame"),
@@ -165,6 +229,6 @@ impl Rule for GrayScottRule {
             &mut self.v_color,
             egui::widgets::color_picker::Alpha::Opaque,
         );
-        Ok(())
+        Ok(changed)
     }
 }
@@ -0,0 +1,719 @@
+//! HashLife, a quadtree algorithm to run life-like rules for a huge number of generations.
+//!
+//! A pattern is stored as a quadtree whose identical subtrees are shared, and
+//! the result of advancing each subtree is memoized. A node of level `n`
+//! covers `2^n x 2^n` cells and its result is the center `2^(n-1) x 2^(n-1)`
+//! cells after `2^(n-2)` generations. Since regular patterns reuse most of the
+//! results, it can jump `2^k` generations at once.
+//!
+//! Unlike `World2D`, the plane is infinite, not periodic. The board of
+//! `HashLifeWorld` is a window onto the plane. Cells that leave the board are
+//! still simulated, but they are not saved when the world is serialized.
+
+use crate::board::{Board, Boundary, Chunk, Grid, SquareGrid};
+use crate::lifegame::LifeGameState;
+#[cfg(feature = "gui")]
+use crate::rule::RuleUi;
+use crate::rule::{MooreNeighborhood, Rule};
+use crate::world::World;
+
+use anyhow::anyhow;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Two-state outer-totalistic rules on the Moore neighborhood, which HashLife can run.
+pub trait LifeRule: Rule<CellState = LifeGameState, Neighborhood = MooreNeighborhood> {
+    /// The numbers of live neighbors to survive and to be born, as bit masks.
+    /// `1 << n` is set if a cell with `n` live neighbors survives (or is born).
+    fn survive_birth_masks(&self) -> (u16, u16);
+}
+
+type NodeId = u32;
+
+const DEAD: NodeId = 0;
+const ALIVE: NodeId = 1;
+
+// the arena is compacted when it exceeds this
+const MAX_NODES: usize = 1 << 22;
+
+/// The largest `k` of a step of `2^k` generations.
+pub const MAX_STEP_LOG2: u32 = 48;
+
+#[derive(Clone, Copy)]
+struct Node {
+    children: [NodeId; 4], // nw, ne, sw, se. unused in leaves
+    level: u32,
+    population: u64,
+}
+
+/// A pattern on an infinite plane and the memoized results.
+///
+/// The root node of level `n` covers `[-2^(n-1), 2^(n-1))` in both x and y.
+pub struct HashLife {
+    survive: u16,
+    birth: u16,
+    nodes: Vec<Node>,
+    table: HashMap<[NodeId; 4], NodeId>,
+    results: HashMap<(NodeId, u32), NodeId>,
+    empty: Vec<NodeId>, // empty node of each level
+    root: NodeId,
+}
+
+impl Default for HashLife {
+    fn default() -> Self {
+        // B3/S23
+        Self::new(0b1100, 0b1000)
+    }
+}
+
+impl HashLife {
+    pub fn new(survive: u16, birth: u16) -> Self {
+        let mut hl = Self {
+            survive,
+            birth,
+            nodes: Vec::new(),
+            table: HashMap::new(),
+            results: HashMap::new(),
+            empty: Vec::new(),
+            root: DEAD,
+        };
+        hl.clear();
+        hl
+    }
+
+    /// Remove all the cells and the memoized results.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.table.clear();
+        self.results.clear();
+        self.empty.clear();
+        self.nodes.push(Node { children: [DEAD; 4], level: 0, population: 0 });
+        self.nodes.push(Node { children: [DEAD; 4], level: 0, population: 1 });
+        self.root = self.empty(3);
+    }
+
+    /// Change the rule. The memoized results are discarded if it differs.
+    pub fn set_rule(&mut self, survive: u16, birth: u16) {
+        if (self.survive, self.birth) != (survive, birth) {
+            self.survive = survive;
+            self.birth = birth;
+            self.results.clear();
+        }
+    }
+
+    pub fn population(&self) -> u64 {
+        self.nodes[self.root as usize].population
+    }
+    /// The number of nodes in memory, including the ones no longer used.
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn level(&self, id: NodeId) -> u32 {
+        self.nodes[id as usize].level
+    }
+    fn children(&self, id: NodeId) -> [NodeId; 4] {
+        self.nodes[id as usize].children
+    }
+
+    fn join(&mut self, children: [NodeId; 4]) -> NodeId {
+        if let Some(id) = self.table.get(&children) {
+            return *id;
+        }
+        let level = self.level(children[0]) + 1;
+        let population = children.iter().map(|c| self.nodes[*c as usize].population).sum();
+        let id = self.nodes.len() as NodeId;
+        self.nodes.push(Node { children, level, population });
+        self.table.insert(children, id);
+        id
+    }
+
+    fn empty(&mut self, level: u32) -> NodeId {
+        if self.empty.is_empty() {
+            self.empty.push(DEAD);
+        }
+        while self.empty.len() <= level as usize {
+            let e = *self.empty.last().expect("level 0 is already pushed");
+            let next = self.join([e; 4]);
+            self.empty.push(next);
+        }
+        self.empty[level as usize]
+    }
+
+    // the center half of a node
+    fn center(&mut self, id: NodeId) -> NodeId {
+        let [nw, ne, sw, se] = self.children(id);
+        self.join([
+            self.children(nw)[3],
+            self.children(ne)[2],
+            self.children(sw)[1],
+            self.children(se)[0],
+        ])
+    }
+    // the center half of two nodes side by side
+    fn center_h(&mut self, w: NodeId, e: NodeId) -> NodeId {
+        let [_, w_ne, _, w_se] = self.children(w);
+        let [e_nw, _, e_sw, _] = self.children(e);
+        self.join([w_ne, e_nw, w_se, e_sw])
+    }
+    // the center half of two nodes stacked vertically
+    fn center_v(&mut self, n: NodeId, s: NodeId) -> NodeId {
+        let [_, _, n_sw, n_se] = self.children(n);
+        let [s_nw, s_ne, _, _] = self.children(s);
+        self.join([n_sw, n_se, s_nw, s_ne])
+    }
+
+    // surround the root by empty cells, doubling its width.
+    fn expand(&mut self) {
+        let level = self.level(self.root);
+        let e = self.empty(level - 1);
+        let [nw, ne, sw, se] = self.children(self.root);
+        let nw = self.join([e, e, e, nw]);
+        let ne = self.join([e, e, ne, e]);
+        let sw = self.join([e, sw, e, e]);
+        let se = self.join([se, e, e, e]);
+        self.root = self.join([nw, ne, sw, se]);
+    }
+
+    // the center 2x2 cells of a 4x4 node after 1 generation.
+    fn step_level2(&mut self, id: NodeId) -> NodeId {
+        let mut cells = [[false; 4]; 4];
+        for (i, child) in self.children(id).into_iter().enumerate() {
+            for (j, leaf) in self.children(child).into_iter().enumerate() {
+                let x = (i & 1) * 2 + (j & 1);
+                let y = (i >> 1) * 2 + (j >> 1);
+                cells[y][x] = leaf == ALIVE;
+            }
+        }
+        let mut next = [DEAD; 4];
+        for (k, n) in next.iter_mut().enumerate() {
+            let x = 1 + (k & 1);
+            let y = 1 + (k >> 1);
+            let around = cells[y - 1..=y + 1].iter().flat_map(|row| &row[x - 1..=x + 1]);
+            let alive = around.filter(|c| **c).count() - cells[y][x] as usize;
+            let mask = if cells[y][x] { self.survive } else { self.birth };
+            if mask & (1 << alive) != 0 {
+                *n = ALIVE;
+            }
+        }
+        self.join(next)
+    }
+
+    // the center half of a node after `2^min(k, level-2)` generations.
+    fn step(&mut self, id: NodeId, k: u32) -> NodeId {
+        let level = self.level(id);
+        let k = k.min(level - 2);
+        if self.nodes[id as usize].population == 0 {
+            return self.empty(level - 1);
+        }
+        if let Some(result) = self.results.get(&(id, k)) {
+            return *result;
+        }
+
+        let result = if level == 2 {
+            self.step_level2(id)
+        } else {
+            // 9 overlapping nodes of level-1
+            let [nw, ne, sw, se] = self.children(id);
+            let n01 = self.center_h(nw, ne);
+            let n10 = self.center_v(nw, sw);
+            let n11 = self.center(id);
+            let n12 = self.center_v(ne, se);
+            let n21 = self.center_h(sw, se);
+            let ns = [nw, n01, ne, n10, n11, n12, sw, n21, se];
+
+            // the first half. at full speed, it also advances 2^(level-3) generations
+            let mut rs = [DEAD; 9];
+            for (r, n) in rs.iter_mut().zip(ns) {
+                *r = if k == level - 2 { self.step(n, k) } else { self.center(n) };
+            }
+
+            // the second half advances the rest
+            let quads = [
+                [rs[0], rs[1], rs[3], rs[4]],
+                [rs[1], rs[2], rs[4], rs[5]],
+                [rs[3], rs[4], rs[6], rs[7]],
+                [rs[4], rs[5], rs[7], rs[8]],
+            ];
+            let mut next = [DEAD; 4];
+            for (r, quad) in next.iter_mut().zip(quads) {
+                let joined = self.join(quad);
+                *r = self.step(joined, k);
+            }
+            self.join(next)
+        };
+        self.results.insert((id, k), result);
+        result
+    }
+
+    // whether all the cells are in the center quarter of the root
+    fn is_padded(&mut self) -> bool {
+        let center = self.center(self.root);
+        let inner = self.center(center);
+        self.nodes[inner as usize].population == self.population()
+    }
+
+    /// Advance the pattern `2^k` generations.
+    pub fn advance(&mut self, k: u32) -> anyhow::Result<()> {
+        if self.birth & 1 != 0 {
+            return Err(anyhow!("HashLife cannot run rules with B0"));
+        }
+        if MAX_STEP_LOG2 < k {
+            return Err(anyhow!("HashLife step 2^{} exceeds 2^{}", k, MAX_STEP_LOG2));
+        }
+        if MAX_NODES < self.nodes.len() {
+            self.collect_garbage();
+        }
+        // the pattern grows at most 2^k cells. the result covers the center half.
+        while self.level(self.root) < k + 3 || !self.is_padded() {
+            self.expand();
+        }
+        self.root = self.step(self.root, k);
+        Ok(())
+    }
+
+    // drop the nodes not reachable from the root and the memoized results.
+    fn collect_garbage(&mut self) {
+        fn copy(
+            id: NodeId,
+            old: &HashLife,
+            new: &mut HashLife,
+            map: &mut HashMap<NodeId, NodeId>,
+        ) -> NodeId {
+            if id == DEAD || id == ALIVE {
+                return id;
+            }
+            if let Some(copied) = map.get(&id) {
+                return *copied;
+            }
+            let mut children = old.children(id);
+            for c in children.iter_mut() {
+                *c = copy(*c, old, new, map);
+            }
+            let copied = new.join(children);
+            map.insert(id, copied);
+            copied
+        }
+        let mut new = HashLife::new(self.survive, self.birth);
+        new.root = copy(self.root, self, &mut new, &mut HashMap::new());
+        *self = new;
+    }
+
+    // make the root large enough to contain the rectangle.
+    fn cover(&mut self, x0: i64, y0: i64, width: usize, height: usize) {
+        loop {
+            let half = 1_i64 << (self.level(self.root) - 1);
+            if -half <= x0.min(y0) && x0 + width as i64 <= half && y0 + height as i64 <= half {
+                return;
+            }
+            self.expand();
+        }
+    }
+
+    /// Overwrite the cells in the rectangle whose top-left corner is at (x0, y0) by a grid.
+    pub fn import(&mut self, grid: &Grid<LifeGameState>, x0: i64, y0: i64) {
+        self.cover(x0, y0, grid.width(), grid.height());
+        let half = 1_i64 << (self.level(self.root) - 1);
+        self.root = self.paste(self.root, -half, -half, grid, x0, y0);
+    }
+
+    // (x, y) is the top-left corner of the node
+    fn paste(
+        &mut self,
+        id: NodeId,
+        x: i64,
+        y: i64,
+        grid: &Grid<LifeGameState>,
+        x0: i64,
+        y0: i64,
+    ) -> NodeId {
+        let level = self.level(id);
+        let size = 1_i64 << level;
+        let (w, h) = (grid.width() as i64, grid.height() as i64);
+        if x + size <= x0 || x0 + w <= x || y + size <= y0 || y0 + h <= y {
+            return id;
+        }
+        if x0 <= x && x + size <= x0 + w && y0 <= y && y + size <= y0 + h {
+            return self.build(grid, (x - x0) as usize, (y - y0) as usize, level);
+        }
+        let half = size / 2;
+        let mut children = self.children(id);
+        for (i, c) in children.iter_mut().enumerate() {
+            let cx = x + half * (i & 1) as i64;
+            let cy = y + half * (i >> 1) as i64;
+            *c = self.paste(*c, cx, cy, grid, x0, y0);
+        }
+        self.join(children)
+    }
+
+    fn build(&mut self, grid: &Grid<LifeGameState>, x: usize, y: usize, level: u32) -> NodeId {
+        if level == 0 {
+            return if *grid.cell_at(x, y) == LifeGameState::Alive { ALIVE } else { DEAD };
+        }
+        let half = 1 << (level - 1);
+        let nw = self.build(grid, x, y, level - 1);
+        let ne = self.build(grid, x + half, y, level - 1);
+        let sw = self.build(grid, x, y + half, level - 1);
+        let se = self.build(grid, x + half, y + half, level - 1);
+        self.join([nw, ne, sw, se])
+    }
+
+    /// Write the cells in the rectangle whose top-left corner is at (x0, y0) into a grid.
+    pub fn export(&mut self, grid: &mut Grid<LifeGameState>, x0: i64, y0: i64) {
+        self.cover(x0, y0, grid.width(), grid.height());
+        let half = 1_i64 << (self.level(self.root) - 1);
+        self.write(self.root, -half, -half, grid, x0, y0);
+    }
+
+    fn write(&self, id: NodeId, x: i64, y: i64, grid: &mut Grid<LifeGameState>, x0: i64, y0: i64) {
+        let node = &self.nodes[id as usize];
+        let size = 1_i64 << node.level;
+        let (w, h) = (grid.width() as i64, grid.height() as i64);
+        if x + size <= x0 || x0 + w <= x || y + size <= y0 || y0 + h <= y {
+            return;
+        }
+        if node.population == 0 || node.level == 0 {
+            let st = if node.population == 0 { LifeGameState::Dead } else { LifeGameState::Alive };
+            for gy in y.max(y0)..(y + size).min(y0 + h) {
+                for gx in x.max(x0)..(x + size).min(x0 + w) {
+                    *grid.cell_at_mut((gx - x0) as usize, (gy - y0) as usize) = st;
+                }
+            }
+            return;
+        }
+        let half = size / 2;
+        for (i, c) in node.children.iter().enumerate() {
+            let cx = x + half * (i & 1) as i64;
+            let cy = y + half * (i >> 1) as i64;
+            self.write(*c, cx, cy, grid, x0, y0);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+
+/// A world run by HashLife. Each update advances `2^step_log2` generations.
+///
+/// The board is kept in sync with the plane: edits on the board are imported
+/// before an update, and the result is exported to the board after it.
+#[derive(Serialize, Deserialize)]
+pub struct HashLifeWorld<R: LifeRule> {
+    #[serde(skip)]
+    rule: R,
+    board: SquareGrid<LifeGameState>,
+    #[serde(default)]
+    generation: u64,
+    #[serde(default)]
+    step_log2: u32,
+
+    #[serde(skip)]
+    hashlife: HashLife,
+    #[serde(skip)]
+    origin: (i64, i64), // the position of the top-left cell of the board on the plane
+    #[serde(skip)]
+    synced: Vec<Chunk<LifeGameState>>, // the board when it was exported last time
+}
+
+impl<R: LifeRule> Default for HashLifeWorld<R> {
+    fn default() -> Self {
+        Self::new(R::default(), 4, 3, 1)
+    }
+}
+
+impl<R: LifeRule> HashLifeWorld<R> {
+    pub fn step_log2(&self) -> u32 {
+        self.step_log2
+    }
+    pub fn set_step_log2(&mut self, k: u32) {
+        self.step_log2 = k.min(MAX_STEP_LOG2);
+    }
+    /// The number of live cells on the whole plane, including outside the board.
+    pub fn population(&self) -> u64 {
+        self.hashlife.population()
+    }
+
+    fn chunks(&self) -> Vec<Chunk<LifeGameState>> {
+        let mut chunks = Vec::with_capacity(self.board.n_chunks_x() * self.board.n_chunks_y());
+        for y in 0..self.board.n_chunks_y() {
+            for x in 0..self.board.n_chunks_x() {
+                chunks.push(self.board.chunk_at(x, y).clone());
+            }
+        }
+        chunks
+    }
+
+    // import the board if it is edited after the last export.
+    fn import(&mut self) {
+        if self.synced != self.chunks() {
+            self.hashlife.import(self.board.grid(), self.origin.0, self.origin.1);
+        }
+    }
+    fn export(&mut self) {
+        self.hashlife.export(self.board.grid_mut(), self.origin.0, self.origin.1);
+        self.synced = self.chunks();
+    }
+}
+
+impl<R: LifeRule> World for HashLifeWorld<R> {
+    type Rule = R;
+    type Board = SquareGrid<LifeGameState>;
+
+    fn new(rule: R, x_chunks: usize, y_chunks: usize, z_chunks: usize) -> Self {
+        assert!(z_chunks == 1, "HashLifeWorld has only 1 layer");
+        let (survive, birth) = rule.survive_birth_masks();
+        let mut world = Self {
+            rule,
+            board: SquareGrid::init(x_chunks, y_chunks, LifeGameState::Dead),
+            generation: 0,
+            step_log2: 0,
+            hashlife: HashLife::new(survive, birth),
+            origin: (0, 0),
+            synced: Vec::new(),
+        };
+        world.synced = world.chunks();
+        world
+    }
+
+    fn rule(&self) -> &R {
+        &self.rule
+    }
+    fn rule_mut(&mut self) -> &mut R {
+        &mut self.rule
+    }
+    fn board(&self) -> &Self::Board {
+        &self.board
+    }
+    fn board_mut(&mut self) -> &mut Self::Board {
+        &mut self.board
+    }
+    fn current_layer(&self) -> usize {
+        0
+    }
+    fn set_current_layer(&mut self, _z: usize) {
+        // do nothing
+    }
+
+    fn width(&self) -> usize {
+        self.board.width()
+    }
+    fn height(&self) -> usize {
+        self.board.height()
+    }
+    fn depth(&self) -> usize {
+        1
+    }
+
+    // the new region shows the cells on the plane
+    fn expand_x(&mut self, n: isize, init: LifeGameState) {
+        self.import();
+        self.board.expand_x(n, init);
+        if n < 0 {
+            self.origin.0 -= (n.unsigned_abs() * self.board.chunk_len()) as i64;
+        }
+        self.export();
+    }
+    fn expand_y(&mut self, n: isize, init: LifeGameState) {
+        self.import();
+        self.board.expand_y(n, init);
+        if n < 0 {
+            self.origin.1 -= (n.unsigned_abs() * self.board.chunk_len()) as i64;
+        }
+        self.export();
+    }
+    fn expand_z(&mut self, _: isize, _: LifeGameState) {
+        // do nothing
+    }
+    // the cells out of the new region are still on the plane
+    fn crop(&mut self, x: Range<usize>, y: Range<usize>) {
+        self.import();
+        self.origin.0 += (x.start * self.board.chunk_len()) as i64;
+        self.origin.1 += (y.start * self.board.chunk_len()) as i64;
+        self.board.crop(x, y);
+        self.export();
+    }
+
+    // the plane is infinite
+    fn boundary(&self) -> Option<Boundary> {
+        None
+    }
+    fn set_boundary(&mut self, boundary: Boundary) -> anyhow::Result<()> {
+        if boundary != Boundary::Infinite {
+            return Err(anyhow!("HashLife runs on an infinite plane"));
+        }
+        Ok(())
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+    fn set_generation(&mut self, generation: u64) {
+        self.generation = generation;
+    }
+
+    fn clear(&mut self) -> anyhow::Result<()> {
+        self.generation = 0;
+        self.board.clear(&self.rule)?;
+        self.hashlife.clear();
+        self.synced = self.chunks();
+        Ok(())
+    }
+    fn randomize<Rn: Rng>(&mut self, rng: &mut Rn) -> anyhow::Result<()> {
+        self.generation = 0;
+        self.board.randomize(&self.rule, rng)?;
+        self.hashlife.clear();
+        self.hashlife.import(self.board.grid(), self.origin.0, self.origin.1);
+        self.synced = self.chunks();
+        Ok(())
+    }
+
+    // the cells that left the board are not restored
+    fn supports_history(&self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "gui")]
+    fn paint(
+        &self,
+        painter: &egui::Painter,
+        origin: egui::Pos2,
+        cell_width: f32,
+    ) -> anyhow::Result<()>
+    where
+        R: RuleUi,
+    {
+        self.board.paint(painter, origin, cell_width, &self.rule, 1.0)
+    }
+
+    #[cfg(feature = "gui")]
+    fn render(&self, cell_width: u32) -> anyhow::Result<image::RgbaImage>
+    where
+        R: RuleUi,
+    {
+        self.board.render(&self.rule, cell_width)
+    }
+
+    #[cfg(feature = "gui")]
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(
+            egui::Slider::new(&mut self.step_log2, 0..=MAX_STEP_LOG2)
+                .text("log2(generations per step)"),
+        );
+        ui.label(format!("population on the plane: {}", self.hashlife.population()));
+        ui.label(format!("nodes: {}", self.hashlife.num_nodes()));
+    }
+
+    fn update(&mut self) -> anyhow::Result<()> {
+        let (survive, birth) = self.rule.survive_birth_masks();
+        self.hashlife.set_rule(survive, birth);
+        self.import();
+        self.hashlife.advance(self.step_log2)?;
+        self.generation += 1 << self.step_log2;
+        self.export();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::CHUNK_LEN;
+    use crate::lifegame::LifeLikeGameRule;
+    use crate::world::World2D;
+
+    type Life = World2D<LifeLikeGameRule<MooreNeighborhood>, SquareGrid<LifeGameState>>;
+
+    // an R-pentomino and a glider going away from it
+    fn init() -> (Life, HashLife) {
+        let rule = LifeLikeGameRule::new(vec![2, 3], vec![3]);
+        let (survive, birth) = rule.survive_birth_masks();
+        let mut world = Life::new(rule, 4, 4, 1);
+        world.set_boundary(Boundary::Infinite).unwrap();
+        let cells = [(21, 20), (22, 20), (20, 21), (21, 21), (21, 22)].into_iter().chain([
+            (41, 40),
+            (42, 41),
+            (40, 42),
+            (41, 42),
+            (42, 42),
+        ]);
+        for (x, y) in cells {
+            *world.board_mut().cell_at_mut(x, y) = LifeGameState::Alive;
+        }
+        let mut hashlife = HashLife::new(survive, birth);
+        let (x0, y0) = plane_origin(&world);
+        hashlife.import(world.board().grid(), x0, y0);
+        (world, hashlife)
+    }
+
+    fn plane_origin(world: &Life) -> (i64, i64) {
+        let (ox, oy) = world.board().chunk_origin();
+        (ox as i64 * CHUNK_LEN as i64, oy as i64 * CHUNK_LEN as i64)
+    }
+
+    // the infinite board covers all the live cells, so the whole plane is compared
+    fn assert_same(world: &Life, hashlife: &mut HashLife) {
+        let (nx, ny) = (world.board().n_chunks_x(), world.board().n_chunks_y());
+        let mut grid = Grid::init(nx, ny, LifeGameState::Dead);
+        let (x0, y0) = plane_origin(world);
+        hashlife.export(&mut grid, x0, y0);
+        let mut population = 0;
+        for y in 0..world.height() {
+            for x in 0..world.width() {
+                let expected = world.board().cell_at(x, y);
+                assert_eq!(
+                    grid.cell_at(x, y),
+                    expected,
+                    "at ({}, {}) in generation {}",
+                    x,
+                    y,
+                    world.generation()
+                );
+                population += (*expected == LifeGameState::Alive) as u64;
+            }
+        }
+        assert_eq!(hashlife.population(), population);
+    }
+
+    #[test]
+    fn advance_same_as_world2d() {
+        for k in [0, 1, 3, 5, 7] {
+            let (mut world, mut hashlife) = init();
+            for _ in 0..3 {
+                hashlife.advance(k).unwrap();
+                for _ in 0..1 << k {
+                    world.update().unwrap();
+                }
+                assert_same(&world, &mut hashlife);
+            }
+        }
+    }
+
+    #[test]
+    fn advance_after_rule_change() {
+        let (mut world, mut hashlife) = init();
+        hashlife.advance(4).unwrap();
+        for _ in 0..16 {
+            world.update().unwrap();
+        }
+        // HighLife, 23/36
+        *world.rule_mut() = LifeLikeGameRule::new(vec![2, 3], vec![3, 6]);
+        let (survive, birth) = world.rule().survive_birth_masks();
+        hashlife.set_rule(survive, birth);
+        for _ in 0..2 {
+            hashlife.advance(4).unwrap();
+            for _ in 0..16 {
+                world.update().unwrap();
+            }
+            assert_same(&world, &mut hashlife);
+        }
+    }
+
+    #[test]
+    fn only_infinite_boundary() {
+        let mut world = HashLifeWorld::<LifeLikeGameRule<MooreNeighborhood>>::default();
+        assert!(world.set_boundary(Boundary::Infinite).is_ok());
+        assert!(world.set_boundary(Boundary::Periodic).is_err());
+        assert!(world.set_boundary(Boundary::Fixed).is_err());
+    }
+}
@@ -0,0 +1,363 @@
+//! Undo/redo history of a board.
+//!
+//! An operation (cell edit, paste, ...) is recorded by taking a snapshot with
+//! `begin` before it and comparing the board with the snapshot in `commit`
+//! after it. A step is recorded by `commit_step` without a snapshot, from the
+//! chunks the board knows to be changed by the step. Only the changed chunks
+//! are kept in the history. The generation of the world is also recorded so
+//! that undoing a step rewinds the counter.
+//!
+//! Chunks are identified by their coordinates relative to the chunk that was
+//! at the top-left corner when the app started. Expanding a board to the
+//! negative direction shifts the local chunk index, so the caller passes the
+//! number of chunks prepended so far as `offset`.
+
+use crate::board::{Board, Chunk};
+use crate::rule::State;
+use std::collections::VecDeque;
+
+struct Snapshot<T: State> {
+    generation: u64,
+    offset: (isize, isize),
+    n_chunks_x: usize,
+    n_chunks_y: usize,
+    chunks: Vec<Chunk<T>>,
+}
+
+impl<T: State> Snapshot<T> {
+    fn get(&self, (x, y): (isize, isize)) -> Option<&Chunk<T>> {
+        let x = x + self.offset.0;
+        let y = y + self.offset.1;
+        if x < 0 || y < 0 || self.n_chunks_x as isize <= x || self.n_chunks_y as isize <= y {
+            None
+        } else {
+            Some(&self.chunks[y as usize * self.n_chunks_x + x as usize])
+        }
+    }
+}
+
+// chunks before (or after, in the redo stack) an operation.
+struct Entry<T: State> {
+    generation: u64,
+    chunks: Vec<((isize, isize), Chunk<T>)>,
+}
+
+pub(crate) struct History<T: State> {
+    undo: VecDeque<Entry<T>>,
+    redo: Vec<Entry<T>>,
+    pending: Option<Snapshot<T>>,
+    num_chunks: usize,            // the number of chunks in the undo stack
+    pub(crate) max_chunks: usize, // older entries are dropped if it exceeds
+    enabled: bool,                // if false, nothing is recorded
+}
+
+impl<T: State> Default for History<T> {
+    fn default() -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            pending: None,
+            num_chunks: 0,
+            max_chunks: 16384,
+            enabled: true,
+        }
+    }
+}
+
+impl<T: State> History<T> {
+    pub(crate) fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+    pub(crate) fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+    pub(crate) fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.pending = None;
+        self.num_chunks = 0;
+    }
+
+    /// Disable the history for a world that cannot be restored from the board.
+    /// Operations are still begun and committed, but nothing is recorded.
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        if self.enabled != enabled {
+            self.clear();
+            self.enabled = enabled;
+        }
+    }
+
+    /// Take a snapshot before an operation. If there is already a pending
+    /// snapshot, the operation will be merged into it.
+    pub(crate) fn begin<B: Board<T>>(
+        &mut self,
+        board: &B,
+        offset: (isize, isize),
+        generation: u64,
+    ) {
+        if self.pending.is_some() {
+            return;
+        }
+        let (n_chunks_x, n_chunks_y) =
+            if self.enabled { (board.n_chunks_x(), board.n_chunks_y()) } else { (0, 0) };
+        let mut chunks = Vec::with_capacity(n_chunks_x * n_chunks_y);
+        for y in 0..n_chunks_y {
+            for x in 0..n_chunks_x {
+                chunks.push(board.chunk_at(x, y).clone());
+            }
+        }
+        self.pending = Some(Snapshot { generation, offset, n_chunks_x, n_chunks_y, chunks });
+    }
+
+    /// Compare the board with the snapshot and record the changed chunks.
+    /// Chunks added while the operation are considered to be filled by `default`.
+    pub(crate) fn commit<B: Board<T>>(
+        &mut self,
+        board: &B,
+        offset: (isize, isize),
+        generation: u64,
+        default: &T,
+    ) {
+        let snapshot = match self.pending.take() {
+            Some(snapshot) if self.enabled => snapshot,
+            _ => return,
+        };
+        self.commit_from(board, offset, snapshot.generation, generation, default, |pos| {
+            snapshot.get(pos)
+        });
+    }
+
+    /// Record an operation from `generation_before` by comparing the board
+    /// with the chunks before it, given by `prev` for the chunk coordinates
+    /// relative to the original top-left chunk. A step of several iterations
+    /// is recorded with the chunks kept by `Generations`, instead of a snapshot.
+    pub(crate) fn commit_from<'a, B, F>(
+        &mut self,
+        board: &B,
+        offset: (isize, isize),
+        generation_before: u64,
+        generation: u64,
+        default: &T,
+        prev: F,
+    ) where
+        B: Board<T>,
+        F: Fn((isize, isize)) -> Option<&'a Chunk<T>>,
+        T: 'a,
+    {
+        if !self.enabled {
+            return;
+        }
+        let mut chunks = Vec::new();
+        for y in 0..board.n_chunks_y() {
+            for x in 0..board.n_chunks_x() {
+                let pos = (x as isize - offset.0, y as isize - offset.1);
+                let curr = board.chunk_at(x, y);
+                match prev(pos) {
+                    Some(prev) if prev == curr => {}
+                    Some(prev) => chunks.push((pos, prev.clone())),
+                    None => {
+                        let prev = Chunk::init(default.clone());
+                        if prev != *curr {
+                            chunks.push((pos, prev));
+                        }
+                    }
+                }
+            }
+        }
+        if chunks.is_empty() && generation_before == generation {
+            return; // nothing happened. keep redo stack.
+        }
+
+        self.redo.clear();
+        self.push_undo(Entry { generation: generation_before, chunks });
+    }
+
+    /// Record a step right after it. Instead of a snapshot, the chunks changed
+    /// by the step are taken from the board. `generation` is the one before it.
+    pub(crate) fn commit_step<B: Board<T>>(
+        &mut self,
+        board: &B,
+        offset: (isize, isize),
+        generation: u64,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let mut chunks = Vec::new();
+        for y in 0..board.n_chunks_y() {
+            for x in 0..board.n_chunks_x() {
+                if board.is_changed_chunk(x, y) == Some(false) {
+                    continue;
+                }
+                let prev = board.prev_chunk_at(x, y);
+                if prev != board.chunk_at(x, y) {
+                    chunks.push(((x as isize - offset.0, y as isize - offset.1), prev.clone()));
+                }
+            }
+        }
+        self.redo.clear();
+        self.push_undo(Entry { generation, chunks });
+    }
+
+    fn push_undo(&mut self, entry: Entry<T>) {
+        self.num_chunks += entry.chunks.len();
+        self.undo.push_back(entry);
+        // keep at least the last one
+        while self.max_chunks < self.num_chunks && 1 < self.undo.len() {
+            let dropped = self.undo.pop_front().expect("undo has at least 2 entries");
+            self.num_chunks -= dropped.chunks.len();
+        }
+    }
+
+    /// Restore the board before the last operation. Returns false if there is nothing to undo.
+    pub(crate) fn undo<B: Board<T>>(
+        &mut self,
+        board: &mut B,
+        offset: (isize, isize),
+        generation: &mut u64,
+    ) -> bool {
+        match self.undo.pop_back() {
+            Some(mut entry) => {
+                self.num_chunks -= entry.chunks.len();
+                Self::swap(&mut entry, board, offset, generation);
+                self.redo.push(entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Re-apply the last undone operation. Returns false if there is nothing to redo.
+    pub(crate) fn redo<B: Board<T>>(
+        &mut self,
+        board: &mut B,
+        offset: (isize, isize),
+        generation: &mut u64,
+    ) -> bool {
+        match self.redo.pop() {
+            Some(mut entry) => {
+                Self::swap(&mut entry, board, offset, generation);
+                self.push_undo(entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // after swapping, the entry has the chunks to go back to the current state.
+    fn swap<B: Board<T>>(
+        entry: &mut Entry<T>,
+        board: &mut B,
+        offset: (isize, isize),
+        generation: &mut u64,
+    ) {
+        std::mem::swap(&mut entry.generation, generation);
+        for ((x, y), chunk) in entry.chunks.iter_mut() {
+            let x = *x + offset.0;
+            let y = *y + offset.1;
+            if 0 <= x && 0 <= y && board.has_chunk(x as usize, y as usize) {
+                std::mem::swap(board.chunk_at_mut(x as usize, y as usize), chunk);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Boundary;
+    use crate::generations::Generations;
+    use crate::lifegame::{LifeGameRule, LifeGameState};
+    use crate::test_util::{alive, offset, Life};
+    use crate::world::World;
+
+    #[test]
+    fn undo_steps_recorded_from_changed_chunks() {
+        for boundary in [Boundary::Periodic, Boundary::Fixed, Boundary::Infinite] {
+            let mut world = Life::new(LifeGameRule::default(), 3, 3, 1);
+            world.set_boundary(boundary).unwrap();
+            // a glider crossing the chunk borders and a still block
+            for (x, y) in [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)] {
+                *world.board_mut().cell_at_mut(x + 20, y + 20) = LifeGameState::Alive;
+            }
+            for (x, y) in [(5, 5), (6, 5), (5, 6), (6, 6)] {
+                *world.board_mut().cell_at_mut(x, y) = LifeGameState::Alive;
+            }
+            let mut history = History::default();
+            let mut snapshots = Vec::new();
+            for _ in 0..40 {
+                snapshots.push(alive(&world));
+                let generation = world.generation();
+                world.update().unwrap();
+                history.commit_step(world.board(), offset(&world), generation);
+            }
+            let last = alive(&world);
+
+            let mut generation = world.generation();
+            while let Some(expected) = snapshots.pop() {
+                let offset = offset(&world);
+                assert!(history.undo(world.board_mut(), offset, &mut generation));
+                assert_eq!(generation, snapshots.len() as u64);
+                assert_eq!(alive(&world), expected, "{:?}", boundary);
+            }
+            assert!(!history.can_undo());
+            let offset = offset(&world);
+            while history.redo(world.board_mut(), offset, &mut generation) {}
+            assert_eq!(generation, 40);
+            assert_eq!(alive(&world), last, "{:?}", boundary);
+        }
+    }
+
+    #[test]
+    fn undo_multi_iteration_step_from_generations() {
+        let mut world = Life::new(LifeGameRule::default(), 3, 3, 1);
+        for (x, y) in [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)] {
+            *world.board_mut().cell_at_mut(x + 14, y + 14) = LifeGameState::Alive;
+        }
+        let mut generations = Generations::default();
+        let mut history = History::default();
+        let before = alive(&world);
+        let generation = world.generation();
+        generations.push(world.board(), offset(&world), generation);
+        for _ in 0..3 {
+            world.update().unwrap();
+        }
+        assert_ne!(alive(&world), before);
+        let after = alive(&world);
+        history.commit_from(
+            world.board(),
+            offset(&world),
+            generation,
+            world.generation(),
+            &LifeGameState::Dead,
+            |pos| generations.latest_chunk(pos),
+        );
+
+        let mut generation = world.generation();
+        let offset = offset(&world);
+        assert!(history.undo(world.board_mut(), offset, &mut generation));
+        assert_eq!(generation, 0);
+        assert_eq!(alive(&world), before);
+        assert!(!history.can_undo());
+        assert!(history.redo(world.board_mut(), offset, &mut generation));
+        assert_eq!(generation, 3);
+        assert_eq!(alive(&world), after);
+    }
+
+    #[test]
+    fn disabled_history_records_nothing() {
+        let mut world = Life::new(LifeGameRule::default(), 2, 2, 1);
+        let mut history = History::default();
+        history.set_enabled(false);
+        history.begin(world.board(), offset(&world), 0);
+        *world.board_mut().cell_at_mut(3, 3) = LifeGameState::Alive;
+        history.commit(world.board(), offset(&world), 0, &LifeGameState::Dead);
+        world.update().unwrap();
+        history.commit_step(world.board(), offset(&world), 0);
+        assert!(!history.can_undo());
+    }
+}
@@ -1,29 +1,49 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
+#[cfg(feature = "gui")]
 mod app;
-mod board;
-mod dynamic_rule;
-mod gray_scott;
-mod lifegame;
-mod rock_paper_scissors;
-mod rule;
-mod wireworld;
-mod world;
+pub mod bitchunk;
+pub mod board;
+pub mod dynamic_rule;
+#[cfg(feature = "gui")]
+mod generations;
+pub mod gray_scott;
+pub mod hashlife;
+#[cfg(feature = "gui")]
+mod history;
+pub mod life3d;
+pub mod lifegame;
+pub mod pattern;
+#[cfg(feature = "gui")]
+mod platform;
+#[cfg(feature = "gui")]
+mod recorder;
+pub mod rock_paper_scissors;
+pub mod rule;
+#[cfg(feature = "gui")]
+mod statistics;
+#[cfg(test)]
+mod test_util;
+pub mod wireworld;
+pub mod world;
+pub mod world3d;
+#[cfg(feature = "gui")]
 mod wrap_app;
 
+#[cfg(feature = "gui")]
 pub use wrap_app::WrapApp as App;
 
 // ----------------------------------------------------------------------------
 // When compiling for web:
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(target_arch = "wasm32", feature = "gui"))]
 use eframe::wasm_bindgen::{self, prelude::*};
 
 /// This is the entry-point for all the web-assembly.
 /// This is called once from the HTML.
 /// It loads the app, installs some callbacks, then returns.
 /// You can add more callbacks like this if you want to call in to your code.
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(target_arch = "wasm32", feature = "gui"))]
 #[wasm_bindgen]
 pub fn start(canvas_id: &str) -> Result<(), eframe::wasm_bindgen::JsValue> {
     // Make sure panics are logged using `console.error`.
@@ -0,0 +1,211 @@
+//! 3D life-like cellular automata.
+//!
+//! Rules are written in Bays' notation `{El}{Eu}{Fl}{Fu}`. A live cell survives
+//! if it has `El..=Eu` live neighbors, and a dead cell becomes alive if it has
+//! `Fl..=Fu` live neighbors, e.g. `4555` and `5766` on 26 neighbors.
+
+use crate::lifegame::LifeGameState;
+use crate::rule::{CubicNeighborhood, Rule, UpdateContext};
+#[cfg(feature = "gui")]
+use crate::rule::{CubicNeighborhoodKind, Neighbors3D, RuleUi};
+use rand::distributions::{Bernoulli, Distribution};
+use rand::Rng;
+
+pub struct Life3DRule {
+    neighborhood: CubicNeighborhood,
+
+    survive: (u32, u32), // range of the number of live neighbors
+    birth: (u32, u32),
+
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    rule: String,
+    #[cfg(feature = "gui")]
+    show_err_msg_about_rule: bool,
+
+    #[cfg(feature = "gui")]
+    background: egui::Color32,
+    #[cfg(feature = "gui")]
+    alive_color: egui::Color32,
+    #[cfg(feature = "gui")]
+    dead_color: egui::Color32,
+}
+
+impl Default for Life3DRule {
+    fn default() -> Self {
+        Self::from_rule("4555").expect("4555 is a valid rule")
+    }
+}
+
+impl Life3DRule {
+    pub fn new(survive: (u32, u32), birth: (u32, u32)) -> Self {
+        let counts = [survive.0, survive.1, birth.0, birth.1];
+        let rule = if counts.iter().all(|n| *n < 10) {
+            counts.iter().map(|n| n.to_string()).collect()
+        } else {
+            counts.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(",")
+        };
+        Self {
+            neighborhood: CubicNeighborhood::default(),
+            survive,
+            birth,
+            rule,
+            #[cfg(feature = "gui")]
+            show_err_msg_about_rule: false,
+            #[cfg(feature = "gui")]
+            background: egui::Color32::from_rgb(24, 24, 128),
+            #[cfg(feature = "gui")]
+            alive_color: egui::Color32::from_rgb(96, 160, 255),
+            #[cfg(feature = "gui")]
+            dead_color: egui::Color32::from_rgb(24, 24, 24),
+        }
+    }
+
+    pub fn from_rule(rule: &str) -> Option<Self> {
+        let (survive, birth) = Self::parse_rule(rule)?;
+        Some(Self::new(survive, birth))
+    }
+
+    pub fn with_neighborhood(mut self, neighborhood: CubicNeighborhood) -> Self {
+        self.neighborhood = neighborhood;
+        self
+    }
+
+    /// Parse a rule `{El}{Eu}{Fl}{Fu}`, e.g. `4555`. If a number has two
+    /// digits, the four numbers are separated by commas, e.g. `5,7,6,6`.
+    pub fn parse_rule(rule: &str) -> Option<((u32, u32), (u32, u32))> {
+        let ns: Vec<u32> = if rule.contains(',') {
+            rule.split(',').map(|n| n.trim().parse().ok()).collect::<Option<_>>()?
+        } else {
+            rule.trim().chars().map(|c| c.to_digit(10)).collect::<Option<_>>()?
+        };
+        match ns[..] {
+            [el, eu, fl, fu] if el <= eu && fl <= fu => Some(((el, eu), (fl, fu))),
+            _ => None,
+        }
+    }
+}
+
+impl Rule for Life3DRule {
+    type CellState = LifeGameState;
+    type Neighborhood = CubicNeighborhood;
+
+    fn neighborhood(&self) -> CubicNeighborhood {
+        self.neighborhood.clone()
+    }
+
+    fn default_state(&self) -> anyhow::Result<Self::CellState> {
+        Ok(LifeGameState::Dead)
+    }
+
+    fn randomize<R: Rng>(&self, rng: &mut R) -> anyhow::Result<Self::CellState> {
+        let distr = Bernoulli::new(0.1).expect("we know 0 < 0.1 < 1.");
+        Ok(if distr.sample(rng) { LifeGameState::Alive } else { LifeGameState::Dead })
+    }
+
+    fn next(&self, st: Self::CellState) -> anyhow::Result<Self::CellState> {
+        Ok(if st == LifeGameState::Dead {
+            LifeGameState::Alive
+        } else {
+            LifeGameState::Dead
+        })
+    }
+
+    fn update(
+        &self,
+        center: Self::CellState,
+        neighbor: impl Iterator<Item = ((isize, isize), Self::CellState)>,
+        _ctx: &UpdateContext,
+    ) -> anyhow::Result<Self::CellState> {
+        let n_alive: u32 =
+            neighbor.map(|(_, c)| if c == LifeGameState::Alive { 1 } else { 0 }).sum();
+        let (lower, upper) = if center == LifeGameState::Alive { self.survive } else { self.birth };
+
+        Ok(if lower <= n_alive && n_alive <= upper {
+            LifeGameState::Alive
+        } else {
+            LifeGameState::Dead
+        })
+    }
+}
+
+#[cfg(feature = "gui")]
+impl RuleUi for Life3DRule {
+    fn background(&self) -> egui::Color32 {
+        self.background
+    }
+
+    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
+        Ok(if *st == LifeGameState::Dead { self.dead_color } else { self.alive_color })
+    }
+
+    fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        _ctx: &egui::Context,
+        _on_side_panel: bool,
+    ) -> anyhow::Result<bool> {
+        ui.label("3D life-like cellular automaton in Bays' notation");
+        ui.separator();
+
+        let mut changed = false;
+        ui.label("rule {El}{Eu}{Fl}{Fu} (e.g. `4555`)");
+        ui.horizontal_wrapped(|ui| {
+            if ui.add(egui::TextEdit::singleline(&mut self.rule)).changed() {
+                self.show_err_msg_about_rule = false;
+            }
+
+            if ui.button("Apply").clicked() {
+                if let Some((survive, birth)) = Self::parse_rule(&self.rule) {
+                    self.survive = survive;
+                    self.birth = birth;
+                    changed = true;
+                } else {
+                    self.show_err_msg_about_rule = true;
+                }
+            }
+        });
+        if self.show_err_msg_about_rule {
+            ui.label("Invalid Rule");
+        }
+        ui.separator();
+
+        let mut kind = self.neighborhood.kind();
+        egui::ComboBox::from_label("neighborhood")
+            .selected_text(kind.name())
+            .show_ui(ui, |ui| {
+                for k in CubicNeighborhoodKind::all() {
+                    ui.selectable_value(&mut kind, k, k.name());
+                }
+            });
+        if kind != self.neighborhood.kind() {
+            self.neighborhood = CubicNeighborhood::new(kind);
+            changed = true;
+        }
+        ui.label(format!("{} neighbors", self.neighborhood.num_neighbors()));
+        ui.separator();
+
+        ui.label("Grid Color");
+        egui::widgets::color_picker::color_edit_button_srgba(
+            ui,
+            &mut self.background,
+            egui::widgets::color_picker::Alpha::Opaque,
+        );
+        ui.separator();
+
+        ui.label("Live Cell Color");
+        egui::widgets::color_picker::color_edit_button_srgba(
+            ui,
+            &mut self.alive_color,
+            egui::widgets::color_picker::Alpha::Opaque,
+        );
+        ui.separator();
+
+        ui.label("Dead Cell Color");
+        egui::widgets::color_picker::color_edit_button_srgba(
+            ui,
+            &mut self.dead_color,
+            egui::widgets::color_picker::Alpha::Opaque,
+        );
+        Ok(changed)
+    }
+}
@@ -1,6 +1,10 @@
-use crate::board::ClipBoard;
-use crate::rule::{Neighbors, Rule, State};
-use arrayvec::ArrayVec;
+use crate::bitchunk;
+use crate::board::{Board, ClipBoard};
+use crate::hashlife::LifeRule;
+use crate::pattern::{self, PatternFormat};
+use crate::rule::{MooreNeighborhood, Neighbors, Rule, State, UpdateContext};
+#[cfg(feature = "gui")]
+use crate::rule::{NeighborsUi, RuleUi, StateUi};
 use rand::distributions::{Bernoulli, Distribution};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
@@ -19,7 +23,13 @@ impl std::default::Default for LifeGameState {
     }
 }
 
+// two states fit in a bit
 impl State for LifeGameState {
+    type Cells = bitchunk::BitChunk;
+}
+
+#[cfg(feature = "gui")]
+impl StateUi for LifeGameState {
     fn inspect(&mut self, ui: &mut egui::Ui, _buf: &mut String) {
         ui.radio_value(self, LifeGameState::Dead, "Dead");
         ui.radio_value(self, LifeGameState::Alive, "Alive");
@@ -28,11 +38,18 @@ impl State for LifeGameState {
 
 // ---------------------------------------------------------------------------
 
+// the numbers of live neighbors to survive and to be born, as bit masks
+const LIFE_MASKS: (u16, u16) = (0b1100, 0b1000); // 23/3
+const HIGHLIFE_MASKS: (u16, u16) = (0b1100, 0b1001000); // 23/36
+
 pub struct LifeGameRule<N> {
     neighbors: std::marker::PhantomData<N>,
 
+    #[cfg(feature = "gui")]
     background: egui::Color32,
+    #[cfg(feature = "gui")]
     alive_color: egui::Color32,
+    #[cfg(feature = "gui")]
     dead_color: egui::Color32,
 
     library: Vec<(String, ClipBoard<LifeGameState>)>,
@@ -49,8 +66,11 @@ impl<N> Default for LifeGameRule<N> {
         ]).expect("3x3=9");
         Self {
             neighbors: std::marker::PhantomData,
+            #[cfg(feature = "gui")]
             background: egui::Color32::from_rgb(24, 128, 24),
+            #[cfg(feature = "gui")]
             alive_color: egui::Color32::from_rgb(24, 255, 24),
+            #[cfg(feature = "gui")]
             dead_color: egui::Color32::from_rgb(24, 24, 24),
             library: vec![("glider".to_string(), glider)],
         }
@@ -61,14 +81,6 @@ impl<N: Neighbors> Rule for LifeGameRule<N> {
     type CellState = LifeGameState;
     type Neighborhood = N;
 
-    fn background(&self) -> egui::Color32 {
-        self.background
-    }
-
-    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
-        Ok(if *st == LifeGameState::Dead { self.dead_color } else { self.alive_color })
-    }
-
     fn default_state(&self) -> anyhow::Result<Self::CellState> {
         Ok(LifeGameState::Dead)
     }
@@ -89,9 +101,11 @@ impl<N: Neighbors> Rule for LifeGameRule<N> {
     fn update(
         &self,
         center: Self::CellState,
-        neighbor: impl Iterator<Item = Self::CellState>,
+        neighbor: impl Iterator<Item = ((isize, isize), Self::CellState)>,
+        _ctx: &UpdateContext,
     ) -> anyhow::Result<Self::CellState> {
-        let n_alive: u32 = neighbor.map(|c| if c == LifeGameState::Alive { 1 } else { 0 }).sum();
+        let n_alive: u32 =
+            neighbor.map(|(_, c)| if c == LifeGameState::Alive { 1 } else { 0 }).sum();
 
         // 23/3
         Ok(if n_alive == 3 || (center == LifeGameState::Alive && n_alive == 2) {
@@ -101,16 +115,64 @@ impl<N: Neighbors> Rule for LifeGameRule<N> {
         })
     }
 
+    fn update_board<B>(&self, board: &mut B, generation: u64) -> anyhow::Result<()>
+    where
+        B: Board<Self::CellState>,
+    {
+        let (survive, birth) = LIFE_MASKS;
+        bitchunk::update_life(self, board, generation, survive, birth)
+    }
+
     fn library(&self) -> Vec<(String, ClipBoard<Self::CellState>)> {
         self.library.clone()
     }
+    fn pattern_formats(&self) -> Vec<PatternFormat> {
+        PatternFormat::all()
+    }
+
+    fn pattern_rule(&self) -> Option<String> {
+        pattern::rule_string(&[2, 3], &[3], &N::default())
+    }
+
+    fn load_pattern(
+        &self,
+        format: PatternFormat,
+        content: &str,
+    ) -> anyhow::Result<ClipBoard<Self::CellState>> {
+        pattern::load(format, content)
+    }
+
+    fn save_pattern(
+        &self,
+        format: PatternFormat,
+        cb: &ClipBoard<Self::CellState>,
+    ) -> anyhow::Result<String> {
+        pattern::save(format, cb, self.pattern_rule().as_deref())
+    }
+}
+
+impl LifeRule for LifeGameRule<MooreNeighborhood> {
+    fn survive_birth_masks(&self) -> (u16, u16) {
+        LIFE_MASKS
+    }
+}
+
+#[cfg(feature = "gui")]
+impl<N: Neighbors> RuleUi for LifeGameRule<N> {
+    fn background(&self) -> egui::Color32 {
+        self.background
+    }
+
+    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
+        Ok(if *st == LifeGameState::Dead { self.dead_color } else { self.alive_color })
+    }
 
     fn ui(
         &mut self,
         ui: &mut egui::Ui,
         _ctx: &egui::Context,
         _on_side_panel: bool,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<bool> {
         ui.hyperlink_to(
             "Conway's Game of Life - Wikipedia",
             "https://en.wikipedia.org/wiki/Conway%27s_Game_of_Life",
@@ -139,7 +201,7 @@ impl<N: Neighbors> Rule for LifeGameRule<N> {
             &mut self.dead_color,
             egui::widgets::color_picker::Alpha::Opaque,
         );
-        Ok(())
+        Ok(false)
     }
 }
 
@@ -148,8 +210,11 @@ impl<N: Neighbors> Rule for LifeGameRule<N> {
 pub struct HighLifeRule<N> {
     neighbors: std::marker::PhantomData<N>,
 
+    #[cfg(feature = "gui")]
     background: egui::Color32,
+    #[cfg(feature = "gui")]
     alive_color: egui::Color32,
+    #[cfg(feature = "gui")]
     dead_color: egui::Color32,
 }
 
@@ -157,8 +222,11 @@ impl<N> Default for HighLifeRule<N> {
     fn default() -> Self {
         Self {
             neighbors: std::marker::PhantomData,
+            #[cfg(feature = "gui")]
             background: egui::Color32::from_rgb(24, 128, 24),
+            #[cfg(feature = "gui")]
             alive_color: egui::Color32::from_rgb(24, 255, 24),
+            #[cfg(feature = "gui")]
             dead_color: egui::Color32::from_rgb(24, 24, 24),
         }
     }
@@ -168,14 +236,6 @@ impl<N: Neighbors> Rule for HighLifeRule<N> {
     type CellState = LifeGameState;
     type Neighborhood = N;
 
-    fn background(&self) -> egui::Color32 {
-        self.background
-    }
-
-    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
-        Ok(if *st == LifeGameState::Dead { self.dead_color } else { self.alive_color })
-    }
-
     fn default_state(&self) -> anyhow::Result<Self::CellState> {
         Ok(LifeGameState::Dead)
     }
@@ -196,10 +256,12 @@ impl<N: Neighbors> Rule for HighLifeRule<N> {
     fn update(
         &self,
         center: Self::CellState,
-        neighbor: impl Iterator<Item = Self::CellState>,
+        neighbor: impl Iterator<Item = ((isize, isize), Self::CellState)>,
+        _ctx: &UpdateContext,
     ) -> anyhow::Result<Self::CellState> {
         let center_is_alive = center == LifeGameState::Alive;
-        let n_alive: u32 = neighbor.map(|c| if c == LifeGameState::Alive { 1 } else { 0 }).sum();
+        let n_alive: u32 =
+            neighbor.map(|(_, c)| if c == LifeGameState::Alive { 1 } else { 0 }).sum();
 
         // 23/36
         Ok(
@@ -214,12 +276,60 @@ impl<N: Neighbors> Rule for HighLifeRule<N> {
         )
     }
 
+    fn update_board<B>(&self, board: &mut B, generation: u64) -> anyhow::Result<()>
+    where
+        B: Board<Self::CellState>,
+    {
+        let (survive, birth) = HIGHLIFE_MASKS;
+        bitchunk::update_life(self, board, generation, survive, birth)
+    }
+    fn pattern_formats(&self) -> Vec<PatternFormat> {
+        PatternFormat::all()
+    }
+
+    fn pattern_rule(&self) -> Option<String> {
+        pattern::rule_string(&[2, 3], &[3, 6], &N::default())
+    }
+
+    fn load_pattern(
+        &self,
+        format: PatternFormat,
+        content: &str,
+    ) -> anyhow::Result<ClipBoard<Self::CellState>> {
+        pattern::load(format, content)
+    }
+
+    fn save_pattern(
+        &self,
+        format: PatternFormat,
+        cb: &ClipBoard<Self::CellState>,
+    ) -> anyhow::Result<String> {
+        pattern::save(format, cb, self.pattern_rule().as_deref())
+    }
+}
+
+impl LifeRule for HighLifeRule<MooreNeighborhood> {
+    fn survive_birth_masks(&self) -> (u16, u16) {
+        HIGHLIFE_MASKS
+    }
+}
+
+#[cfg(feature = "gui")]
+impl<N: Neighbors> RuleUi for HighLifeRule<N> {
+    fn background(&self) -> egui::Color32 {
+        self.background
+    }
+
+    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
+        Ok(if *st == LifeGameState::Dead { self.dead_color } else { self.alive_color })
+    }
+
     fn ui(
         &mut self,
         ui: &mut egui::Ui,
         _ctx: &egui::Context,
         _on_side_panel: bool,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<bool> {
         ui.hyperlink_to(
             "Highlife (cellular automaton) - Wikipedia",
             "https://en.wikipedia.org/wiki/Highlife_(cellular_automaton)",
@@ -248,85 +358,111 @@ impl<N: Neighbors> Rule for HighLifeRule<N> {
             &mut self.dead_color,
             egui::widgets::color_picker::Alpha::Opaque,
         );
-        Ok(())
+        Ok(false)
     }
 }
 
 // ----------------------------------------------------------------------------
 
 pub struct LifeLikeGameRule<N> {
-    neighbors: std::marker::PhantomData<N>,
+    neighborhood: N,
 
-    survive: ArrayVec<u32, 9>, // number of neighboring cells is in [0, 8]
-    birth: ArrayVec<u32, 9>,
+    survive: Vec<u32>, // numbers of live neighbors
+    birth: Vec<u32>,
 
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
     rule: String,
+    #[cfg(feature = "gui")]
     show_err_msg_about_rule: bool,
 
+    #[cfg(feature = "gui")]
     background: egui::Color32,
+    #[cfg(feature = "gui")]
     alive_color: egui::Color32,
+    #[cfg(feature = "gui")]
     dead_color: egui::Color32,
 }
 
-impl<N> Default for LifeLikeGameRule<N> {
+impl<N: Default> Default for LifeLikeGameRule<N> {
     fn default() -> Self {
-        let mut survive = ArrayVec::new();
-        survive.push(2_u32);
-        survive.push(3_u32);
-
-        let mut birth = ArrayVec::new();
-        birth.push(3_u32);
-
         Self {
-            neighbors: std::marker::PhantomData,
-            survive,
-            birth,
+            neighborhood: N::default(),
+            survive: vec![2, 3],
+            birth: vec![3],
             rule: "23/3".to_string(),
+            #[cfg(feature = "gui")]
             show_err_msg_about_rule: false,
+            #[cfg(feature = "gui")]
             background: egui::Color32::from_rgb(24, 128, 24),
+            #[cfg(feature = "gui")]
             alive_color: egui::Color32::from_rgb(24, 255, 24),
+            #[cfg(feature = "gui")]
             dead_color: egui::Color32::from_rgb(24, 24, 24),
         }
     }
 }
 
-impl<N> LifeLikeGameRule<N> {
+impl<N: Default> LifeLikeGameRule<N> {
     pub fn new(survive: Vec<u32>, birth: Vec<u32>) -> Self {
-        let rule = format!(
-            "{}/{}",
-            survive.iter().fold("".to_string(), |acc, x| acc + x.to_string().as_str()),
-            birth.iter().fold("".to_string(), |acc, x| acc + x.to_string().as_str())
-        );
+        let rule = format!("{}/{}", Self::counts_string(&survive), Self::counts_string(&birth));
         Self {
-            neighbors: std::marker::PhantomData,
-            survive: ArrayVec::from_iter(survive.into_iter()),
-            birth: ArrayVec::from_iter(birth.into_iter()),
+            neighborhood: N::default(),
+            survive,
+            birth,
             rule,
+            #[cfg(feature = "gui")]
             show_err_msg_about_rule: false,
+            #[cfg(feature = "gui")]
             background: egui::Color32::from_rgb(0, 128, 0),
+            #[cfg(feature = "gui")]
             alive_color: egui::Color32::from_rgb(0, 255, 0),
+            #[cfg(feature = "gui")]
             dead_color: egui::Color32::from_rgb(0, 0, 0),
         }
     }
 
+    pub fn with_neighborhood(mut self, neighborhood: N) -> Self {
+        self.neighborhood = neighborhood;
+        self
+    }
+
     pub fn is_valid_rule(rule: &str) -> bool {
-        let has_separator = rule.chars().filter(|c| *c == '/').count() == 1;
-        let has_digit_only = rule.chars().all(|c| c.is_ascii_digit() || c == '/');
-        has_separator && has_digit_only
+        Self::parse_rule(rule).is_some()
     }
 
+    /// Parse `{survive}/{birth}` rule. Each digit is a number of live neighbors,
+    /// e.g. `23/3`. For larger neighborhoods, numbers and ranges are separated
+    /// by commas, e.g. `34-58/34-45`.
     pub fn parse_rule(rule: &str) -> Option<(Vec<u32>, Vec<u32>)> {
-        if !Self::is_valid_rule(rule) {
-            return None;
-        }
-
-        // convert `23/3` into [2, 3] and [3]
-        let s_b: Vec<&str> = rule.split('/').collect();
+        let (survive, birth) = rule.split_once('/')?;
+        Some((Self::parse_counts(survive)?, Self::parse_counts(birth)?))
+    }
 
-        let survive = s_b[0].chars().map(|c| c.to_digit(10).unwrap()).collect();
-        let birth = s_b[1].chars().map(|c| c.to_digit(10).unwrap()).collect();
+    fn parse_counts(counts: &str) -> Option<Vec<u32>> {
+        if !counts.contains([',', '-']) {
+            // convert `23` into [2, 3]
+            return counts.chars().map(|c| c.to_digit(10)).collect();
+        }
+        let mut ns = Vec::new();
+        for item in counts.split(',') {
+            match item.split_once('-') {
+                Some((lower, upper)) => {
+                    let lower: u32 = lower.trim().parse().ok()?;
+                    let upper: u32 = upper.trim().parse().ok()?;
+                    ns.extend(lower..=upper);
+                }
+                None => ns.push(item.trim().parse().ok()?),
+            }
+        }
+        Some(ns)
+    }
 
-        Some((survive, birth))
+    fn counts_string(ns: &[u32]) -> String {
+        if ns.iter().all(|n| *n < 10) {
+            ns.iter().map(|n| n.to_string()).collect()
+        } else {
+            ns.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(",")
+        }
     }
 
     pub fn from_rule(rule: &str) -> Self {
@@ -336,18 +472,21 @@ impl<N> LifeLikeGameRule<N> {
 
         LifeLikeGameRule::new(survive, birth)
     }
+
+    // the numbers of live neighbors to survive and to be born, as bit masks.
+    // they are used only with less than 16 neighbors.
+    fn masks(&self) -> (u16, u16) {
+        let mask = |ns: &[u32]| ns.iter().filter(|n| **n < 16).fold(0, |m, n| m | (1 << n));
+        (mask(&self.survive), mask(&self.birth))
+    }
 }
 
 impl<N: Neighbors> Rule for LifeLikeGameRule<N> {
     type CellState = LifeGameState;
     type Neighborhood = N;
 
-    fn background(&self) -> egui::Color32 {
-        self.background
-    }
-
-    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
-        Ok(if *st == LifeGameState::Dead { self.dead_color } else { self.alive_color })
+    fn neighborhood(&self) -> N {
+        self.neighborhood.clone()
     }
 
     fn default_state(&self) -> anyhow::Result<Self::CellState> {
@@ -370,10 +509,12 @@ impl<N: Neighbors> Rule for LifeLikeGameRule<N> {
     fn update(
         &self,
         center: Self::CellState,
-        neighbor: impl Iterator<Item = Self::CellState>,
+        neighbor: impl Iterator<Item = ((isize, isize), Self::CellState)>,
+        _ctx: &UpdateContext,
     ) -> anyhow::Result<Self::CellState> {
         let center_is_alive = center == LifeGameState::Alive;
-        let n_alive: u32 = neighbor.map(|c| if c == LifeGameState::Alive { 1 } else { 0 }).sum();
+        let n_alive: u32 =
+            neighbor.map(|(_, c)| if c == LifeGameState::Alive { 1 } else { 0 }).sum();
 
         let meet_survive_rule = self.survive.iter().any(|n| *n == n_alive);
         let meet_birth_rule = self.birth.iter().any(|n| *n == n_alive);
@@ -387,18 +528,67 @@ impl<N: Neighbors> Rule for LifeLikeGameRule<N> {
         )
     }
 
+    fn update_board<B>(&self, board: &mut B, generation: u64) -> anyhow::Result<()>
+    where
+        B: Board<Self::CellState>,
+    {
+        let (survive, birth) = self.masks();
+        bitchunk::update_life(self, board, generation, survive, birth)
+    }
+    fn pattern_formats(&self) -> Vec<PatternFormat> {
+        PatternFormat::all()
+    }
+
+    fn pattern_rule(&self) -> Option<String> {
+        pattern::rule_string(&self.survive, &self.birth, &self.neighborhood)
+    }
+
+    fn load_pattern(
+        &self,
+        format: PatternFormat,
+        content: &str,
+    ) -> anyhow::Result<ClipBoard<Self::CellState>> {
+        pattern::load(format, content)
+    }
+
+    fn save_pattern(
+        &self,
+        format: PatternFormat,
+        cb: &ClipBoard<Self::CellState>,
+    ) -> anyhow::Result<String> {
+        pattern::save(format, cb, self.pattern_rule().as_deref())
+    }
+}
+
+impl LifeRule for LifeLikeGameRule<MooreNeighborhood> {
+    fn survive_birth_masks(&self) -> (u16, u16) {
+        self.masks()
+    }
+}
+
+#[cfg(feature = "gui")]
+impl<N: NeighborsUi> RuleUi for LifeLikeGameRule<N> {
+    fn background(&self) -> egui::Color32 {
+        self.background
+    }
+
+    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
+        Ok(if *st == LifeGameState::Dead { self.dead_color } else { self.alive_color })
+    }
+
     fn ui(
         &mut self,
         ui: &mut egui::Ui,
         _ctx: &egui::Context,
         _on_side_panel: bool,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<bool> {
         ui.hyperlink_to(
             "Life-like cellular automaton - Wikipedia",
             "https://en.wikipedia.org/wiki/Life-like_cellular_automaton",
         );
         ui.separator();
 
+        let mut changed = false;
         ui.label("rule {survive}/{birth} (e.g. `23/3`)");
         ui.horizontal_wrapped(|ui| {
             if ui.add(egui::TextEdit::singleline(&mut self.rule)).changed() {
@@ -407,8 +597,9 @@ impl<N: Neighbors> Rule for LifeLikeGameRule<N> {
 
             if ui.button("Apply").clicked() {
                 if let Some((survive, birth)) = Self::parse_rule(&self.rule) {
-                    self.survive = ArrayVec::from_iter(survive.into_iter());
-                    self.birth = ArrayVec::from_iter(birth.into_iter());
+                    self.survive = survive;
+                    self.birth = birth;
+                    changed = true;
                 } else {
                     self.show_err_msg_about_rule = true;
                 }
@@ -419,6 +610,8 @@ impl<N: Neighbors> Rule for LifeLikeGameRule<N> {
         }
         ui.separator();
 
+        changed |= self.neighborhood.ui(ui);
+
         ui.label("Grid Color");
         egui::widgets::color_picker::color_edit_button_srgba(
             ui,
@@ -441,6 +634,6 @@ impl<N: Neighbors> Rule for LifeLikeGameRule<N> {
             &mut self.dead_color,
             egui::widgets::color_picker::Alpha::Opaque,
         );
-        Ok(())
+        Ok(changed)
     }
 }
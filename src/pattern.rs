@@ -0,0 +1,753 @@
+//! Pattern file formats of life-like cellular automata.
+//!
+//! Currently, the following formats are supported.
+//!
+//! - Run Length Encoded (`.rle`)
+//!   - <https://conwaylife.com/wiki/Run_Length_Encoded>
+//! - Plaintext (`.cells`)
+//!   - <https://conwaylife.com/wiki/Plaintext>
+//! - Life 1.06 (`.lif`, `.life`)
+//!   - <https://conwaylife.com/wiki/Life_1.06>
+//!
+use crate::board::ClipBoard;
+use crate::lifegame::LifeGameState;
+use crate::rule::{ConfigurableNeighborhood, HexGridNeighborhood, Neighbors};
+
+use anyhow::anyhow;
+use anyhow::Context as _;
+
+/// Pattern file formats.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PatternFormat {
+    Rle,
+    Plaintext,
+    Life106,
+}
+
+impl PatternFormat {
+    pub fn all() -> Vec<Self> {
+        vec![PatternFormat::Rle, PatternFormat::Plaintext, PatternFormat::Life106]
+    }
+
+    /// Guess the format from the extension of a file.
+    pub fn from_filename(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name.ends_with(".rle") {
+            Some(PatternFormat::Rle)
+        } else if name.ends_with(".cells") {
+            Some(PatternFormat::Plaintext)
+        } else if name.ends_with(".lif") || name.ends_with(".life") {
+            Some(PatternFormat::Life106)
+        } else {
+            None
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PatternFormat::Rle => "rle",
+            PatternFormat::Plaintext => "cells",
+            PatternFormat::Life106 => "lif",
+        }
+    }
+}
+
+/// Convert `{survive}/{birth}` rule into `B{birth}/S{survive}` notation.
+///
+/// Following Golly, `H` is appended for hexagonal neighborhood and `V` for
+/// von Neumann neighborhood. If a count is 10 or more, or the neighborhood is
+/// wider, the rule is written in Larger than Life notation, e.g.
+/// `R2,C2,M0,S6-9,B7-8,NM`, for square and diamond neighborhoods. None for
+/// other neighborhoods, which have no notation.
+pub fn rule_string<N: Neighbors>(
+    survive: &[u32],
+    birth: &[u32],
+    neighborhood: &N,
+) -> Option<String> {
+    let range = neighborhood.range();
+    // the same cells in any order
+    fn same<A: Neighbors, B: Neighbors>(a: &A, b: &B) -> bool {
+        let sorted = |offsets: &[(isize, isize)]| {
+            let mut offsets = offsets.to_vec();
+            offsets.sort_unstable();
+            offsets
+        };
+        (0..2).all(|y| sorted(a.offsets(y)) == sorted(b.offsets(y)))
+    }
+    let is = |other: &ConfigurableNeighborhood| same(neighborhood, other);
+    let kind = if range == 1 && same(neighborhood, &HexGridNeighborhood::default()) {
+        'H'
+    } else if is(&ConfigurableNeighborhood::moore(range)) {
+        'M'
+    } else if is(&ConfigurableNeighborhood::von_neumann(range)) {
+        'N'
+    } else {
+        return None;
+    };
+
+    if range == 1 && survive.iter().chain(birth.iter()).all(|n| *n < 10) {
+        let digits = |ns: &[u32]| ns.iter().map(|n| n.to_string()).collect::<String>();
+        let suffix = match kind {
+            'H' => "H",
+            'N' => "V",
+            _ => "",
+        };
+        return Some(format!("B{}/S{}{}", digits(birth), digits(survive), suffix));
+    }
+    Some(format!(
+        "R{},C2,M0,S{},B{},N{}",
+        range,
+        intervals_string(survive),
+        intervals_string(birth),
+        kind
+    ))
+}
+
+// `[2, 3, 4, 6]` into `2-4,6`
+fn intervals_string(ns: &[u32]) -> String {
+    let mut ns = ns.to_vec();
+    ns.sort_unstable();
+    ns.dedup();
+    let mut intervals: Vec<(u32, u32)> = Vec::new();
+    for n in ns {
+        match intervals.last_mut() {
+            Some((_, upper)) if *upper + 1 == n => *upper = n,
+            _ => intervals.push((n, n)),
+        }
+    }
+    let items: Vec<String> = intervals
+        .iter()
+        .map(|(l, u)| if l == u { l.to_string() } else { format!("{}-{}", l, u) })
+        .collect();
+    items.join(",")
+}
+
+/// Read a pattern file as a clipboard. Dead cells become `None`.
+///
+/// The rule written in the file is not applied. See `rule_of`.
+pub fn load(format: PatternFormat, content: &str) -> anyhow::Result<ClipBoard<LifeGameState>> {
+    match format {
+        PatternFormat::Rle => load_rle(content),
+        PatternFormat::Plaintext => load_plaintext(content),
+        PatternFormat::Life106 => load_life106(content),
+    }
+}
+
+/// The rule written in a pattern file, if the format has a field for it.
+pub fn rule_of(format: PatternFormat, content: &str) -> Option<String> {
+    match format {
+        PatternFormat::Rle => rle_rule(content),
+        PatternFormat::Plaintext | PatternFormat::Life106 => None,
+    }
+}
+
+/// Whether two notations, e.g. `B3/S23` and `23/3`, are the same rule.
+pub fn is_same_rule(a: &str, b: &str) -> bool {
+    match (parse_rule(a), parse_rule(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.trim().eq_ignore_ascii_case(b.trim()),
+    }
+}
+
+// `B3/S23`, `S23/B3` or `23/3` (survive/birth) into the sorted digits of
+// birth and survive, and the suffix of the neighborhood. A suffix after `:`,
+// e.g. `:T20,20` of a bounded grid, is ignored.
+fn parse_rule(rule: &str) -> Option<(Vec<u32>, Vec<u32>, String)> {
+    let rule = rule.split(':').next().unwrap_or_default().trim().to_ascii_uppercase();
+    if rule.starts_with('R') {
+        return parse_ltl_rule(&rule);
+    }
+    let (first, second) = rule.split_once('/')?;
+    let (second, suffix) = second.split_at(second.find(&['H', 'V'][..]).unwrap_or(second.len()));
+    let (birth, survive) = match (first.strip_prefix('B'), second.strip_prefix('S')) {
+        (Some(b), Some(s)) => (b, s),
+        _ => match (first.strip_prefix('S'), second.strip_prefix('B')) {
+            (Some(s), Some(b)) => (b, s),
+            _ => (second, first),
+        },
+    };
+    let digits = |s: &str| -> Option<Vec<u32>> {
+        let mut ds = s.chars().map(|c| c.to_digit(10)).collect::<Option<Vec<u32>>>()?;
+        ds.sort_unstable();
+        Some(ds)
+    };
+    Some((digits(birth)?, digits(survive)?, suffix.to_string()))
+}
+
+// Larger than Life notation, `R2,C2,M0,S6-9,B7-8,NM`. Golly writes intervals as
+// `6..9`. Counts can also be listed as `S2,4-5`. Range 1 has the same suffix
+// as `B3/S23V`, so that the two notations of a rule are the same.
+fn parse_ltl_rule(rule: &str) -> Option<(Vec<u32>, Vec<u32>, String)> {
+    let mut range = 1;
+    let mut middle = false;
+    let mut kind = 'M';
+    let (mut birth, mut survive) = (Vec::new(), Vec::new());
+    let mut counts: Option<&mut Vec<u32>> = None;
+    for item in rule.split(',').map(|item| item.trim()) {
+        let key = item.chars().next()?;
+        let value = if key.is_ascii_digit() { item } else { &item[key.len_utf8()..] };
+        match key {
+            'R' => range = value.parse().ok()?,
+            'C' => {
+                // the number of states. 0 is the same as 2
+                if !matches!(value, "0" | "2") {
+                    return None;
+                }
+            }
+            'M' => middle = value.parse::<u32>().ok()? == 1,
+            'N' => kind = value.chars().next().filter(|k| matches!(k, 'M' | 'N'))?,
+            'S' | 'B' => {
+                let ns = if key == 'S' { &mut survive } else { &mut birth };
+                parse_interval(value, ns)?;
+                counts = Some(ns);
+            }
+            _ if key.is_ascii_digit() => parse_interval(value, counts.as_mut()?)?,
+            _ => return None,
+        }
+    }
+    if middle {
+        // a live center is counted in survival
+        survive = survive.into_iter().filter(|n| 0 < *n).map(|n| n - 1).collect();
+    }
+    for ns in [&mut birth, &mut survive] {
+        ns.sort_unstable();
+        ns.dedup();
+    }
+    let suffix = match (range, kind) {
+        (1, 'M') => String::new(),
+        (1, _) => "V".to_string(),
+        _ => format!("R{}N{}", range, kind),
+    };
+    Some((birth, survive, suffix))
+}
+
+// `6-9`, `6..9` or `6` into the counts. an empty one is no count.
+fn parse_interval(value: &str, ns: &mut Vec<u32>) -> Option<()> {
+    if value.is_empty() {
+        return Some(());
+    }
+    let (lower, upper) = value
+        .split_once("..")
+        .or_else(|| value.split_once('-'))
+        .unwrap_or((value, value));
+    let (lower, upper): (u32, u32) = (lower.parse().ok()?, upper.parse().ok()?);
+    // no neighborhood has so many neighbors
+    if 1024 < upper {
+        return None;
+    }
+    ns.extend(lower..=upper);
+    Some(())
+}
+
+/// Write a clipboard as a pattern file. Both `None` and `Dead` are written as dead cells.
+///
+/// `rule` is written only if the format has a field for it. RLE needs it.
+pub fn save(
+    format: PatternFormat,
+    cb: &ClipBoard<LifeGameState>,
+    rule: Option<&str>,
+) -> anyhow::Result<String> {
+    match format {
+        PatternFormat::Rle => {
+            let rule = rule.ok_or_else(|| anyhow!("the rule has no notation to write in RLE"))?;
+            Ok(save_rle(cb, rule))
+        }
+        PatternFormat::Plaintext => Ok(save_plaintext(cb)),
+        PatternFormat::Life106 => Ok(save_life106(cb)),
+    }
+}
+
+// the maximum width and height of a pattern. files describing a larger one
+// are refused instead of allocating the cells.
+const MAX_PATTERN_LEN: usize = 4096;
+
+fn check_size(w: usize, h: usize) -> anyhow::Result<()> {
+    if MAX_PATTERN_LEN < w || MAX_PATTERN_LEN < h {
+        return Err(anyhow!(
+            "pattern of {}x{} is larger than {}x{}",
+            w,
+            h,
+            MAX_PATTERN_LEN,
+            MAX_PATTERN_LEN
+        ));
+    }
+    Ok(())
+}
+
+fn is_alive(cb: &ClipBoard<LifeGameState>, x: usize, y: usize) -> bool {
+    *cb.cell_at(x, y) == Some(LifeGameState::Alive)
+}
+
+// convert a list of live cells into a clipboard. cells outside of (w, h) expand it.
+fn from_alive_cells(
+    w: usize,
+    h: usize,
+    alive: &[(usize, usize)],
+) -> anyhow::Result<ClipBoard<LifeGameState>> {
+    let w = alive.iter().map(|(x, _)| x + 1).fold(w, usize::max);
+    let h = alive.iter().map(|(_, y)| y + 1).fold(h, usize::max);
+    if w == 0 || h == 0 {
+        return Err(anyhow!("pattern is empty"));
+    }
+    check_size(w, h)?;
+    let mut cb = ClipBoard::new(w, h);
+    for (x, y) in alive.iter() {
+        *cb.cell_at_mut(*x, *y) = Some(LifeGameState::Alive);
+    }
+    Ok(cb)
+}
+
+// ---------------------------------------------------------------------------
+// RLE
+//
+// ```
+// #N Glider
+// #C comment
+// x = 3, y = 3, rule = B3/S23
+// bob$2bo$3o!
+// ```
+
+fn load_rle(content: &str) -> anyhow::Result<ClipBoard<LifeGameState>> {
+    let mut w = 0;
+    let mut h = 0;
+    let mut alive = Vec::new();
+
+    let mut x = 0;
+    let mut y = 0;
+    let mut run: Option<usize> = None;
+
+    'lines: for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('x') {
+            // header line, `x = m, y = n, rule = abc`
+            for item in line.split(',') {
+                let (key, value) =
+                    item.split_once('=').ok_or_else(|| anyhow!("invalid RLE header: {}", line))?;
+                match key.trim() {
+                    "x" => w = value.trim().parse().context("invalid width in RLE header")?,
+                    "y" => h = value.trim().parse().context("invalid height in RLE header")?,
+                    // the rest is the rule, read by `rle_rule`. it can contain `,`
+                    "rule" => break,
+                    _ => {}
+                }
+            }
+            check_size(w, h)?;
+            continue;
+        }
+
+        for c in line.chars() {
+            match c {
+                '0'..='9' => {
+                    let d = c.to_digit(10).expect("already checked") as usize;
+                    // no run can be longer than a pattern
+                    let n = run.unwrap_or(0).checked_mul(10).and_then(|n| n.checked_add(d));
+                    run =
+                        Some(n.filter(|n| *n <= MAX_PATTERN_LEN).ok_or_else(|| {
+                            anyhow!("run count in RLE exceeds {}", MAX_PATTERN_LEN)
+                        })?);
+                }
+                'b' | '.' => {
+                    x = advance(x, run.take().unwrap_or(1))?;
+                }
+                '$' => {
+                    y = advance(y, run.take().unwrap_or(1))?;
+                    x = 0;
+                }
+                '!' => break 'lines,
+                c if c.is_ascii_alphabetic() => {
+                    // `o` for two-state rules. multi-state letters are also live cells.
+                    let end = advance(x, run.take().unwrap_or(1))?;
+                    if y == MAX_PATTERN_LEN {
+                        return Err(anyhow!("RLE pattern exceeds {} rows", MAX_PATTERN_LEN));
+                    }
+                    alive.extend((x..end).map(|x| (x, y)));
+                    x = end;
+                }
+                c if c.is_whitespace() => {}
+                _ => return Err(anyhow!("unexpected character in RLE: {:?}", c)),
+            }
+        }
+    }
+    from_alive_cells(w, h, &alive)
+}
+
+// move `pos` by a run of cells or rows, within a pattern.
+fn advance(pos: usize, run: usize) -> anyhow::Result<usize> {
+    // both are at most MAX_PATTERN_LEN
+    let pos = pos + run;
+    if MAX_PATTERN_LEN < pos {
+        return Err(anyhow!("RLE pattern exceeds {} cells", MAX_PATTERN_LEN));
+    }
+    Ok(pos)
+}
+
+// the `rule` field of the header line
+fn rle_rule(content: &str) -> Option<String> {
+    let header = content
+        .lines()
+        .map(|l| l.trim())
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter(|l| l.starts_with('x'))?;
+    // the rule continues to the end of the line, e.g. `B3/S23:T20,20`
+    let mut start = 0;
+    for item in header.split(',') {
+        if let Some((key, _)) = item.split_once('=') {
+            if key.trim() == "rule" {
+                return Some(header[start + key.len() + 1..].trim().to_string());
+            }
+        }
+        start += item.len() + 1;
+    }
+    None
+}
+
+// a line of RLE should not exceed 70 characters.
+const MAX_LINE_LEN: usize = 70;
+
+fn save_rle(cb: &ClipBoard<LifeGameState>, rule: &str) -> String {
+    // `3o` for `ooo`. run count 1 is omitted.
+    fn push(items: &mut Vec<String>, n: usize, tag: char) {
+        if n == 1 {
+            items.push(tag.to_string());
+        } else if 1 < n {
+            items.push(format!("{}{}", n, tag));
+        }
+    }
+
+    let mut items: Vec<String> = Vec::new();
+
+    let mut newlines = 0;
+    for y in 0..cb.height() {
+        let mut x = 0;
+        while x < cb.width() {
+            let state = is_alive(cb, x, y);
+            let mut n = 1;
+            while x + n < cb.width() && is_alive(cb, x + n, y) == state {
+                n += 1;
+            }
+            if state {
+                push(&mut items, newlines, '$');
+                newlines = 0;
+                push(&mut items, n, 'o');
+            } else if x + n < cb.width() {
+                push(&mut items, newlines, '$');
+                newlines = 0;
+                push(&mut items, n, 'b');
+            } // trailing dead cells can be omitted
+            x += n;
+        }
+        newlines += 1;
+    }
+    items.push("!".to_string());
+
+    let mut rle = format!("x = {}, y = {}, rule = {}\n", cb.width(), cb.height(), rule);
+    let mut line_len = 0;
+    for item in items.iter() {
+        if MAX_LINE_LEN < line_len + item.len() {
+            rle.push('\n');
+            line_len = 0;
+        }
+        rle.push_str(item);
+        line_len += item.len();
+    }
+    rle.push('\n');
+    rle
+}
+
+// ---------------------------------------------------------------------------
+// Plaintext
+//
+// ```
+// !Name: Glider
+// !comment
+// .O.
+// ..O
+// OOO
+// ```
+
+fn load_plaintext(content: &str) -> anyhow::Result<ClipBoard<LifeGameState>> {
+    let mut alive = Vec::new();
+    let mut y = 0;
+    let mut w = 0;
+    for line in content.lines() {
+        let line = line.trim_end();
+        if line.starts_with('!') {
+            continue;
+        }
+        for (x, c) in line.chars().enumerate() {
+            match c {
+                '.' => {}
+                'O' | '*' => alive.push((x, y)),
+                _ => return Err(anyhow!("unexpected character in plaintext: {:?}", c)),
+            }
+        }
+        w = w.max(line.chars().count());
+        y += 1;
+    }
+    from_alive_cells(w, y, &alive)
+}
+
+fn save_plaintext(cb: &ClipBoard<LifeGameState>) -> String {
+    let mut cells = String::new();
+    for y in 0..cb.height() {
+        for x in 0..cb.width() {
+            cells.push(if is_alive(cb, x, y) { 'O' } else { '.' });
+        }
+        cells.push('\n');
+    }
+    cells
+}
+
+// ---------------------------------------------------------------------------
+// Life 1.06
+//
+// ```
+// #Life 1.06
+// 0 -1
+// 1 0
+// -1 1
+// 0 1
+// 1 1
+// ```
+
+fn load_life106(content: &str) -> anyhow::Result<ClipBoard<LifeGameState>> {
+    let mut lines = content.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+    match lines.next() {
+        Some("#Life 1.06") => {}
+        Some(header) => return Err(anyhow!("not a Life 1.06 file. header: {}", header)),
+        None => return Err(anyhow!("pattern is empty")),
+    }
+
+    let mut coords: Vec<(i64, i64)> = Vec::new();
+    for line in lines {
+        if line.starts_with('#') {
+            continue;
+        }
+        let mut xy = line.split_whitespace().map(|v| v.parse::<i64>());
+        match (xy.next(), xy.next(), xy.next()) {
+            (Some(x), Some(y), None) => coords.push((
+                x.context(format!("invalid coordinate: {}", line))?,
+                y.context(format!("invalid coordinate: {}", line))?,
+            )),
+            _ => return Err(anyhow!("line should be `x y`: {}", line)),
+        }
+    }
+
+    // coordinates can be negative. move the bounding box to the origin.
+    let x0 = coords.iter().map(|(x, _)| *x).min().unwrap_or(0);
+    let y0 = coords.iter().map(|(_, y)| *y).min().unwrap_or(0);
+    let x1 = coords.iter().map(|(x, _)| *x).max().unwrap_or(0);
+    let y1 = coords.iter().map(|(_, y)| *y).max().unwrap_or(0);
+    // the extent can overflow before it is checked by `from_alive_cells`
+    let extent = |min: i64, max: i64| max.checked_sub(min).filter(|d| *d < MAX_PATTERN_LEN as i64);
+    if extent(x0, x1).is_none() || extent(y0, y1).is_none() {
+        return Err(anyhow!("pattern is larger than {}x{}", MAX_PATTERN_LEN, MAX_PATTERN_LEN));
+    }
+    let alive: Vec<(usize, usize)> =
+        coords.iter().map(|(x, y)| ((x - x0) as usize, (y - y0) as usize)).collect();
+    from_alive_cells(0, 0, &alive)
+}
+
+fn save_life106(cb: &ClipBoard<LifeGameState>) -> String {
+    let mut life = "#Life 1.06\n".to_string();
+    for y in 0..cb.height() {
+        for x in 0..cb.width() {
+            if is_alive(cb, x, y) {
+                life += &format!("{} {}\n", x, y);
+            }
+        }
+    }
+    life
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the size and the live cells of a clipboard
+    fn alive_cells(cb: &ClipBoard<LifeGameState>) -> (usize, usize, Vec<(usize, usize)>) {
+        let mut alive = Vec::new();
+        for y in 0..cb.height() {
+            for x in 0..cb.width() {
+                if is_alive(cb, x, y) {
+                    alive.push((x, y));
+                }
+            }
+        }
+        (cb.width(), cb.height(), alive)
+    }
+
+    const GLIDER: &str = "#N Glider\nx = 3, y = 3, rule = B3/S23\nbob$2bo$3o!\n";
+
+    #[test]
+    fn rle_glider_round_trip() {
+        let cb = load_rle(GLIDER).unwrap();
+        assert_eq!(alive_cells(&cb), (3, 3, vec![(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)]));
+        let saved = save_rle(&cb, "B3/S23");
+        assert_eq!(saved, "x = 3, y = 3, rule = B3/S23\nbo$2bo$3o!\n");
+        assert_eq!(alive_cells(&load_rle(&saved).unwrap()), alive_cells(&cb));
+    }
+
+    #[test]
+    fn rle_run_counts() {
+        // multi-digit runs and `3$` for two empty rows
+        let cb = load_rle("x = 14, y = 4\n12bo$o3$11b3o!").unwrap();
+        let (w, h, alive) = alive_cells(&cb);
+        assert_eq!((w, h), (14, 5));
+        assert_eq!(alive, vec![(12, 0), (0, 1), (11, 4), (12, 4), (13, 4)]);
+        let saved = save_rle(&cb, "B3/S23");
+        assert!(saved.contains("12bo$o3$11b3o!"), "{}", saved);
+        assert_eq!(alive_cells(&load_rle(&saved).unwrap()), (w, h, alive));
+    }
+
+    #[test]
+    fn rle_without_trailing_bang() {
+        let cb = load_rle("x = 3, y = 3\nbob$2bo$3o\n").unwrap();
+        assert_eq!(alive_cells(&cb), alive_cells(&load_rle(GLIDER).unwrap()));
+    }
+
+    #[test]
+    fn rle_lines_are_wrapped() {
+        // alternating cells make a long run of short items
+        let mut cb = ClipBoard::new(200, 3);
+        for y in 0..3 {
+            for x in (y % 2..200).step_by(2) {
+                *cb.cell_at_mut(x, y) = Some(LifeGameState::Alive);
+            }
+        }
+        let saved = save_rle(&cb, "B3/S23");
+        assert!(5 < saved.lines().count());
+        assert!(saved.lines().all(|l| l.len() <= MAX_LINE_LEN), "{}", saved);
+        assert_eq!(alive_cells(&load_rle(&saved).unwrap()), alive_cells(&cb));
+    }
+
+    #[test]
+    fn rle_too_large() {
+        // too many digits to fit in usize
+        assert!(load_rle("x = 1, y = 1\n99999999999999999999999o!").is_err());
+        assert!(load_rle(&format!("x = 1, y = 1\n{}o!", MAX_PATTERN_LEN + 1)).is_err());
+        assert!(load_rle("x = 100000, y = 1\no!").is_err());
+        assert!(load_rle("x = 1, y = 1\n4000b4000bo!").is_err());
+        assert!(load_rle("x = 1, y = 1\n4000$4000$o!").is_err());
+        assert!(load_rle(&format!("x = 1, y = 1\n{}$o!", MAX_PATTERN_LEN)).is_err());
+
+        let (w, h, alive) = alive_cells(&load_rle("x = 1, y = 1\n4095$4095bo!").unwrap());
+        assert_eq!((w, h), (MAX_PATTERN_LEN, MAX_PATTERN_LEN));
+        assert_eq!(alive, vec![(4095, 4095)]);
+    }
+
+    #[test]
+    fn rle_rule_in_header() {
+        assert_eq!(rule_of(PatternFormat::Rle, GLIDER).as_deref(), Some("B3/S23"));
+        assert_eq!(rule_of(PatternFormat::Rle, "x = 1, y = 1\no!"), None);
+        assert_eq!(rule_of(PatternFormat::Plaintext, "O"), None);
+
+        assert!(is_same_rule("B3/S23", "b3/s32"));
+        assert!(is_same_rule("B3/S23", "23/3"));
+        assert!(is_same_rule("B36/S23", "S23/B63"));
+        assert!(is_same_rule("B2/S34H", "b2/s34h:T20,20"));
+        assert!(!is_same_rule("B3/S23", "B36/S23"));
+        assert!(!is_same_rule("B2/S34H", "B2/S34"));
+        assert!(is_same_rule("WireWorld", "wireworld"));
+
+        let bounded = "x = 3, y = 3, rule = B3/S23:T20,20\nbob$2bo$3o!\n";
+        assert_eq!(rule_of(PatternFormat::Rle, bounded).as_deref(), Some("B3/S23:T20,20"));
+        assert_eq!(
+            alive_cells(&load_rle(bounded).unwrap()),
+            alive_cells(&load_rle(GLIDER).unwrap())
+        );
+    }
+
+    #[test]
+    fn rule_string_of_neighborhoods() {
+        use crate::rule::{MooreNeighborhood, VonNeumannNeighborhood};
+
+        let moore = MooreNeighborhood::default();
+        assert_eq!(rule_string(&[2, 3], &[3], &moore).as_deref(), Some("B3/S23"));
+        let hex = HexGridNeighborhood::default();
+        assert_eq!(rule_string(&[3, 4], &[2], &hex).as_deref(), Some("B2/S34H"));
+        let von_neumann = VonNeumannNeighborhood::default();
+        assert_eq!(rule_string(&[1], &[1, 2], &von_neumann).as_deref(), Some("B12/S1V"));
+        // equivalent to the above
+        let von_neumann = ConfigurableNeighborhood::von_neumann(1);
+        assert_eq!(rule_string(&[1], &[1, 2], &von_neumann).as_deref(), Some("B12/S1V"));
+
+        let wide = ConfigurableNeighborhood::moore(2);
+        let rule = rule_string(&[6, 7, 8, 9, 12], &[7, 8], &wide).unwrap();
+        assert_eq!(rule, "R2,C2,M0,S6-9,12,B7-8,NM");
+        let large = ConfigurableNeighborhood::moore(1);
+        assert_eq!(rule_string(&[2], &[3, 10], &large).unwrap(), "R1,C2,M0,S2,B3,10,NM");
+        assert_eq!(rule_string(&[2], &[3], &ConfigurableNeighborhood::circular(3)), None);
+    }
+
+    #[test]
+    fn larger_than_life_rules() {
+        // counts over 10 are not split into digits
+        assert!(!is_same_rule("R1,C2,M0,S2,B3,10,NM", "B3/S2"));
+        assert!(!is_same_rule("R1,C2,M0,S2,B3,10,NM", "B310/S2"));
+        assert!(is_same_rule("R1,C2,M0,S2-3,B3,NM", "B3/S23"));
+        assert!(is_same_rule("R1,C0,M1,S3-4,B3,NN", "B3/S23V"));
+        assert!(is_same_rule("R2,C2,M0,S6-9,12,B7-8,NM", "r2,c2,s6..9,12..12,b7,8,nm"));
+        assert!(!is_same_rule("R2,C2,M0,S6-9,B7-8,NM", "R2,C2,M0,S6-9,B7-8,NN"));
+        assert!(!is_same_rule("R2,C2,M0,S6-9,B7-8,NM", "R3,C2,M0,S6-9,B7-8,NM"));
+        assert_eq!(parse_rule("R2,C3,S2,B3,NM"), None);
+        assert_eq!(parse_rule("R2,C2,S2-4000000000,B3,NM"), None);
+
+        let cb = load_rle("x = 1, y = 1\no!").unwrap();
+        let saved = save(PatternFormat::Rle, &cb, Some("R2,C2,M0,S6-9,B7-8,NM")).unwrap();
+        assert_eq!(rule_of(PatternFormat::Rle, &saved).as_deref(), Some("R2,C2,M0,S6-9,B7-8,NM"));
+        assert!(save(PatternFormat::Rle, &cb, None).is_err());
+        assert!(save(PatternFormat::Plaintext, &cb, None).is_ok());
+    }
+
+    #[test]
+    fn life106_negative_coordinates() {
+        let content = "#Life 1.06\n#D comment\n0 -1\n1 0\n-1 1\n0 1\n1 1\n";
+        let cb = load_life106(content).unwrap();
+        assert_eq!(alive_cells(&cb), alive_cells(&load_rle(GLIDER).unwrap()));
+        assert_eq!(save_life106(&cb), "#Life 1.06\n1 0\n2 1\n0 2\n1 2\n2 2\n");
+    }
+
+    #[test]
+    fn life106_header_only() {
+        assert!(load_life106("#Life 1.06\n").is_err());
+        assert!(load_life106("").is_err());
+        assert!(load_life106("#Life 1.05\n0 0\n").is_err());
+        assert!(load_life106("#Life 1.06\n0 0 0\n").is_err());
+    }
+
+    #[test]
+    fn life106_too_large() {
+        assert!(load_life106("#Life 1.06\n0 0\n4096 0\n").is_err());
+        assert!(load_life106("#Life 1.06\n0 -2048\n0 2048\n").is_err());
+        let far = format!("#Life 1.06\n{} 0\n{} 0\n", i64::MIN, i64::MAX);
+        assert!(load_life106(&far).is_err());
+
+        let cb = load_life106("#Life 1.06\n-2048 0\n2047 0\n").unwrap();
+        assert_eq!((cb.width(), cb.height()), (MAX_PATTERN_LEN, 1));
+    }
+
+    #[test]
+    fn plaintext_too_wide() {
+        let row = ".".repeat(MAX_PATTERN_LEN) + "O\n";
+        assert!(load_plaintext(&row).is_err());
+        assert!(load_plaintext(&"O\n".repeat(MAX_PATTERN_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn plaintext_ragged_rows_and_comments() {
+        let content = "!Name: Glider\n!\n.O\n..O\nOOO\n";
+        let cb = load_plaintext(content).unwrap();
+        assert_eq!(alive_cells(&cb), alive_cells(&load_rle(GLIDER).unwrap()));
+        assert_eq!(save_plaintext(&cb), ".O.\n..O\nOOO\n");
+
+        // an empty row is a row of dead cells
+        let (w, h, alive) = alive_cells(&load_plaintext("O\n\n*..\n").unwrap());
+        assert_eq!((w, h, alive), (3, 3, vec![(0, 0), (0, 2)]));
+        assert!(load_plaintext("!only a comment\n").is_err());
+        assert!(load_plaintext(".x.\n").is_err());
+    }
+}
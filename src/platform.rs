@@ -0,0 +1,109 @@
+//! Platform-dependent file I/O.
+//!
+//! On web, a file is saved by downloading it and loaded by drag-and-drop.
+//! On native, a file is written to / read from the local file system.
+
+use anyhow::anyhow;
+use anyhow::Context as _;
+
+// how long the URL of a downloaded file is kept
+#[cfg(target_arch = "wasm32")]
+const REVOKE_DELAY_MS: i32 = 60_000;
+
+/// Save `content` as a file.
+///
+/// On web, it lets the browser download `content` as `filename`.
+/// On native, `filename` is a path to the file to be written.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn save_file(filename: &str, mime: &str, content: &[u8]) -> anyhow::Result<()> {
+    use wasm_bindgen::JsCast;
+
+    // JsValue does not satisfy trait bound of anyhow context
+    let js_error = |e: wasm_bindgen::JsValue| anyhow!("{:?}", e);
+
+    let uint8arr = js_sys::Uint8Array::new_with_length(content.len() as u32);
+    uint8arr.copy_from(content);
+
+    let array = js_sys::Array::new();
+    array.push(&uint8arr.buffer());
+
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(
+        &array,
+        web_sys::BlobPropertyBag::new().type_(mime),
+    )
+    .map_err(js_error)
+    .context("Failed to create Blob")?;
+
+    let url = web_sys::Url::create_object_url_with_blob(&blob)
+        .map_err(js_error)
+        .context("Failed to create URL of Blob")?;
+
+    let window = web_sys::window().ok_or_else(|| anyhow!("window is not found"))?;
+    let document = window.document().ok_or_else(|| anyhow!("document is not found"))?;
+    let downloadable = document
+        .create_element("a")
+        .map_err(js_error)
+        .context("Failed to create element")?;
+
+    downloadable.set_attribute("href", &url).map_err(js_error)?;
+    downloadable.set_attribute("download", filename).map_err(js_error)?;
+    downloadable
+        .dyn_into::<web_sys::HtmlElement>()
+        .map_err(|e| anyhow!("{:?}", e))?
+        .click();
+
+    // the browser may start the download after `click` returns, so the URL
+    // is revoked later
+    let revoke = wasm_bindgen::closure::Closure::once_into_js(move || {
+        let _ = web_sys::Url::revoke_object_url(&url);
+    });
+    window
+        .set_timeout_with_callback_and_timeout_and_arguments_0(
+            revoke.unchecked_ref(),
+            REVOKE_DELAY_MS,
+        )
+        .map_err(js_error)
+        .context("Failed to schedule revoking URL of Blob")?;
+    Ok(())
+}
+
+/// Save `content` as a file.
+///
+/// On web, it lets the browser download `content` as `filename`.
+/// On native, `filename` is a path to the file to be written.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn save_file(filename: &str, _mime: &str, content: &[u8]) -> anyhow::Result<()> {
+    std::fs::write(filename, content).context(format!("Couldn't write file -> {}", filename))
+}
+
+/// Load content of a file from a path. Only available on native.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn load_file(path: &str) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(path).context(format!("Couldn't read file -> {}", path))
+}
+
+/// Name of a file dropped on the window.
+///
+/// On web, `name` is set. On native, only the path is given.
+pub(crate) fn dropped_file_name(file: &egui::DroppedFile) -> String {
+    if let Some(path) = &file.path {
+        if file.name.is_empty() {
+            return path.display().to_string();
+        }
+    }
+    file.name.clone()
+}
+
+/// Content of a file dropped on the window.
+///
+/// On web, the content is already loaded. On native, only the path is given.
+pub(crate) fn dropped_file_content(file: &egui::DroppedFile) -> anyhow::Result<Vec<u8>> {
+    if let Some(bytes) = &file.bytes {
+        return Ok(bytes.to_vec());
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(path) = &file.path {
+        return std::fs::read(path).context(format!("Couldn't read file -> {}", path.display()));
+    }
+    Err(anyhow!("file {} could not read", file.name))
+}
@@ -0,0 +1,212 @@
+//! Record an animation of a simulation run.
+//!
+//! Frames are rendered from the board directly (see `World::render`), not
+//! captured from the screen. All the frames are kept in memory until the
+//! recording stops, because APNG needs the number of frames in its header.
+//! So a recording stops capturing at `MAX_RECORDING_BYTES` of frames.
+//!
+//! The size of the animation is fixed at the first frame. If the board is
+//! resized while recording, later frames are cropped or padded with
+//! transparent pixels at the right and the bottom.
+
+use anyhow::anyhow;
+use anyhow::Context as _;
+
+/// Animated image formats.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum AnimationFormat {
+    Gif,
+    Apng,
+}
+
+impl AnimationFormat {
+    pub(crate) fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+        }
+    }
+    pub(crate) fn mime(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "image/gif",
+            AnimationFormat::Apng => "image/apng",
+        }
+    }
+}
+
+/// The maximum total size of the recorded frames in RGBA.
+const MAX_RECORDING_BYTES: usize = 512 << 20;
+
+pub(crate) struct Recorder {
+    pub(crate) format: AnimationFormat,
+    pub(crate) interval: u32, // record every k-th step
+    pub(crate) delay_ms: u16, // delay between frames
+    recording: bool,
+    full: bool, // reached max_bytes
+    step: u32,
+    frames: Vec<image::RgbaImage>,
+    max_bytes: usize,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self {
+            format: AnimationFormat::Gif,
+            interval: 1,
+            delay_ms: 100,
+            recording: false,
+            full: false,
+            step: 0,
+            frames: Vec::new(),
+            max_bytes: MAX_RECORDING_BYTES,
+        }
+    }
+}
+
+impl Recorder {
+    pub(crate) fn is_recording(&self) -> bool {
+        self.recording
+    }
+    pub(crate) fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+    /// True if no more frame is captured because of the size limit.
+    pub(crate) fn is_full(&self) -> bool {
+        self.full
+    }
+
+    /// Start recording. The first frame is the current state.
+    pub(crate) fn start(&mut self, first: image::RgbaImage) {
+        self.recording = true;
+        self.full = false;
+        self.step = 0;
+        self.frames = vec![first];
+    }
+
+    /// Discard the recorded frames.
+    pub(crate) fn cancel(&mut self) {
+        self.recording = false;
+        self.full = false;
+        self.frames.clear();
+    }
+
+    /// Called after each step. `render` is called only for the recorded steps.
+    pub(crate) fn on_step<F>(&mut self, render: F) -> anyhow::Result<()>
+    where
+        F: FnOnce() -> anyhow::Result<image::RgbaImage>,
+    {
+        if !self.recording || self.full {
+            return Ok(());
+        }
+        self.step += 1;
+        if self.step % self.interval.max(1) != 0 {
+            return Ok(());
+        }
+        let (w, h) = self.frames[0].dimensions();
+        let frame_bytes = w as usize * h as usize * 4;
+        if self.max_bytes < (self.frames.len() + 1) * frame_bytes {
+            self.full = true;
+            return Err(anyhow!(
+                "recording stopped at {} frames, the limit of {} MiB. stop it to save",
+                self.frames.len(),
+                self.max_bytes >> 20
+            ));
+        }
+        let mut frame = render()?;
+        if frame.dimensions() != (w, h) {
+            // keep the size of the first frame
+            let mut canvas = image::RgbaImage::new(w, h);
+            image::imageops::replace(&mut canvas, &frame, 0, 0);
+            frame = canvas;
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    /// Stop recording and encode the frames.
+    pub(crate) fn stop(&mut self) -> anyhow::Result<Vec<u8>> {
+        self.recording = false;
+        self.full = false;
+        let frames = std::mem::take(&mut self.frames);
+        match self.format {
+            AnimationFormat::Gif => encode_gif(frames, self.delay_ms),
+            AnimationFormat::Apng => encode_apng(frames, self.delay_ms),
+        }
+    }
+}
+
+fn encode_gif(frames: Vec<image::RgbaImage>, delay_ms: u16) -> anyhow::Result<Vec<u8>> {
+    use image::codecs::gif::{GifEncoder, Repeat};
+
+    let mut buf = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut buf);
+        encoder.set_repeat(Repeat::Infinite)?;
+        let delay = image::Delay::from_numer_denom_ms(delay_ms as u32, 1);
+        encoder
+            .encode_frames(frames.into_iter().map(|img| image::Frame::from_parts(img, 0, 0, delay)))
+            .context("Failed to encode GIF")?;
+    }
+    Ok(buf)
+}
+
+fn encode_apng(frames: Vec<image::RgbaImage>, delay_ms: u16) -> anyhow::Result<Vec<u8>> {
+    let (w, h) = frames.first().ok_or_else(|| anyhow!("no frame is recorded"))?.dimensions();
+
+    let mut buf = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut buf, w, h);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(frames.len() as u32, 0)?; // 0 means infinite loop
+        encoder.set_frame_delay(delay_ms, 1000)?;
+
+        let mut writer = encoder.write_header().context("Failed to write APNG header")?;
+        for frame in frames.iter() {
+            writer.write_image_data(frame.as_raw()).context("Failed to write APNG frame")?;
+        }
+        writer.finish().context("Failed to encode APNG")?;
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(w: u32, h: u32) -> image::RgbaImage {
+        image::RgbaImage::from_pixel(w, h, image::Rgba([255, 0, 0, 255]))
+    }
+
+    #[test]
+    fn resized_frames_are_cropped_or_padded() {
+        let mut recorder = Recorder::default();
+        recorder.start(filled(4, 3));
+        recorder.on_step(|| Ok(filled(6, 2))).unwrap();
+        recorder.on_step(|| Ok(filled(2, 5))).unwrap();
+        assert_eq!(recorder.num_frames(), 3);
+        for frame in recorder.frames.iter() {
+            assert_eq!(frame.dimensions(), (4, 3));
+        }
+        // padded with transparent pixels
+        assert_eq!(recorder.frames[1].get_pixel(0, 2)[3], 0);
+        assert_eq!(recorder.frames[2].get_pixel(3, 0)[3], 0);
+        assert_eq!(recorder.frames[2].get_pixel(1, 2)[3], 255);
+        assert!(!recorder.stop().unwrap().is_empty());
+    }
+
+    #[test]
+    fn recording_stops_at_the_limit() {
+        let mut recorder = Recorder { max_bytes: 3 * 4 * 4 * 4, ..Recorder::default() };
+        recorder.start(filled(4, 4));
+        recorder.on_step(|| Ok(filled(4, 4))).unwrap();
+        recorder.on_step(|| Ok(filled(4, 4))).unwrap();
+        assert!(recorder.on_step(|| Ok(filled(4, 4))).is_err());
+        assert!(recorder.is_recording() && recorder.is_full());
+        // no more frame nor error
+        recorder.on_step(|| panic!("not rendered")).unwrap();
+        assert_eq!(recorder.num_frames(), 3);
+        assert!(!recorder.stop().unwrap().is_empty());
+        assert!(!recorder.is_full());
+    }
+}
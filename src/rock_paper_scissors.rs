@@ -1,4 +1,8 @@
-use crate::rule::{Neighbors, Rule, State};
+use crate::board::{Board, PlainCells};
+use crate::rule::{Neighbors, Rule, State, UpdateContext};
+#[cfg(feature = "gui")]
+use crate::rule::{NeighborsUi, RuleUi, StateUi};
+use crate::world;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
@@ -16,6 +20,11 @@ impl std::default::Default for RockPaperScissorsState {
 }
 
 impl State for RockPaperScissorsState {
+    type Cells = PlainCells<Self>;
+}
+
+#[cfg(feature = "gui")]
+impl StateUi for RockPaperScissorsState {
     fn inspect(&mut self, ui: &mut egui::Ui, _buf: &mut String) {
         ui.radio_value(self, RockPaperScissorsState::Rock, "Rock");
         ui.radio_value(self, RockPaperScissorsState::Paper, "Paper");
@@ -24,41 +33,48 @@ impl State for RockPaperScissorsState {
 }
 
 pub struct RockPaperScissorsRule<N> {
-    neighbors: std::marker::PhantomData<N>,
+    neighborhood: N,
+    #[cfg(feature = "gui")]
     background: egui::Color32,
+    #[cfg(feature = "gui")]
     rock_color: egui::Color32,
+    #[cfg(feature = "gui")]
     paper_color: egui::Color32,
+    #[cfg(feature = "gui")]
     scissors_color: egui::Color32,
     threshold: u32,
 }
 
-impl<N> Default for RockPaperScissorsRule<N> {
+impl<N: Default> Default for RockPaperScissorsRule<N> {
     fn default() -> Self {
         Self {
-            neighbors: std::marker::PhantomData,
+            neighborhood: N::default(),
+            #[cfg(feature = "gui")]
             background: egui::Color32::from_rgb(0, 0, 0),
+            #[cfg(feature = "gui")]
             rock_color: egui::Color32::from_rgb(0, 0, 255),
+            #[cfg(feature = "gui")]
             paper_color: egui::Color32::from_rgb(0, 255, 0),
+            #[cfg(feature = "gui")]
             scissors_color: egui::Color32::from_rgb(255, 0, 0),
             threshold: 3,
         }
     }
 }
 
+impl<N> RockPaperScissorsRule<N> {
+    pub fn with_neighborhood(mut self, neighborhood: N) -> Self {
+        self.neighborhood = neighborhood;
+        self
+    }
+}
+
 impl<N: Neighbors> Rule for RockPaperScissorsRule<N> {
     type CellState = RockPaperScissorsState;
     type Neighborhood = N;
 
-    fn background(&self) -> egui::Color32 {
-        self.background
-    }
-
-    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
-        match *st {
-            RockPaperScissorsState::Rock => Ok(self.rock_color),
-            RockPaperScissorsState::Paper => Ok(self.paper_color),
-            RockPaperScissorsState::Scissors => Ok(self.scissors_color),
-        }
+    fn neighborhood(&self) -> N {
+        self.neighborhood.clone()
     }
 
     fn default_state(&self) -> anyhow::Result<Self::CellState> {
@@ -85,7 +101,8 @@ impl<N: Neighbors> Rule for RockPaperScissorsRule<N> {
     fn update(
         &self,
         center: Self::CellState,
-        neighbor: impl Iterator<Item = Self::CellState>,
+        neighbor: impl Iterator<Item = ((isize, isize), Self::CellState)>,
+        _ctx: &UpdateContext,
     ) -> anyhow::Result<Self::CellState> {
         let wins = match center {
             RockPaperScissorsState::Rock => RockPaperScissorsState::Paper,
@@ -93,17 +110,39 @@ impl<N: Neighbors> Rule for RockPaperScissorsRule<N> {
             RockPaperScissorsState::Scissors => RockPaperScissorsState::Rock,
         };
 
-        let n_wins: u32 = neighbor.map(|c| if c == wins { 1 } else { 0 }).sum();
+        let n_wins: u32 = neighbor.map(|(_, c)| if c == wins { 1 } else { 0 }).sum();
 
         Ok(if n_wins >= self.threshold { wins } else { center })
     }
 
+    fn update_board<B>(&self, board: &mut B, generation: u64) -> anyhow::Result<()>
+    where
+        B: Board<Self::CellState>,
+    {
+        world::update_parallel(self, board, generation)
+    }
+}
+
+#[cfg(feature = "gui")]
+impl<N: NeighborsUi> RuleUi for RockPaperScissorsRule<N> {
+    fn background(&self) -> egui::Color32 {
+        self.background
+    }
+
+    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
+        match *st {
+            RockPaperScissorsState::Rock => Ok(self.rock_color),
+            RockPaperScissorsState::Paper => Ok(self.paper_color),
+            RockPaperScissorsState::Scissors => Ok(self.scissors_color),
+        }
+    }
+
     fn ui(
         &mut self,
         ui: &mut egui::Ui,
         _ctx: &egui::Context,
         _on_side_panel: bool,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<bool> {
         ui.label("Grid Color");
         egui::widgets::color_picker::color_edit_button_srgba(
             ui,
@@ -134,11 +173,18 @@ impl<N: Neighbors> Rule for RockPaperScissorsRule<N> {
             egui::widgets::color_picker::Alpha::Opaque,
         );
 
-        ui.add(
-            egui::Slider::new(&mut self.threshold, 0..=N::num_neighbors() as u32)
-                .text("win/lose threshold"),
-        );
+        ui.separator();
 
-        Ok(())
+        let mut changed = self.neighborhood.ui(ui);
+        let num_neighbors = self.neighborhood.num_neighbors() as u32;
+        self.threshold = self.threshold.min(num_neighbors);
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.threshold, 0..=num_neighbors)
+                    .text("win/lose threshold"),
+            )
+            .changed();
+
+        Ok(changed)
     }
 }
@@ -1,5 +1,7 @@
-use crate::board::ClipBoard;
-use rand::Rng;
+use crate::board::{Board, ChunkCells, ClipBoard, CHUNK_LEN};
+use crate::pattern::PatternFormat;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 /// State of a cell.
 ///
@@ -7,17 +9,27 @@ use rand::Rng;
 ///
 /// To initialize the board, it requires `Clone` and `Default`.
 /// But before any operation, the state will be cleared by using `Rule::default_state`.
+/// `PartialEq` is used to find which chunks are changed, e.g. by undo history.
+/// `Cells` is how a chunk stores the states, normally `board::PlainCells<Self>`.
 ///
 /// Most of the operations are provided in `Rule` trait.
 ///
-pub trait State: Clone + Default + std::fmt::Debug {
+pub trait State: Clone + Default + PartialEq + std::fmt::Debug {
+    type Cells: ChunkCells<Self>;
+}
+
+/// UI of a cell state. Available only with `gui` feature.
+#[cfg(feature = "gui")]
+pub trait StateUi: State {
     /// Generate UI to inspect and modify the cell state.
     fn inspect(&mut self, ui: &mut egui::Ui, buf: &mut String);
 }
 
 /// Rule of the cellular automaton.
 ///
-/// It contains rule of update/clear/randomize, visualization, and UI related functions.
+/// It contains rule of update/clear/randomize. Since it does not depend on
+/// egui, a `World` can be updated without any GUI. Visualization and UI
+/// related functions are in `RuleUi`.
 ///
 /// Since `miniascape` supports `DynamicRule` that takes rhai script as the update rule,
 /// most of the functions *can fail*. For example, it fails if the rhai script contains
@@ -27,13 +39,18 @@ pub trait Rule: Default {
     /// Corresponding cell state.
     type CellState: State;
 
+    /// Neighborhood of a cell. Normally one of the types that implement `Neighbors`,
+    /// or `Neighbors3D` for the rules of `world3d::World3D`.
     type Neighborhood;
 
-    /// Background color.
-    fn background(&self) -> egui::Color32;
-
-    /// Color of a cell.
-    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32>;
+    /// The neighborhood used in the current update. By default, the default
+    /// value of the type. Rules with `ConfigurableNeighborhood` return their own.
+    fn neighborhood(&self) -> Self::Neighborhood
+    where
+        Self::Neighborhood: Default,
+    {
+        Default::default()
+    }
 
     /// the default cell state. When a board is cleared, all the cells have this value.
     fn default_state(&self) -> anyhow::Result<Self::CellState>;
@@ -45,12 +62,60 @@ pub trait Rule: Default {
     fn next(&self, st: Self::CellState) -> anyhow::Result<Self::CellState>;
 
     /// Update the center cell using the neighboring cells.
+    ///
+    /// Each neighbor comes with its offset `(dx, dy)` from the center, in the
+    /// order of `Neighbors::offsets` of the current neighborhood. Isotropic
+    /// rules can just ignore the offsets. `ctx` tells where and when the cell is.
     fn update(
         &self,
         center: Self::CellState,
-        neighbors: impl Iterator<Item = Self::CellState>,
+        neighbors: impl Iterator<Item = ((isize, isize), Self::CellState)>,
+        ctx: &UpdateContext,
     ) -> anyhow::Result<Self::CellState>;
 
+    /// Update the center cell in a 3D world (`world3d::World3D`). Each neighbor
+    /// comes with its offset `(dx, dy, dz)`, in the order of `Neighbors3D::offsets`.
+    ///
+    /// By default, `dz` is dropped and `update` is called, which is enough
+    /// for totalistic rules.
+    fn update_3d(
+        &self,
+        center: Self::CellState,
+        neighbors: impl Iterator<Item = ((isize, isize, isize), Self::CellState)>,
+        ctx: &UpdateContext,
+    ) -> anyhow::Result<Self::CellState> {
+        self.update(center, neighbors.map(|((dx, dy, _), st)| ((dx, dy), st)), ctx)
+    }
+
+    /// Write the next states of all the cells into the buffer of the board.
+    /// `generation` is the generation of the current states.
+    ///
+    /// By default, cells are updated one by one. If a rule is `Sync`, it can
+    /// override this by `world::update_parallel` to update chunks in parallel.
+    fn update_board<B>(&self, board: &mut B, generation: u64) -> anyhow::Result<()>
+    where
+        B: Board<Self::CellState>,
+        Self::Neighborhood: Neighbors,
+        Self: Sized,
+    {
+        crate::world::update_serial(self, board, generation)
+    }
+
+    /// The state of the cells out of the board under the fixed boundary condition.
+    fn boundary_state(&self) -> anyhow::Result<Self::CellState> {
+        self.default_state()
+    }
+
+    /// Whether chunks that did not change in the last step can be skipped.
+    ///
+    /// If a chunk and its neighbors are stable, the next states are the same
+    /// as the current ones as long as `update` depends only on the cells. Rules
+    /// that use randomness, or that change almost all the cells in every step
+    /// and gain nothing from tracking, should return false.
+    fn skip_stable_chunks(&self) -> bool {
+        true
+    }
+
     /// The number of updates in one step. Normally 1.
     /// This *step* means update of a window.
     fn iteration_per_step(&self) -> u32 {
@@ -61,67 +126,171 @@ pub trait Rule: Default {
         Vec::new()
     }
 
+    /// Category of a cell state in the population statistics.
+    /// By default, the debug representation of the state is used, which works
+    /// well for enum states. Continuous states should be binned into a few groups.
+    fn category(&self, st: &Self::CellState) -> String {
+        format!("{:?}", st)
+    }
+
+    /// Pattern file formats that can be imported/exported. Normally none.
+    fn pattern_formats(&self) -> Vec<PatternFormat> {
+        Vec::new()
+    }
+
+    /// The notation of the rule written in pattern files, e.g. `B3/S23`.
+    /// None if the rule has no notation.
+    fn pattern_rule(&self) -> Option<String> {
+        None
+    }
+
+    /// Read a pattern file as a clipboard.
+    fn load_pattern(
+        &self,
+        format: PatternFormat,
+        _content: &str,
+    ) -> anyhow::Result<ClipBoard<Self::CellState>> {
+        Err(anyhow::anyhow!("{:?} format is not supported by this rule", format))
+    }
+
+    /// Write a clipboard as a pattern file.
+    fn save_pattern(
+        &self,
+        format: PatternFormat,
+        _cb: &ClipBoard<Self::CellState>,
+    ) -> anyhow::Result<String> {
+        Err(anyhow::anyhow!("{:?} format is not supported by this rule", format))
+    }
+}
+
+/// Where and when a cell is updated. Passed to `Rule::update`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct UpdateContext {
+    /// Position of the cell. It does not change when the board is expanded to
+    /// the left or the top, or cropped, so it can be out of `0..width`.
+    pub x: isize,
+    pub y: isize,
+    /// The number of cells in a row of the board.
+    pub width: usize,
+    /// The number of cells in a column of the board.
+    pub height: usize,
+    /// The layer of the cell in a 3D world. Always 0 in 2D worlds.
+    pub z: isize,
+    /// The number of layers. Always 1 in 2D worlds.
+    pub depth: usize,
+    /// The generation of the current states.
+    pub generation: u64,
+}
+
+impl UpdateContext {
+    /// A random number generator seeded by the position and the generation.
+    ///
+    /// A cell draws the same numbers in the same generation, regardless of the
+    /// order of the update, so that a stochastic rule is reproducible.
+    pub fn rng(&self) -> StdRng {
+        // splitmix64 finalizer
+        let mix = |z: u64| {
+            let z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            let z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        let seed = mix(mix(mix(self.generation) ^ self.x as u64) ^ self.y as u64) ^ self.z as u64;
+        StdRng::seed_from_u64(seed)
+    }
+}
+
+/// Visualization and UI of a rule. Available only with `gui` feature.
+#[cfg(feature = "gui")]
+pub trait RuleUi: Rule {
+    /// Background color.
+    fn background(&self) -> egui::Color32;
+
+    /// Color of a cell.
+    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32>;
+
+    /// Returns true if the update rule is changed, so that stable chunks are
+    /// updated again.
     fn ui(
         &mut self,
         ui: &mut egui::Ui,
         ctx: &egui::Context,
         on_side_panel: bool,
-    ) -> anyhow::Result<()>;
+    ) -> anyhow::Result<bool>;
+}
+
+/// Relative positions of neighboring cells.
+///
+/// The coordinates of the neighbors, `(x + dx, y + dy)`, can be out of the board.
+/// They are mapped onto the board, or to the fixed state, by the boundary
+/// condition of the board (`board::Boundary`).
+///
+/// A neighborhood is a value so that it can be changed at runtime
+/// (`ConfigurableNeighborhood`). Rules return it by `Rule::neighborhood`.
+pub trait Neighbors: Clone + Default + Sync {
+    /// The offsets `(dx, dy)` of the neighbors of a cell in the row `y`.
+    /// Only hexagonal grids depend on `y`. `y` grows downward.
+    ///
+    /// `Rule::update` receives the neighbors in this order.
+    fn offsets(&self, y: isize) -> &[(isize, isize)];
+
+    fn num_neighbors(&self) -> usize {
+        self.offsets(0).len()
+    }
+
+    /// The largest `|dx|` or `|dy|` of the neighbors.
+    fn range(&self) -> usize {
+        (0..2)
+            .flat_map(|y| self.offsets(y).iter())
+            .map(|(dx, dy)| dx.unsigned_abs().max(dy.unsigned_abs()))
+            .max()
+            .unwrap_or(0)
+    }
 }
 
-/// Index of neighboring cells.
-pub trait Neighbors: Default {
-    type Neighborhood: IntoIterator<Item = (usize, usize)>;
-    fn neighbors(x: isize, y: isize, w: isize, h: isize) -> Self::Neighborhood;
-    fn num_neighbors() -> usize;
+/// UI of a neighborhood. Available only with `gui` feature.
+#[cfg(feature = "gui")]
+pub trait NeighborsUi: Neighbors {
+    /// Returns true if the neighborhood is changed. Fixed neighborhoods show nothing,
+    /// others end with a separator.
+    fn ui(&mut self, _ui: &mut egui::Ui) -> bool {
+        false
+    }
 }
 
 /// Von-Neumann Neighborhood. Up, Down, Left, Right cells are the neighbors.
-#[derive(Default)]
+///
+/// The order is up, right, left and down:
+/// `(0, -1), (1, 0), (-1, 0), (0, 1)`.
+#[derive(Clone, Default)]
 pub struct VonNeumannNeighborhood {}
 
 /// Moore Neighborhood. 8 neighboring cells in a square grid are the neighbors.
-#[derive(Default)]
+///
+/// The order is row-major from the upper left:
+/// `(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)`.
+#[derive(Clone, Default)]
 pub struct MooreNeighborhood {}
 
 /// Neighborhood on a hexagonal grid.
-#[derive(Default)]
+///
+/// The order is upper left, upper right, left, right, lower left and lower
+/// right. Since odd rows are shifted to the right, the offsets are
+/// `(-1, -1), (0, -1), (-1, 0), (1, 0), (-1, 1), (0, 1)` in even rows and
+/// `(0, -1), (1, -1), (-1, 0), (1, 0), (0, 1), (1, 1)` in odd rows.
+#[derive(Clone, Default)]
 pub struct HexGridNeighborhood {}
 
 impl Neighbors for VonNeumannNeighborhood {
-    type Neighborhood = [(usize, usize); 4];
-    fn neighbors(x: isize, y: isize, w: isize, h: isize) -> Self::Neighborhood {
-        let xprev = (if x == 0 { w - 1 } else { x - 1 }) as usize;
-        let xnext = (if x == w - 1 { 0 } else { x + 1 }) as usize;
-        let yprev = (if y == 0 { h - 1 } else { y - 1 }) as usize;
-        let ynext = (if y == h - 1 { 0 } else { y + 1 }) as usize;
-        let x = x as usize;
-        let y = y as usize;
-        [(x, yprev), (xnext, y), (xprev, y), (x, ynext)]
-    }
-
-    fn num_neighbors() -> usize {
-        4
+    fn offsets(&self, _y: isize) -> &[(isize, isize)] {
+        &[(0, -1), (1, 0), (-1, 0), (0, 1)]
     }
 }
 impl Neighbors for MooreNeighborhood {
-    type Neighborhood = [(usize, usize); 8];
-
     #[rustfmt::skip]
-    fn neighbors(x: isize, y: isize, w: isize, h: isize) -> Self::Neighborhood {
-        let xprev = (if x == 0 { w - 1 } else { x - 1 }) as usize;
-        let xnext = (if x == w - 1 { 0 } else { x + 1 }) as usize;
-        let yprev = (if y == 0 { h - 1 } else { y - 1 }) as usize;
-        let ynext = (if y == h - 1 { 0 } else { y + 1 }) as usize;
-        let x = x as usize;
-        let y = y as usize;
-        [(xprev, yprev), (x, yprev), (xnext, yprev),
-         (xprev, y    ),             (xnext, y    ),
-         (xprev, ynext), (x, ynext), (xnext, ynext)]
-    }
-
-    fn num_neighbors() -> usize {
-        8
+    fn offsets(&self, _y: isize) -> &[(isize, isize)] {
+        &[(-1, -1), (0, -1), (1, -1),
+          (-1,  0),          (1,  0),
+          (-1,  1), (0,  1), (1,  1)]
     }
 }
 
@@ -157,27 +326,430 @@ impl Neighbors for MooreNeighborhood {
 //          `.'   `.'   `.'   `.'   `.'   `.'   `.'
 //
 impl Neighbors for HexGridNeighborhood {
-    type Neighborhood = [(usize, usize); 6];
     #[rustfmt::skip]
-    fn neighbors(x: isize, y: isize, w: isize, h: isize) -> Self::Neighborhood {
-        let xprev = (if x == 0 { w - 1 } else { x - 1 }) as usize;
-        let xnext = (if x == w - 1 { 0 } else { x + 1 }) as usize;
-        let yprev = (if y == 0 { h - 1 } else { y - 1 }) as usize;
-        let ynext = (if y == h - 1 { 0 } else { y + 1 }) as usize;
-        let x = x as usize;
-        let y = y as usize;
-
-        if y % 2 == 0 {
-            [(xprev, yprev), (x,     yprev),
-             (xprev, y    ), (xnext, y),
-             (xprev, ynext), (x,     ynext)]
+    fn offsets(&self, y: isize) -> &[(isize, isize)] {
+        if y.rem_euclid(2) == 0 {
+            &[(-1, -1), (0, -1),
+              (-1,  0), (1,  0),
+              (-1,  1), (0,  1)]
+        } else {
+            &[(0, -1), (1, -1),
+              (-1, 0), (1,  0),
+              (0,  1), (1,  1)]
+        }
+    }
+}
+
+#[cfg(feature = "gui")]
+impl NeighborsUi for VonNeumannNeighborhood {}
+#[cfg(feature = "gui")]
+impl NeighborsUi for MooreNeighborhood {}
+#[cfg(feature = "gui")]
+impl NeighborsUi for HexGridNeighborhood {}
+
+// ----------------------------------------------------------------------------
+
+/// The largest range of `ConfigurableNeighborhood`.
+///
+/// Stable chunks are skipped only if all the neighbors are in the adjacent chunks.
+pub const MAX_RANGE: usize = CHUNK_LEN;
+
+/// Shape of `ConfigurableNeighborhood`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NeighborhoodShape {
+    /// Cells in the square of `(2r+1) x (2r+1)`, i.e. `max(|dx|, |dy|) <= r`.
+    Moore,
+    /// Cells in the diamond of `|dx| + |dy| <= r`.
+    VonNeumann,
+    /// Cells closer than `r + 1/2` in the Euclidean distance, i.e. `dx^2 + dy^2 <= r^2 + r`.
+    Circular,
+    /// Cells given by a mask.
+    Custom,
+}
+
+impl NeighborhoodShape {
+    pub fn all() -> [Self; 4] {
+        [Self::Moore, Self::VonNeumann, Self::Circular, Self::Custom]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Moore => "moore",
+            Self::VonNeumann => "von-neumann",
+            Self::Circular => "circular",
+            Self::Custom => "custom",
+        }
+    }
+
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Self::all()
+            .into_iter()
+            .find(|s| s.name() == name)
+            .ok_or_else(|| anyhow::anyhow!("unknown neighborhood: {}", name))
+    }
+}
+
+/// Neighborhood on a square grid that can be changed at runtime, as in
+/// Larger than Life. The offsets are sorted in the row-major order from the
+/// upper left, as `MooreNeighborhood`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ConfigurableNeighborhood {
+    shape: NeighborhoodShape,
+    range: usize,
+    offsets: Vec<(isize, isize)>,
+
+    #[cfg(feature = "gui")]
+    mask: String,
+    #[cfg(feature = "gui")]
+    mask_err: Option<String>,
+}
+
+impl Default for ConfigurableNeighborhood {
+    fn default() -> Self {
+        Self::moore(1)
+    }
+}
+
+impl ConfigurableNeighborhood {
+    /// A neighborhood of a shape and a range, at most `MAX_RANGE`. `Custom`
+    /// becomes an empty mask.
+    pub fn new(shape: NeighborhoodShape, range: usize) -> Self {
+        let range = range.min(MAX_RANGE);
+        let r = range as isize;
+        let contains = |dx: isize, dy: isize| match shape {
+            NeighborhoodShape::Moore => true,
+            NeighborhoodShape::VonNeumann => dx.abs() + dy.abs() <= r,
+            NeighborhoodShape::Circular => dx * dx + dy * dy <= r * r + r,
+            NeighborhoodShape::Custom => false,
+        };
+        let mut offsets = Vec::new();
+        for dy in -r..=r {
+            for dx in -r..=r {
+                if (dx, dy) != (0, 0) && contains(dx, dy) {
+                    offsets.push((dx, dy));
+                }
+            }
+        }
+        Self::with_offsets(shape, range, offsets)
+    }
+
+    pub fn moore(range: usize) -> Self {
+        Self::new(NeighborhoodShape::Moore, range)
+    }
+    pub fn von_neumann(range: usize) -> Self {
+        Self::new(NeighborhoodShape::VonNeumann, range)
+    }
+    pub fn circular(range: usize) -> Self {
+        Self::new(NeighborhoodShape::Circular, range)
+    }
+
+    /// A custom neighborhood from a mask, e.g.
+    ///
+    /// ```text
+    /// .#.
+    /// #.#
+    /// ...
+    /// ```
+    ///
+    /// `#` (or `1`) is a neighbor and `.` (or `0`) is not. The numbers of rows
+    /// and columns should be odd. The center character is ignored because the
+    /// center cell is passed to the rule separately.
+    pub fn from_mask(mask: &str) -> anyhow::Result<Self> {
+        let rows: Vec<&str> = mask.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).collect();
+        let h = rows.len();
+        let w = rows.first().map(|r| r.chars().count()).unwrap_or(0);
+        if h % 2 == 0 || w % 2 == 0 {
+            return Err(anyhow::anyhow!(
+                "a mask should have odd numbers of rows and columns, but it is {}x{}",
+                w,
+                h
+            ));
+        }
+        let (rx, ry) = ((w / 2) as isize, (h / 2) as isize);
+
+        let mut offsets = Vec::new();
+        for (j, row) in rows.iter().enumerate() {
+            if row.chars().count() != w {
+                return Err(anyhow::anyhow!("row {} of the mask is not {} wide", j + 1, w));
+            }
+            for (i, c) in row.chars().enumerate() {
+                let (dx, dy) = (i as isize - rx, j as isize - ry);
+                match c {
+                    '#' | '1' if (dx, dy) != (0, 0) => offsets.push((dx, dy)),
+                    '#' | '1' | '.' | '0' => {}
+                    _ => return Err(anyhow::anyhow!("unknown character in a mask: {}", c)),
+                }
+            }
+        }
+        Self::from_offsets(offsets)
+    }
+
+    /// A custom neighborhood from offsets. The center and duplicates are removed.
+    pub fn from_offsets(mut offsets: Vec<(isize, isize)>) -> anyhow::Result<Self> {
+        offsets.retain(|o| *o != (0, 0));
+        offsets.sort_by_key(|(dx, dy)| (*dy, *dx));
+        offsets.dedup();
+
+        let range = offsets
+            .iter()
+            .map(|(dx, dy)| dx.unsigned_abs().max(dy.unsigned_abs()))
+            .max()
+            .unwrap_or(0);
+        if MAX_RANGE < range {
+            return Err(anyhow::anyhow!(
+                "the range of a neighborhood should be at most {}, but it is {}",
+                MAX_RANGE,
+                range
+            ));
+        }
+        Ok(Self::with_offsets(NeighborhoodShape::Custom, range, offsets))
+    }
+
+    fn with_offsets(shape: NeighborhoodShape, range: usize, offsets: Vec<(isize, isize)>) -> Self {
+        Self {
+            #[cfg(feature = "gui")]
+            mask: mask_string(range, &offsets),
+            #[cfg(feature = "gui")]
+            mask_err: None,
+            shape,
+            range,
+            offsets,
+        }
+    }
+
+    pub fn shape(&self) -> NeighborhoodShape {
+        self.shape
+    }
+
+    /// Write the neighborhood as a mask that `from_mask` reads.
+    pub fn to_mask(&self) -> String {
+        mask_string(self.range, &self.offsets)
+    }
+}
+
+fn mask_string(range: usize, offsets: &[(isize, isize)]) -> String {
+    let r = range.max(1) as isize;
+    let mut mask = String::new();
+    for dy in -r..=r {
+        for dx in -r..=r {
+            mask.push(if offsets.contains(&(dx, dy)) { '#' } else { '.' });
+        }
+        mask.push('\n');
+    }
+    mask
+}
+
+impl Neighbors for ConfigurableNeighborhood {
+    fn offsets(&self, _y: isize) -> &[(isize, isize)] {
+        &self.offsets
+    }
+    fn range(&self) -> usize {
+        self.range
+    }
+}
+
+#[cfg(feature = "gui")]
+impl NeighborsUi for ConfigurableNeighborhood {
+    fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        ui.label("Neighborhood");
+        let mut shape = self.shape;
+        egui::ComboBox::from_id_source("neighborhood_shape")
+            .selected_text(shape.name())
+            .show_ui(ui, |ui| {
+                for s in NeighborhoodShape::all() {
+                    ui.selectable_value(&mut shape, s, s.name());
+                }
+            });
+        if shape != self.shape {
+            if shape == NeighborhoodShape::Custom {
+                // start editing from the current neighborhood
+                self.shape = shape;
+            } else {
+                *self = Self::new(shape, self.range.max(1));
+                changed = true;
+            }
+        }
+
+        if self.shape == NeighborhoodShape::Custom {
+            ui.label("`#` is a neighbor, `.` is not. The center is ignored.");
+            ui.add(egui::TextEdit::multiline(&mut self.mask).code_editor());
+            if ui.button("Apply").clicked() {
+                match Self::from_mask(&self.mask) {
+                    Ok(nh) => {
+                        *self = nh;
+                        changed = true;
+                    }
+                    Err(e) => self.mask_err = Some(format!("{}", e)),
+                }
+            }
+            if let Some(e) = &self.mask_err {
+                ui.label(e);
+            }
         } else {
-            [(x, yprev), (xnext, yprev),
-             (xprev, y), (xnext, y),
-             (x, ynext), (xnext, ynext)]
+            let mut range = self.range;
+            if ui.add(egui::Slider::new(&mut range, 1..=MAX_RANGE).text("range")).changed() {
+                *self = Self::new(self.shape, range);
+                changed = true;
+            }
         }
+        ui.label(format!("{} neighbors", self.offsets.len()));
+        ui.separator();
+        changed
     }
-    fn num_neighbors() -> usize {
-        6
+}
+
+// ----------------------------------------------------------------------------
+
+/// Relative positions of neighboring cells in a 3D world (`world3d::World3D`).
+///
+/// As in `Neighbors`, the coordinates can be out of the world and are mapped
+/// by the boundary condition.
+pub trait Neighbors3D: Clone + Default + Sync {
+    /// The offsets `(dx, dy, dz)` of the neighbors.
+    /// `Rule::update_3d` receives the neighbors in this order.
+    fn offsets(&self) -> &[(isize, isize, isize)];
+
+    fn num_neighbors(&self) -> usize {
+        self.offsets().len()
+    }
+}
+
+/// Kind of `CubicNeighborhood`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CubicNeighborhoodKind {
+    /// 6 cells that share a face with the center.
+    VonNeumann,
+    /// 18 cells that share a face or an edge with the center.
+    Edges,
+    /// 26 cells that share a face, an edge or a corner with the center.
+    Moore,
+}
+
+impl CubicNeighborhoodKind {
+    pub fn all() -> [Self; 3] {
+        [Self::VonNeumann, Self::Edges, Self::Moore]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::VonNeumann => "6 (faces)",
+            Self::Edges => "18 (faces and edges)",
+            Self::Moore => "26 (faces, edges and corners)",
+        }
+    }
+}
+
+/// Neighborhood on a cubic lattice. The offsets are sorted by `dz`, `dy` and
+/// then `dx`, from `(-1, -1, -1)` to `(1, 1, 1)`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CubicNeighborhood {
+    kind: CubicNeighborhoodKind,
+    offsets: Vec<(isize, isize, isize)>,
+}
+
+impl Default for CubicNeighborhood {
+    fn default() -> Self {
+        Self::new(CubicNeighborhoodKind::Moore)
+    }
+}
+
+impl CubicNeighborhood {
+    pub fn new(kind: CubicNeighborhoodKind) -> Self {
+        // the number of non-zero components of the offset
+        let max_nonzero = match kind {
+            CubicNeighborhoodKind::VonNeumann => 1,
+            CubicNeighborhoodKind::Edges => 2,
+            CubicNeighborhoodKind::Moore => 3,
+        };
+        let mut offsets = Vec::new();
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1_isize {
+                    let nonzero = [dx, dy, dz].iter().filter(|d| **d != 0).count();
+                    if 0 < nonzero && nonzero <= max_nonzero {
+                        offsets.push((dx, dy, dz));
+                    }
+                }
+            }
+        }
+        Self { kind, offsets }
+    }
+
+    pub fn kind(&self) -> CubicNeighborhoodKind {
+        self.kind
+    }
+}
+
+impl Neighbors3D for CubicNeighborhood {
+    fn offsets(&self) -> &[(isize, isize, isize)] {
+        &self.offsets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_of_moore_neighborhood() {
+        let n = ConfigurableNeighborhood::from_mask("###\n#.#\n###").unwrap();
+        assert_eq!(n.offsets(0), MooreNeighborhood::default().offsets(0));
+        assert_eq!(n.range(), 1);
+        assert_eq!(n.shape(), NeighborhoodShape::Custom);
+        // `1` and `0` also work, and the center is ignored
+        assert_eq!(
+            ConfigurableNeighborhood::from_mask("111\n111\n111").unwrap().offsets(0),
+            n.offsets(0)
+        );
+        assert!(ConfigurableNeighborhood::from_mask("...\n.#.\n...")
+            .unwrap()
+            .offsets(0)
+            .is_empty());
+    }
+
+    #[test]
+    fn mask_round_trip() {
+        for n in [
+            ConfigurableNeighborhood::moore(2),
+            ConfigurableNeighborhood::von_neumann(3),
+            ConfigurableNeighborhood::circular(4),
+        ] {
+            let m = ConfigurableNeighborhood::from_mask(&n.to_mask()).unwrap();
+            assert_eq!(m.offsets(0), n.offsets(0), "{:?}", n.shape());
+            assert_eq!(m.range(), n.range());
+        }
+    }
+
+    #[test]
+    fn mask_of_other_sizes() {
+        // indented, surrounded by blank lines, and wider than high
+        let n = ConfigurableNeighborhood::from_mask("\n  #....\n  ...#.\n  .....\n").unwrap();
+        assert_eq!(n.offsets(0), &[(-2, -1), (1, 0)]);
+        assert_eq!(n.range(), 2);
+        // a mask larger than the neighbors
+        let n = ConfigurableNeighborhood::from_mask(".....\n.....\n..#..\n.....\n....#").unwrap();
+        assert_eq!(n.offsets(0), &[(2, 2)]);
+        assert_eq!(n.range(), 2);
+    }
+
+    #[test]
+    fn invalid_masks() {
+        // even sizes, a ragged row, an unknown character and an empty mask
+        let invalid = ["##\n##", "###\n#.#", "###\n#.\n###", "###\n#x#\n###", ""];
+        for mask in invalid {
+            assert!(ConfigurableNeighborhood::from_mask(mask).is_err(), "{:?}", mask);
+        }
+        // beyond MAX_RANGE
+        let w = 2 * (MAX_RANGE + 1) + 1;
+        let mut mask = vec![".".repeat(w); w];
+        mask[0] = format!("#{}", ".".repeat(w - 1));
+        assert!(ConfigurableNeighborhood::from_mask(&mask.join("\n")).is_err());
+        mask[0] = ".".repeat(w);
+        mask[1] = format!(".#{}", ".".repeat(w - 2));
+        assert_eq!(
+            ConfigurableNeighborhood::from_mask(&mask.join("\n")).unwrap().range(),
+            MAX_RANGE
+        );
     }
 }
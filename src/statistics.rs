@@ -0,0 +1,165 @@
+//! Population statistics of a world.
+//!
+//! Cells are categorized by `Rule::category` and counted after each step.
+//! The history of the counts is plotted against the generation.
+
+use crate::board::Board;
+use crate::rule::Rule;
+use crate::world::World;
+use std::collections::{BTreeMap, VecDeque};
+
+// categories more than this are merged into "others"
+const MAX_CATEGORIES: usize = 16;
+// the oldest samples are dropped if a series exceeds this
+const MAX_SAMPLES: usize = 10000;
+// the distinct states counted before `Rule::category` is called. states are
+// not hashable, e.g. floats, and continuous ones rarely repeat, so the rest
+// of the cells are categorized one by one.
+const MAX_DISTINCT_STATES: usize = 64;
+
+#[derive(Default)]
+pub(crate) struct Statistics {
+    pub(crate) enabled: bool,
+    latest: Vec<(String, u64)>,
+    series: BTreeMap<String, VecDeque<(u64, u64)>>, // category -> [(generation, count)]
+}
+
+impl Statistics {
+    pub(crate) fn clear(&mut self) {
+        self.latest.clear();
+        self.series.clear();
+    }
+
+    /// Count the cells in the world and append it to the history.
+    pub(crate) fn record<W: World>(&mut self, world: &W) {
+        if !self.enabled {
+            return;
+        }
+        let rule = world.rule();
+        let board = world.board();
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+        let mut states = Vec::new(); // [(state, count)]
+        let mut too_many_states = false;
+        for y in 0..board.height() {
+            for x in 0..board.width() {
+                let cell = board.cell_at(x, y);
+                if too_many_states {
+                    *counts.entry(rule.category(cell)).or_default() += 1;
+                } else if let Some((_, n)) = states.iter_mut().find(|(st, _)| *st == cell) {
+                    *n += 1;
+                } else if states.len() < MAX_DISTINCT_STATES {
+                    states.push((cell, 1));
+                } else {
+                    too_many_states = true;
+                    *counts.entry(rule.category(cell)).or_default() += 1;
+                }
+            }
+        }
+        for (st, n) in states {
+            *counts.entry(rule.category(st)).or_default() += n;
+        }
+
+        let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        if MAX_CATEGORIES < counts.len() {
+            let others = counts.split_off(MAX_CATEGORIES - 1).iter().map(|(_, n)| n).sum();
+            counts.push(("others".to_string(), others));
+        }
+
+        // after undo or step back, the samples from the discarded future are removed
+        let generation = world.generation();
+        for samples in self.series.values_mut() {
+            samples.retain(|(g, _)| *g < generation);
+        }
+        for (category, count) in counts.iter() {
+            let samples = self.series.entry(category.clone()).or_default();
+            samples.push_back((generation, *count));
+            if MAX_SAMPLES < samples.len() {
+                samples.pop_front();
+            }
+        }
+        self.series.retain(|_, samples| !samples.is_empty());
+        self.latest = counts;
+    }
+
+    pub(crate) fn ui(&mut self, ui: &mut egui::Ui) {
+        use egui::plot::{Legend, Line, Plot, Value, Values};
+
+        egui::Grid::new("population").striped(true).show(ui, |ui| {
+            for (category, count) in self.latest.iter() {
+                ui.label(category);
+                ui.label(count.to_string());
+                ui.end_row();
+            }
+        });
+
+        Plot::new("population_plot")
+            .height(160.0)
+            .legend(Legend::default())
+            .show(ui, |plot_ui| {
+                for (category, samples) in self.series.iter() {
+                    let values = samples.iter().map(|(g, n)| Value::new(*g as f64, *n as f64));
+                    plot_ui.line(Line::new(Values::from_values_iter(values)).name(category));
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::SquareGrid;
+    use crate::gray_scott::GrayScottRule;
+    use crate::lifegame::LifeGameRule;
+    use crate::rule::MooreNeighborhood;
+    use crate::world::World2D;
+    use rand::SeedableRng;
+
+    // count the category of each cell
+    fn count_each_cell<W: World>(world: &W) -> BTreeMap<String, u64> {
+        let board = world.board();
+        let mut counts = BTreeMap::new();
+        for y in 0..board.height() {
+            for x in 0..board.width() {
+                *counts.entry(world.rule().category(board.cell_at(x, y))).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    fn recorded<W: World>(world: &W) -> BTreeMap<String, u64> {
+        let mut stats = Statistics { enabled: true, ..Statistics::default() };
+        stats.record(world);
+        stats.latest.into_iter().collect()
+    }
+
+    #[test]
+    fn same_as_counting_each_cell() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+
+        let mut life: World2D<LifeGameRule<MooreNeighborhood>, SquareGrid<_>> =
+            World::new(LifeGameRule::default(), 3, 2, 1);
+        life.randomize(&mut rng).unwrap();
+        assert_eq!(recorded(&life), count_each_cell(&life));
+
+        // every cell has a distinct state
+        let mut gray_scott: World2D<GrayScottRule, SquareGrid<_>> =
+            World::new(GrayScottRule::default(), 3, 2, 1);
+        gray_scott.randomize(&mut rng).unwrap();
+        assert_eq!(recorded(&gray_scott), count_each_cell(&gray_scott));
+    }
+
+    #[test]
+    fn oldest_samples_are_dropped() {
+        let mut world: World2D<LifeGameRule<MooreNeighborhood>, SquareGrid<_>> =
+            World::new(LifeGameRule::default(), 1, 1, 1);
+        let mut stats = Statistics { enabled: true, ..Statistics::default() };
+        for generation in 0..MAX_SAMPLES as u64 + 3 {
+            world.set_generation(generation);
+            stats.record(&world);
+        }
+        let samples = stats.series.values().next().unwrap();
+        assert_eq!(samples.len(), MAX_SAMPLES);
+        assert_eq!(samples.front(), Some(&(3, 256)));
+    }
+}
@@ -0,0 +1,57 @@
+//! Fixtures shared by the tests of several modules.
+
+use crate::board::{Board, Boundary, SquareGrid};
+use crate::lifegame::{LifeGameRule, LifeGameState};
+use crate::rule::MooreNeighborhood;
+#[cfg(feature = "gui")]
+use crate::world::World;
+use crate::world::World2D;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+pub(crate) type Life = World2D<LifeGameRule<MooreNeighborhood>, SquareGrid<LifeGameState>>;
+
+/// The live cells of a board in the row-major order.
+pub(crate) fn alive_cells<B: Board<LifeGameState>>(board: &B) -> Vec<(usize, usize)> {
+    let mut alive = Vec::new();
+    for y in 0..board.height() {
+        for x in 0..board.width() {
+            if *board.cell_at(x, y) == LifeGameState::Alive {
+                alive.push((x, y));
+            }
+        }
+    }
+    alive
+}
+
+/// The live cells on the plane, so that boards of different origins compare.
+#[cfg(feature = "gui")]
+pub(crate) fn alive(world: &Life) -> Vec<(isize, isize)> {
+    let (ox, oy) = crate::world::context_origin(world.board());
+    alive_cells(world.board())
+        .into_iter()
+        .map(|(x, y)| (x as isize + ox, y as isize + oy))
+        .collect()
+}
+
+/// The offset of the chunks given to `History` and `Generations`, as the app does.
+#[cfg(feature = "gui")]
+pub(crate) fn offset(world: &Life) -> (isize, isize) {
+    let (ox, oy) = world.board().chunk_origin();
+    (-ox, -oy)
+}
+
+/// A board of `nx` x `ny` chunks with random live cells, the same for the same size.
+pub(crate) fn random_board(nx: usize, ny: usize, boundary: Boundary) -> SquareGrid<LifeGameState> {
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut board = SquareGrid::init(nx, ny, LifeGameState::Dead);
+    board.set_boundary(boundary, LifeGameState::Dead);
+    for y in 0..board.height() {
+        for x in 0..board.width() {
+            if rng.gen_bool(0.35) {
+                *board.cell_at_mut(x, y) = LifeGameState::Alive;
+            }
+        }
+    }
+    board
+}
@@ -1,4 +1,8 @@
-use crate::rule::{MooreNeighborhood, Rule, State};
+use crate::board::{Board, PlainCells};
+use crate::rule::{MooreNeighborhood, Rule, State, UpdateContext};
+#[cfg(feature = "gui")]
+use crate::rule::{RuleUi, StateUi};
+use crate::world;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
@@ -17,6 +21,11 @@ impl std::default::Default for WireWorldState {
 }
 
 impl State for WireWorldState {
+    type Cells = PlainCells<Self>;
+}
+
+#[cfg(feature = "gui")]
+impl StateUi for WireWorldState {
     fn inspect(&mut self, ui: &mut egui::Ui, _buf: &mut String) {
         ui.radio_value(self, WireWorldState::Void, "Void");
         ui.radio_value(self, WireWorldState::Head, "Head");
@@ -26,20 +35,30 @@ impl State for WireWorldState {
 }
 
 pub struct WireWorldRule {
+    #[cfg(feature = "gui")]
     grid_color: egui::Color32,
+    #[cfg(feature = "gui")]
     void_color: egui::Color32,
+    #[cfg(feature = "gui")]
     wire_color: egui::Color32,
+    #[cfg(feature = "gui")]
     head_color: egui::Color32,
+    #[cfg(feature = "gui")]
     tail_color: egui::Color32,
 }
 
 impl Default for WireWorldRule {
     fn default() -> Self {
         Self {
+            #[cfg(feature = "gui")]
             grid_color: egui::Color32::from_rgb(128, 128, 0),
+            #[cfg(feature = "gui")]
             void_color: egui::Color32::from_rgb(0, 0, 0),
+            #[cfg(feature = "gui")]
             wire_color: egui::Color32::from_rgb(255, 255, 0),
+            #[cfg(feature = "gui")]
             head_color: egui::Color32::from_rgb(0, 0, 255),
+            #[cfg(feature = "gui")]
             tail_color: egui::Color32::from_rgb(255, 0, 0),
         }
     }
@@ -49,19 +68,6 @@ impl Rule for WireWorldRule {
     type CellState = WireWorldState;
     type Neighborhood = MooreNeighborhood;
 
-    fn background(&self) -> egui::Color32 {
-        self.grid_color
-    }
-
-    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
-        Ok(match *st {
-            WireWorldState::Void => self.void_color,
-            WireWorldState::Head => self.head_color,
-            WireWorldState::Tail => self.tail_color,
-            WireWorldState::Wire => self.wire_color,
-        })
-    }
-
     fn default_state(&self) -> anyhow::Result<Self::CellState> {
         Ok(WireWorldState::Void)
     }
@@ -88,7 +94,8 @@ impl Rule for WireWorldRule {
     fn update(
         &self,
         center: Self::CellState,
-        neighbor: impl Iterator<Item = Self::CellState>,
+        neighbor: impl Iterator<Item = ((isize, isize), Self::CellState)>,
+        _ctx: &UpdateContext,
     ) -> anyhow::Result<Self::CellState> {
         Ok(match center {
             WireWorldState::Void => WireWorldState::Void,
@@ -96,7 +103,7 @@ impl Rule for WireWorldRule {
             WireWorldState::Tail => WireWorldState::Wire,
             WireWorldState::Wire => {
                 let nheads: u32 =
-                    neighbor.map(|c| if c == WireWorldState::Head { 1 } else { 0 }).sum();
+                    neighbor.map(|(_, c)| if c == WireWorldState::Head { 1 } else { 0 }).sum();
                 if nheads == 1 || nheads == 2 {
                     WireWorldState::Head
                 } else {
@@ -106,12 +113,35 @@ impl Rule for WireWorldRule {
         })
     }
 
+    fn update_board<B>(&self, board: &mut B, generation: u64) -> anyhow::Result<()>
+    where
+        B: Board<Self::CellState>,
+    {
+        world::update_parallel(self, board, generation)
+    }
+}
+
+#[cfg(feature = "gui")]
+impl RuleUi for WireWorldRule {
+    fn background(&self) -> egui::Color32 {
+        self.grid_color
+    }
+
+    fn color(&self, st: &Self::CellState) -> anyhow::Result<egui::Color32> {
+        Ok(match *st {
+            WireWorldState::Void => self.void_color,
+            WireWorldState::Head => self.head_color,
+            WireWorldState::Tail => self.tail_color,
+            WireWorldState::Wire => self.wire_color,
+        })
+    }
+
     fn ui(
         &mut self,
         ui: &mut egui::Ui,
         _ctx: &egui::Context,
         _on_side_panel: bool,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<bool> {
         ui.hyperlink_to("Wireworld - Wikipedia", "https://en.wikipedia.org/wiki/Wireworld");
         ui.separator();
 
@@ -153,6 +183,6 @@ impl Rule for WireWorldRule {
             &mut self.tail_color,
             egui::widgets::color_picker::Alpha::Opaque,
         );
-        Ok(())
+        Ok(false)
     }
 }
@@ -1,8 +1,11 @@
-use crate::board::{Board, CHUNK_LEN};
-use crate::rule::{Neighbors, Rule};
+use crate::board::{Board, Boundary, CHUNK_LEN};
+#[cfg(feature = "gui")]
+use crate::rule::RuleUi;
+use crate::rule::{Neighbors, Rule, State, UpdateContext};
 
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::ops::Range;
 
 pub trait World {
     type Rule: Rule;
@@ -27,16 +30,75 @@ pub trait World {
     fn expand_y(&mut self, n: isize, init: <<Self as World>::Rule as Rule>::CellState);
     fn expand_z(&mut self, n: isize, init: <<Self as World>::Rule as Rule>::CellState);
 
+    /// Keep only the chunks in the ranges of chunk indices.
+    fn crop(&mut self, x: Range<usize>, y: Range<usize>);
+
+    /// Remove `|n|` chunks from the end (n > 0) or the beginning (n < 0).
+    /// At least a chunk is left.
+    fn shrink_x(&mut self, n: isize) {
+        let (nx, ny) = (self.board().n_chunks_x(), self.board().n_chunks_y());
+        let na = n.unsigned_abs().min(nx - 1);
+        if n < 0 {
+            self.crop(na..nx, 0..ny);
+        } else {
+            self.crop(0..nx - na, 0..ny);
+        }
+    }
+    fn shrink_y(&mut self, n: isize) {
+        let (nx, ny) = (self.board().n_chunks_x(), self.board().n_chunks_y());
+        let na = n.unsigned_abs().min(ny - 1);
+        if n < 0 {
+            self.crop(0..nx, na..ny);
+        } else {
+            self.crop(0..nx, 0..ny - na);
+        }
+    }
+    /// Remove the chunks at the border that contain only the default state.
+    fn auto_crop(&mut self) -> anyhow::Result<()> {
+        let empty = self.rule().default_state()?;
+        let (x, y) = self.board().occupied_chunks(&empty).unwrap_or((0..1, 0..1));
+        self.crop(x, y);
+        Ok(())
+    }
+
+    /// None if the world has no selectable boundary condition.
+    fn boundary(&self) -> Option<Boundary>;
+    fn set_boundary(&mut self, boundary: Boundary) -> anyhow::Result<()>;
+
+    /// the number of updates since the world is cleared or randomized.
+    /// If a rule iterates several times in a step, each iteration is counted.
+    fn generation(&self) -> u64;
+    fn set_generation(&mut self, generation: u64);
+
     fn clear(&mut self) -> anyhow::Result<()>;
     fn randomize<Rn: Rng>(&mut self, rng: &mut Rn) -> anyhow::Result<()>;
 
+    /// Whether undo, redo and step back work. They restore the chunks of the
+    /// board, so a world that has states out of the board does not support them.
+    fn supports_history(&self) -> bool {
+        true
+    }
+
     /// visualize the slice of the world.
+    #[cfg(feature = "gui")]
     fn paint(
         &self,
         painter: &egui::Painter,
         origin: egui::Pos2,
         cell_width: f32,
-    ) -> anyhow::Result<()>;
+    ) -> anyhow::Result<()>
+    where
+        Self::Rule: RuleUi;
+
+    /// rasterize the whole board with `cell_width` pixels per cell.
+    #[cfg(feature = "gui")]
+    fn render(&self, cell_width: u32) -> anyhow::Result<image::RgbaImage>
+    where
+        Self::Rule: RuleUi;
+
+    /// controls specific to the world, shown in the status of the side panel.
+    #[cfg(feature = "gui")]
+    fn ui(&mut self, _ui: &mut egui::Ui) {}
 
     fn update(&mut self) -> anyhow::Result<()>;
 }
@@ -46,6 +108,8 @@ pub struct World2D<R: Rule, B: Board<R::CellState>> {
     #[serde(skip)]
     rule: R,
     board: B,
+    #[serde(default)]
+    generation: u64,
 }
 
 impl<R: Rule, B: Board<R::CellState>> std::default::Default for World2D<R, B> {
@@ -54,7 +118,7 @@ impl<R: Rule, B: Board<R::CellState>> std::default::Default for World2D<R, B> {
         let init = rule.default_state().unwrap_or_default();
         let mut board = B::init(4, 3, init);
         board.clear(&rule).expect("default construction must not fail");
-        Self { rule, board }
+        Self { rule, board, generation: 0 }
     }
 }
 
@@ -72,7 +136,7 @@ where
         let init = rule.default_state().unwrap_or_default();
         let mut board = B::init(x_chunks, y_chunks, init);
         board.clear(&rule).expect("default construction must not fail");
-        Self { rule, board }
+        Self { rule, board, generation: 0 }
     }
 
     fn rule(&self) -> &R {
@@ -113,53 +177,276 @@ where
     fn expand_z(&mut self, _n: isize, _init: R::CellState) {
         // do nothing
     }
+    fn crop(&mut self, x: Range<usize>, y: Range<usize>) {
+        self.board.crop(x, y);
+    }
+
+    fn boundary(&self) -> Option<Boundary> {
+        Some(self.board.boundary())
+    }
+    fn set_boundary(&mut self, boundary: Boundary) -> anyhow::Result<()> {
+        let background = self.rule.default_state()?;
+        self.board.set_boundary(boundary, background);
+        Ok(())
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+    fn set_generation(&mut self, generation: u64) {
+        self.generation = generation;
+    }
 
     fn clear(&mut self) -> anyhow::Result<()> {
+        self.generation = 0;
         self.board.clear(&self.rule)
     }
     fn randomize<Rn: Rng>(&mut self, rng: &mut Rn) -> anyhow::Result<()> {
+        self.generation = 0;
         self.board.randomize(&self.rule, rng)
     }
 
     /// visualize the slice of the world.
+    #[cfg(feature = "gui")]
     fn paint(
         &self,
         painter: &egui::Painter,
         origin: egui::Pos2,
         cell_width: f32,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<()>
+    where
+        R: RuleUi,
+    {
         self.board.paint(painter, origin, cell_width, &self.rule, 1.0)
     }
 
+    #[cfg(feature = "gui")]
+    fn render(&self, cell_width: u32) -> anyhow::Result<image::RgbaImage>
+    where
+        R: RuleUi,
+    {
+        self.board.render(&self.rule, cell_width)
+    }
+
     fn update(&mut self) -> anyhow::Result<()> {
+        // `begin_update` activates only the chunks next to the changed ones
+        debug_assert!(self.rule.neighborhood().range() <= CHUNK_LEN);
         for _ in 0..self.rule.iteration_per_step() {
-            for cj in 0..self.board.n_chunks_y() {
-                let y0 = cj * CHUNK_LEN;
-                for ci in 0..self.board.n_chunks_x() {
-                    let x0 = ci * CHUNK_LEN;
-                    for j in 0..CHUNK_LEN {
-                        for i in 0..CHUNK_LEN {
-                            let x = x0 + i;
-                            let y = y0 + j;
-                            let idxs = R::Neighborhood::neighbors(
-                                x as isize,
-                                y as isize,
-                                self.width() as isize,
-                                self.height() as isize,
-                            );
-
-                            *self.board.bufcell_at_mut(x, y) = self.rule.update(
-                                self.board.cell_at(x, y).clone(),
-                                idxs.into_iter()
-                                    .map(|(x, y)| self.board.cell_at(x, y).clone())
-                                    .into_iter(),
-                            )?;
-                        }
+            self.board.begin_update(self.rule.skip_stable_chunks());
+            self.rule.update_board(&mut self.board, self.generation)?;
+            self.board.swap_buffer();
+            self.generation += 1;
+        }
+        Ok(())
+    }
+}
+
+// the position of the cell at (0, 0) of the board in `UpdateContext`
+pub(crate) fn context_origin<T: State, B: Board<T>>(board: &B) -> (isize, isize) {
+    let (ox, oy) = board.chunk_origin();
+    (ox * CHUNK_LEN as isize, oy * CHUNK_LEN as isize)
+}
+
+/// The cells of a chunk and the `range` cells around it, in the row-major
+/// order of the `(CHUNK_LEN + 2 * range)` square. Cells out of the board are
+/// `fixed`. Neighbors are looked up in it without resolving the boundary.
+pub(crate) fn chunk_with_halo<'a, T: State, B: Board<T>>(
+    board: &'a B,
+    cx: usize,
+    cy: usize,
+    range: usize,
+    fixed: &'a T,
+) -> Vec<&'a T> {
+    let side = CHUNK_LEN + 2 * range;
+    let (x0, y0) = (
+        (cx * CHUNK_LEN) as isize - range as isize,
+        (cy * CHUNK_LEN) as isize - range as isize,
+    );
+    let mut halo = Vec::with_capacity(side * side);
+    for y in y0..y0 + side as isize {
+        for x in x0..x0 + side as isize {
+            halo.push(board.neighbor_at(x, y).unwrap_or(fixed));
+        }
+    }
+    halo
+}
+
+/// Write the next states into the buffer, cell by cell. Inactive chunks are skipped.
+pub fn update_serial<R, B>(rule: &R, board: &mut B, generation: u64) -> anyhow::Result<()>
+where
+    R: Rule,
+    R::Neighborhood: Neighbors,
+    B: Board<R::CellState>,
+{
+    let fixed = rule.boundary_state()?;
+    let neighborhood = rule.neighborhood();
+    let (ox, oy) = context_origin(board);
+    let (width, height) = (board.width(), board.height());
+    for cj in 0..board.n_chunks_y() {
+        let y0 = cj * CHUNK_LEN;
+        for ci in 0..board.n_chunks_x() {
+            if !board.is_active_chunk(ci, cj) {
+                continue;
+            }
+            let x0 = ci * CHUNK_LEN;
+            for j in 0..CHUNK_LEN {
+                for i in 0..CHUNK_LEN {
+                    let x = x0 + i;
+                    let y = y0 + j;
+                    let (xi, yi) = (x as isize, y as isize);
+                    let ctx = UpdateContext {
+                        x: xi + ox,
+                        y: yi + oy,
+                        width,
+                        height,
+                        z: 0,
+                        depth: 1,
+                        generation,
+                    };
+
+                    *board.bufcell_at_mut(x, y) = rule.update(
+                        board.cell_at(x, y).clone(),
+                        neighborhood.offsets(yi).iter().map(|&(dx, dy)| {
+                            (
+                                (dx, dy),
+                                board.neighbor_at(xi + dx, yi + dy).unwrap_or(&fixed).clone(),
+                            )
+                        }),
+                        &ctx,
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the next states into the buffer. Chunks are updated in parallel on
+/// native targets and serially on wasm. Inactive chunks are skipped.
+pub fn update_parallel<R, B>(rule: &R, board: &mut B, generation: u64) -> anyhow::Result<()>
+where
+    R: Rule + Sync,
+    R::Neighborhood: Neighbors,
+    R::CellState: Send + Sync,
+    B: Board<R::CellState>,
+{
+    let fixed = rule.boundary_state()?;
+    let neighborhood = rule.neighborhood();
+    let (ox, oy) = context_origin(board);
+    let (width, height) = (board.width(), board.height());
+    board.fill_buffer(|board, x, y| {
+        let (x, y) = (x as isize, y as isize);
+        let ctx = UpdateContext { x: x + ox, y: y + oy, width, height, z: 0, depth: 1, generation };
+        rule.update(
+            board.cell_at(x as usize, y as usize).clone(),
+            neighborhood.offsets(y).iter().map(|&(dx, dy)| {
+                ((dx, dy), board.neighbor_at(x + dx, y + dy).unwrap_or(&fixed).clone())
+            }),
+            &ctx,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::SquareGrid;
+    use crate::lifegame::{LifeGameRule, LifeGameState};
+    use crate::rule::MooreNeighborhood;
+    use crate::test_util::{alive_cells, Life};
+    use crate::wireworld::WireWorldRule;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // runs without any UI
+    #[test]
+    fn blinker_and_glider() {
+        let mut world = Life::new(LifeGameRule::default(), 2, 2, 1);
+        let blinker = [(5, 4), (5, 5), (5, 6)];
+        let glider = [(21, 20), (22, 21), (20, 22), (21, 22), (22, 22)];
+        for (x, y) in blinker.iter().chain(glider.iter()) {
+            *world.board_mut().cell_at_mut(*x, *y) = LifeGameState::Alive;
+        }
+
+        world.update().unwrap();
+        let alive = alive_cells(world.board());
+        for cell in [(4, 5), (5, 5), (6, 5)] {
+            assert!(alive.contains(&cell), "{:?}", alive);
+        }
+        assert!(!alive.contains(&(5, 4)));
+
+        for _ in 0..3 {
+            world.update().unwrap();
+        }
+        assert_eq!(world.generation(), 4);
+        // the blinker is back, and the glider moves by (1, 1)
+        let mut expected: Vec<(usize, usize)> = blinker.to_vec();
+        expected.extend(glider.iter().map(|(x, y)| (x + 1, y + 1)));
+        expected.sort_by_key(|(x, y)| (*y, *x));
+        assert_eq!(alive_cells(world.board()), expected);
+    }
+
+    // a board with random cells in the central chunk, so that the others are
+    // stable until the cells spread to them
+    fn board_with_random_center<R: Rule>(rule: &R, boundary: Boundary) -> SquareGrid<R::CellState> {
+        let mut rng = StdRng::seed_from_u64(42);
+        let background = rule.default_state().unwrap();
+        let mut board = SquareGrid::init(5, 5, background.clone());
+        board.set_boundary(boundary, background);
+        for y in 33..47 {
+            for x in 33..47 {
+                *board.cell_at_mut(x, y) = rule.randomize(&mut rng).unwrap();
+            }
+        }
+        board
+    }
+
+    fn assert_skipping_same_as_full_update<R>(rule: R)
+    where
+        R: Rule + Sync,
+        R::Neighborhood: Neighbors,
+        R::CellState: Send + Sync,
+    {
+        for boundary in
+            [Boundary::Periodic, Boundary::Fixed, Boundary::Reflective, Boundary::Infinite]
+        {
+            let mut skipping = board_with_random_center(&rule, boundary);
+            let mut full = board_with_random_center(&rule, boundary);
+            let mut skipped = false;
+            for generation in 0..30 {
+                skipping.begin_update(true);
+                let (nx, ny) = (skipping.n_chunks_x(), skipping.n_chunks_y());
+                skipped |= (0..ny).any(|cy| (0..nx).any(|cx| !skipping.is_active_chunk(cx, cy)));
+                update_parallel(&rule, &mut skipping, generation).unwrap();
+                skipping.swap_buffer();
+
+                full.begin_update(false);
+                update_serial(&rule, &mut full, generation).unwrap();
+                full.swap_buffer();
+
+                assert_eq!(skipping.chunk_origin(), full.chunk_origin());
+                assert_eq!((skipping.width(), skipping.height()), (full.width(), full.height()));
+                for y in 0..full.height() {
+                    for x in 0..full.width() {
+                        assert_eq!(
+                            skipping.cell_at(x, y),
+                            full.cell_at(x, y),
+                            "({}, {}) in {} under {:?}",
+                            x,
+                            y,
+                            generation,
+                            boundary
+                        );
                     }
                 }
             }
-            self.board.swap_buffer();
+            assert!(skipped, "no chunk is skipped under {:?}", boundary);
         }
-        Ok(())
+    }
+
+    #[test]
+    fn skipping_stable_chunks_same_as_full_update() {
+        assert_skipping_same_as_full_update(LifeGameRule::<MooreNeighborhood>::default());
+        assert_skipping_same_as_full_update(WireWorldRule::default());
     }
 }
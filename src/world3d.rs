@@ -0,0 +1,464 @@
+//! A 3D world made of a stack of 2D layers.
+//!
+//! Each layer is a `SquareGrid` of the same size. Only one layer is shown at a
+//! time, optionally with the cells of the adjacent layers as ghosts. A cell
+//! sees the neighbors in the adjacent layers by `Neighbors3D`. Along the x and
+//! y axes, the boundary condition works as in 2D; along the z axis, the layers
+//! are periodic, fixed or reflective as a single column of cells.
+//!
+//! As in 2D, a chunk is updated only if a chunk next to it, in the same layer
+//! or in the adjacent layers, is changed in the last step.
+
+use crate::board::{Board, Boundary, SquareGrid, CHUNK_LEN};
+#[cfg(feature = "gui")]
+use crate::rule::RuleUi;
+use crate::rule::{Neighbors3D, Rule, UpdateContext};
+use crate::world::{self, World};
+
+use anyhow::anyhow;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+#[derive(Serialize, Deserialize)]
+pub struct World3D<R: Rule> {
+    #[serde(skip)]
+    rule: R,
+    #[serde(bound(
+        serialize = "R::CellState: Serialize",
+        deserialize = "R::CellState: Deserialize<'de>"
+    ))]
+    layers: Vec<SquareGrid<R::CellState>>,
+    // the z coordinate of the first layer. it decreases when layers are prepended.
+    #[serde(default)]
+    origin_z: isize,
+    #[serde(default)]
+    generation: u64,
+
+    #[serde(skip)]
+    current_layer: usize,
+    #[serde(skip)]
+    ghost: bool,
+}
+
+impl<R: Rule> std::default::Default for World3D<R> {
+    fn default() -> Self {
+        Self::with_rule(R::default(), 4, 3, 1)
+    }
+}
+
+impl<R: Rule> World3D<R> {
+    // `z_chunks * CHUNK_LEN` layers of `x_chunks` x `y_chunks` chunks
+    fn with_rule(rule: R, x_chunks: usize, y_chunks: usize, z_chunks: usize) -> Self {
+        let init = rule.default_state().unwrap_or_default();
+        let layers = (0..z_chunks.max(1) * CHUNK_LEN)
+            .map(|_| SquareGrid::init(x_chunks, y_chunks, init.clone()))
+            .collect();
+        Self { rule, layers, origin_z: 0, generation: 0, current_layer: 0, ghost: false }
+    }
+
+    pub fn layer(&self, z: usize) -> &SquareGrid<R::CellState> {
+        &self.layers[z]
+    }
+    pub fn layer_mut(&mut self, z: usize) -> &mut SquareGrid<R::CellState> {
+        &mut self.layers[z]
+    }
+
+    /// Whether the cells in the adjacent layers are painted faintly.
+    pub fn ghost(&self) -> bool {
+        self.ghost
+    }
+    pub fn set_ghost(&mut self, ghost: bool) {
+        self.ghost = ghost;
+    }
+
+    // The chunks to be updated in each layer, or None to update all. Since a
+    // neighbor is at most CHUNK_LEN cells away in x and y, and in the adjacent
+    // layers, a change reaches at most the 8 neighboring chunks in the same
+    // and the adjacent layers in a step.
+    fn active_chunks(&self) -> Vec<Option<Vec<bool>>> {
+        let depth = self.layers.len();
+        if !self.rule.skip_stable_chunks() {
+            return vec![None; depth];
+        }
+        let (nx, ny) = (self.layers[0].n_chunks_x(), self.layers[0].n_chunks_y());
+        let boundary = self.layers[0].boundary();
+        let mut active = vec![vec![false; nx * ny]; depth];
+        for (z, layer) in self.layers.iter().enumerate() {
+            for y in 0..ny {
+                for x in 0..nx {
+                    // unknown changes, e.g. after resizing, are counted as changed
+                    if layer.is_changed_chunk(x, y) == Some(false) {
+                        continue;
+                    }
+                    for dz in -1..=1 {
+                        let z = match boundary.resolve(0, z as isize + dz, 1, depth) {
+                            Some((_, z)) => z,
+                            None => continue,
+                        };
+                        for dy in -1..=1 {
+                            for dx in -1..=1 {
+                                let (x, y) = (x as isize + dx, y as isize + dy);
+                                if let Some((x, y)) = boundary.resolve(x, y, nx, ny) {
+                                    active[z][y * nx + x] = true;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        active.into_iter().map(Some).collect()
+    }
+}
+
+impl<R> World for World3D<R>
+where
+    R: Rule + Sync,
+    R::CellState: Send + Sync,
+    R::Neighborhood: Neighbors3D,
+{
+    type Rule = R;
+    type Board = SquareGrid<R::CellState>;
+
+    fn new(rule: R, x_chunks: usize, y_chunks: usize, z_chunks: usize) -> Self {
+        Self::with_rule(rule, x_chunks, y_chunks, z_chunks)
+    }
+
+    fn rule(&self) -> &R {
+        &self.rule
+    }
+    fn rule_mut(&mut self) -> &mut R {
+        &mut self.rule
+    }
+    fn board(&self) -> &Self::Board {
+        &self.layers[self.current_layer]
+    }
+    fn board_mut(&mut self) -> &mut Self::Board {
+        &mut self.layers[self.current_layer]
+    }
+    fn current_layer(&self) -> usize {
+        self.current_layer
+    }
+    fn set_current_layer(&mut self, z: usize) {
+        self.current_layer = z.min(self.layers.len() - 1);
+    }
+
+    fn width(&self) -> usize {
+        self.layers[0].width()
+    }
+    fn height(&self) -> usize {
+        self.layers[0].height()
+    }
+    fn depth(&self) -> usize {
+        self.layers.len()
+    }
+
+    fn expand_x(&mut self, n: isize, init: R::CellState) {
+        for layer in self.layers.iter_mut() {
+            layer.expand_x(n, init.clone());
+        }
+    }
+    fn expand_y(&mut self, n: isize, init: R::CellState) {
+        for layer in self.layers.iter_mut() {
+            layer.expand_y(n, init.clone());
+        }
+    }
+    // `|n| * CHUNK_LEN` layers are appended (n > 0) or prepended (n < 0).
+    // the current layer keeps showing the same cells.
+    fn expand_z(&mut self, n: isize, init: R::CellState) {
+        let (nx, ny) = (self.layers[0].n_chunks_x(), self.layers[0].n_chunks_y());
+        let boundary = self.layers[0].boundary();
+        let na = n.unsigned_abs() * CHUNK_LEN;
+        let new_layers = (0..na).map(|_| {
+            let mut layer = SquareGrid::init(nx, ny, init.clone());
+            layer.set_boundary(boundary, init.clone());
+            layer
+        });
+        if n < 0 {
+            self.layers.splice(0..0, new_layers);
+            self.origin_z -= na as isize;
+            self.current_layer += na;
+        } else {
+            self.layers.extend(new_layers);
+        }
+    }
+    fn crop(&mut self, x: Range<usize>, y: Range<usize>) {
+        for layer in self.layers.iter_mut() {
+            layer.crop(x.clone(), y.clone());
+        }
+    }
+    // keep the chunks occupied in any layer
+    fn auto_crop(&mut self) -> anyhow::Result<()> {
+        let empty = self.rule.default_state()?;
+        let occupied = self.layers.iter().filter_map(|layer| layer.occupied_chunks(&empty)).fold(
+            None,
+            |acc: Option<(Range<usize>, Range<usize>)>, (xs, ys)| {
+                Some(match acc {
+                    None => (xs, ys),
+                    Some((x, y)) => (
+                        x.start.min(xs.start)..x.end.max(xs.end),
+                        y.start.min(ys.start)..y.end.max(ys.end),
+                    ),
+                })
+            },
+        );
+        let (x, y) = occupied.unwrap_or((0..1, 0..1));
+        self.crop(x, y);
+        Ok(())
+    }
+
+    fn boundary(&self) -> Option<Boundary> {
+        Some(self.layers[0].boundary())
+    }
+    fn set_boundary(&mut self, boundary: Boundary) -> anyhow::Result<()> {
+        if boundary == Boundary::Infinite {
+            return Err(anyhow!("a 3D world does not support the infinite boundary"));
+        }
+        let background = self.rule.default_state()?;
+        for layer in self.layers.iter_mut() {
+            layer.set_boundary(boundary, background.clone());
+        }
+        Ok(())
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+    fn set_generation(&mut self, generation: u64) {
+        self.generation = generation;
+    }
+
+    fn clear(&mut self) -> anyhow::Result<()> {
+        self.generation = 0;
+        for layer in self.layers.iter_mut() {
+            layer.clear(&self.rule)?;
+        }
+        Ok(())
+    }
+    fn randomize<Rn: Rng>(&mut self, rng: &mut Rn) -> anyhow::Result<()> {
+        self.generation = 0;
+        for layer in self.layers.iter_mut() {
+            layer.randomize(&self.rule, rng)?;
+        }
+        Ok(())
+    }
+
+    // the board is only the current layer
+    fn supports_history(&self) -> bool {
+        false
+    }
+
+    /// visualize the current layer. The cells in the adjacent layers are drawn
+    /// as small translucent squares if `ghost` is on.
+    #[cfg(feature = "gui")]
+    fn paint(
+        &self,
+        painter: &egui::Painter,
+        origin: egui::Pos2,
+        cell_width: f32,
+    ) -> anyhow::Result<()>
+    where
+        R: RuleUi,
+    {
+        let board = self.board();
+        board.paint(painter, origin, cell_width, &self.rule, 1.0)?;
+        if !self.ghost {
+            return Ok(());
+        }
+
+        let region = painter.clip_rect();
+        let regsize = region.max - region.min;
+        let rwidth = 1.0_f32 / cell_width;
+        let cell_begin_x = (origin.x * rwidth).floor().max(0.0) as usize;
+        let cell_begin_y = (origin.y * rwidth).floor().max(0.0) as usize;
+        let cell_end_x =
+            (((origin.x + regsize.x) * rwidth).ceil().max(0.0) as usize).min(board.width());
+        let cell_end_y =
+            (((origin.y + regsize.y) * rwidth).ceil().max(0.0) as usize).min(board.height());
+
+        let empty = self.rule.default_state()?;
+        let size = egui::vec2(cell_width * 0.5, cell_width * 0.5);
+        let z = self.current_layer;
+        for adjacent in [z.checked_sub(1), Some(z + 1).filter(|z| *z < self.layers.len())] {
+            let layer = match adjacent {
+                Some(z) => &self.layers[z],
+                None => continue,
+            };
+            for j in cell_begin_y..cell_end_y {
+                for i in cell_begin_x..cell_end_x {
+                    let st = layer.cell_at(i, j);
+                    if *st == empty || *board.cell_at(i, j) != empty {
+                        continue;
+                    }
+                    let color = self.rule.color(st)?;
+                    let color =
+                        egui::Color32::from_rgba_unmultiplied(color.r(), color.g(), color.b(), 96);
+                    let center = layer.location(i, j, origin, region.min, cell_width);
+                    painter.rect_filled(
+                        egui::Rect::from_center_size(center, size),
+                        egui::Rounding::none(),
+                        color,
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "gui")]
+    fn render(&self, cell_width: u32) -> anyhow::Result<image::RgbaImage>
+    where
+        R: RuleUi,
+    {
+        self.board().render(&self.rule, cell_width)
+    }
+
+    #[cfg(feature = "gui")]
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.ghost, "show adjacent layers");
+    }
+
+    fn update(&mut self) -> anyhow::Result<()> {
+        let neighborhood = self.rule.neighborhood();
+        // `active_chunks` activates only the chunks next to the changed ones
+        debug_assert!(neighborhood.offsets().iter().all(|(dx, dy, dz)| {
+            dx.unsigned_abs() <= CHUNK_LEN && dy.unsigned_abs() <= CHUNK_LEN && dz.abs() <= 1
+        }));
+        let fixed = self.rule.boundary_state()?;
+        let boundary = self.layers[0].boundary();
+        let (width, height, depth) = (self.width(), self.height(), self.depth());
+        let (ox, oy) = world::context_origin(&self.layers[0]);
+
+        for _ in 0..self.rule.iteration_per_step() {
+            let active = self.active_chunks();
+            for (layer, active) in self.layers.iter_mut().zip(active) {
+                layer.grid_mut().set_active_chunks(active);
+            }
+            // all the layers are read while the next states are written, so
+            // the buffers are taken out of the layers
+            let mut buffers: Vec<_> =
+                self.layers.iter_mut().map(|layer| layer.grid_mut().take_buffer()).collect();
+            let (rule, layers, generation) = (&self.rule, &self.layers, self.generation);
+            let origin_z = self.origin_z;
+            let result = buffers.iter_mut().enumerate().try_for_each(|(z, buffer)| {
+                layers[z].grid().fill_chunks(buffer, |cx, cy, chunk| {
+                    for j in 0..CHUNK_LEN {
+                        for i in 0..CHUNK_LEN {
+                            let (x, y) = (cx * CHUNK_LEN + i, cy * CHUNK_LEN + j);
+                            let (xi, yi, zi) = (x as isize, y as isize, z as isize);
+                            let ctx = UpdateContext {
+                                x: xi + ox,
+                                y: yi + oy,
+                                width,
+                                height,
+                                z: zi + origin_z,
+                                depth,
+                                generation,
+                            };
+                            let neighbors = neighborhood.offsets().iter().map(|&(dx, dy, dz)| {
+                                let st = boundary
+                                    .resolve(0, zi + dz, 1, depth)
+                                    .and_then(|(_, z)| layers[z].neighbor_at(xi + dx, yi + dy))
+                                    .unwrap_or(&fixed);
+                                ((dx, dy, dz), st.clone())
+                            });
+                            *chunk.cell_at_mut(i, j) =
+                                rule.update_3d(layers[z].cell_at(x, y).clone(), neighbors, &ctx)?;
+                        }
+                    }
+                    Ok(())
+                })
+            });
+            for (layer, buffer) in self.layers.iter_mut().zip(buffers) {
+                layer.grid_mut().put_buffer(buffer);
+            }
+            result?;
+
+            for layer in self.layers.iter_mut() {
+                layer.swap_buffer();
+            }
+            self.generation += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::life3d::Life3DRule;
+    use crate::lifegame::LifeGameState;
+    use rand::SeedableRng;
+
+    fn cells(world: &World3D<Life3DRule>) -> Vec<Vec<LifeGameState>> {
+        let (width, height) = (world.width(), world.height());
+        let layer = |z: usize| {
+            (0..width * height)
+                .map(|i| *world.layer(z).cell_at(i % width, i / width))
+                .collect()
+        };
+        (0..world.depth()).map(layer).collect()
+    }
+
+    // every cell from its neighbors, without skipping any chunk
+    fn reference(
+        rule: &Life3DRule,
+        boundary: Boundary,
+        cells: &[Vec<LifeGameState>],
+        width: usize,
+    ) -> Vec<Vec<LifeGameState>> {
+        let (height, depth) = (cells[0].len() / width, cells.len());
+        let ctx = UpdateContext { x: 0, y: 0, width, height, z: 0, depth, generation: 0 };
+        let neighborhood = rule.neighborhood();
+        let mut next = cells.to_vec();
+        for z in 0..depth {
+            for y in 0..height {
+                for x in 0..width {
+                    let neighbors = neighborhood.offsets().iter().map(|&(dx, dy, dz)| {
+                        let z = boundary.resolve(0, z as isize + dz, 1, depth);
+                        let xy = boundary.resolve(x as isize + dx, y as isize + dy, width, height);
+                        let st = match (z, xy) {
+                            (Some((_, z)), Some((x, y))) => cells[z][y * width + x],
+                            _ => LifeGameState::Dead,
+                        };
+                        ((dx, dy, dz), st)
+                    });
+                    let center = cells[z][y * width + x];
+                    next[z][y * width + x] = rule.update_3d(center, neighbors, &ctx).unwrap();
+                }
+            }
+        }
+        next
+    }
+
+    #[test]
+    fn skipping_stable_chunks_same_as_full_update() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        for boundary in [Boundary::Periodic, Boundary::Fixed, Boundary::Reflective] {
+            let rule = Life3DRule::from_rule("4555").unwrap();
+            let mut world = World3D::new(Life3DRule::from_rule("4555").unwrap(), 2, 2, 1);
+            world.set_boundary(boundary).unwrap();
+            // a random block in a corner, so that the other chunks are stable for a while
+            for z in 0..6 {
+                for y in 0..10 {
+                    for x in 0..10 {
+                        let st = rule.randomize(&mut rng).unwrap();
+                        *world.layer_mut(z).cell_at_mut(x + 2, y + 2) = st;
+                    }
+                }
+            }
+            let width = world.width();
+            for i in 0..12 {
+                if i == 6 {
+                    // an edit far from the block
+                    world.set_current_layer(12);
+                    *world.board_mut().cell_at_mut(25, 25) = LifeGameState::Alive;
+                }
+                let expected = reference(&rule, boundary, &cells(&world), width);
+                world.update().unwrap();
+                assert_eq!(cells(&world), expected, "step {} under {:?}", i, boundary);
+            }
+        }
+    }
+}
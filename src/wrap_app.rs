@@ -1,10 +1,15 @@
 use crate::app::App;
 use crate::board::{HexGrid, SquareGrid};
-use crate::rule::{HexGridNeighborhood, MooreNeighborhood, VonNeumannNeighborhood};
+use crate::hashlife::HashLifeWorld;
+use crate::rule::{
+    ConfigurableNeighborhood, HexGridNeighborhood, MooreNeighborhood, VonNeumannNeighborhood,
+};
 use crate::world::World2D;
+use crate::world3d::World3D;
 
 use crate::dynamic_rule::{DynamicRule, DynamicState};
 use crate::gray_scott::{GrayScottRule, GrayScottState};
+use crate::life3d::Life3DRule;
 use crate::lifegame::{HighLifeRule, LifeGameRule, LifeGameState, LifeLikeGameRule};
 use crate::rock_paper_scissors::{RockPaperScissorsRule, RockPaperScissorsState};
 use crate::wireworld::{WireWorldRule, WireWorldState};
@@ -20,6 +25,24 @@ enum GridKind {
 enum SquareNeighborKind {
     Moore,
     Neumann,
+    Configurable,
+}
+
+impl SquareNeighborKind {
+    // the neighborhood of a square grid can be selected in a combo box
+    fn combo_box(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Select Neighborhood")
+            .selected_text(format!("{:?}", self))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(self, SquareNeighborKind::Moore, "Moore neighborhood");
+                ui.selectable_value(self, SquareNeighborKind::Neumann, "Von Neumann Neighborhood");
+                ui.selectable_value(
+                    self,
+                    SquareNeighborKind::Configurable,
+                    "Configurable (Larger than Life)",
+                );
+            });
+    }
 }
 
 /// An application that manages sub-applications that corresponds to one cell automaton.
@@ -30,6 +53,9 @@ pub struct WrapApp {
     focus: Option<usize>,
 
     life_game_rule: String,
+    life3d_rule: String,
+    use_hashlife: bool,
+    lifelike_square_neighbor_kind: SquareNeighborKind,
 
     dynamic_grid_kind: GridKind,
     dynamic_square_neighbor_kind: SquareNeighborKind,
@@ -65,6 +91,9 @@ impl WrapApp {
             apps: Vec::new(),
             focus: None,
             life_game_rule: "23/3".to_string(),
+            life3d_rule: "4555".to_string(),
+            use_hashlife: false,
+            lifelike_square_neighbor_kind: SquareNeighborKind::Moore,
             dynamic_grid_kind: GridKind::Square,
             dynamic_square_neighbor_kind: SquareNeighborKind::Moore,
             rock_paper_scissors_grid_kind: GridKind::Square,
@@ -123,14 +152,24 @@ impl WrapApp {
                     .clicked()
                 {
                     self.focus = Some(self.apps.len());
-                    self.apps.push((
-                        "LifeGame".to_string(),
-                        Box::new(App::<
-                            World2D<LifeGameRule<MooreNeighborhood>, SquareGrid<LifeGameState>>,
-                        >::default()),
-                    ));
+                    if self.use_hashlife {
+                        self.apps.push((
+                            "LifeGame (HashLife)".to_string(),
+                            Box::new(
+                                App::<HashLifeWorld<LifeGameRule<MooreNeighborhood>>>::default(),
+                            ),
+                        ));
+                    } else {
+                        self.apps.push((
+                            "LifeGame".to_string(),
+                            Box::new(App::<
+                                World2D<LifeGameRule<MooreNeighborhood>, SquareGrid<LifeGameState>>,
+                            >::default()),
+                        ));
+                    }
                 }
                 ui.label(egui::RichText::new("Conway's Game of Life.").size(20.0));
+                ui.checkbox(&mut self.use_hashlife, "HashLife");
             });
         });
     }
@@ -147,14 +186,24 @@ impl WrapApp {
                     .clicked()
                 {
                     self.focus = Some(self.apps.len());
-                    self.apps.push((
-                        "HighLife".to_string(),
-                        Box::new(App::<
-                            World2D<HighLifeRule<MooreNeighborhood>, SquareGrid<LifeGameState>>,
-                        >::default()),
-                    ));
+                    if self.use_hashlife {
+                        self.apps.push((
+                            "HighLife (HashLife)".to_string(),
+                            Box::new(
+                                App::<HashLifeWorld<HighLifeRule<MooreNeighborhood>>>::default(),
+                            ),
+                        ));
+                    } else {
+                        self.apps.push((
+                            "HighLife".to_string(),
+                            Box::new(App::<
+                                World2D<HighLifeRule<MooreNeighborhood>, SquareGrid<LifeGameState>>,
+                            >::default()),
+                        ));
+                    }
                 }
                 ui.label(egui::RichText::new("HighLife(23/36)").size(20.0));
+                ui.checkbox(&mut self.use_hashlife, "HashLife");
             });
         });
     }
@@ -172,20 +221,63 @@ impl WrapApp {
                     && LifeLikeGameRule::<MooreNeighborhood>::is_valid_rule(&self.life_game_rule)
                 {
                     self.focus = Some(self.apps.len());
-                    self.apps.push((
-                        self.life_game_rule.clone(),
-                        Box::new(App::<
-                            World2D<LifeLikeGameRule<MooreNeighborhood>, SquareGrid<LifeGameState>>,
-                        >::new(LifeLikeGameRule::from_rule(
-                            &self.life_game_rule,
-                        ))),
-                    ));
+                    match self.lifelike_square_neighbor_kind {
+                        SquareNeighborKind::Moore => {
+                            let rule = LifeLikeGameRule::from_rule(&self.life_game_rule);
+                            if self.use_hashlife {
+                                self.apps.push((
+                                    format!("{} (HashLife)", self.life_game_rule),
+                                    Box::new(App::<
+                                        HashLifeWorld<LifeLikeGameRule<MooreNeighborhood>>,
+                                    >::new(rule)),
+                                ));
+                            } else {
+                                self.apps.push((
+                                    self.life_game_rule.clone(),
+                                    Box::new(App::<
+                                        World2D<
+                                            LifeLikeGameRule<MooreNeighborhood>,
+                                            SquareGrid<LifeGameState>,
+                                        >,
+                                    >::new(rule)),
+                                ));
+                            }
+                        }
+                        SquareNeighborKind::Neumann => self.apps.push((
+                            format!("{}V", self.life_game_rule),
+                            Box::new(App::<
+                                World2D<
+                                    LifeLikeGameRule<VonNeumannNeighborhood>,
+                                    SquareGrid<LifeGameState>,
+                                >,
+                            >::new(
+                                LifeLikeGameRule::from_rule(&self.life_game_rule)
+                            )),
+                        )),
+                        SquareNeighborKind::Configurable => self.apps.push((
+                            self.life_game_rule.clone(),
+                            Box::new(App::<
+                                World2D<
+                                    LifeLikeGameRule<ConfigurableNeighborhood>,
+                                    SquareGrid<LifeGameState>,
+                                >,
+                            >::new(
+                                LifeLikeGameRule::from_rule(&self.life_game_rule)
+                            )),
+                        )),
+                    }
                 }
                 ui.label(egui::RichText::new("Life-Like").size(20.0));
                 ui.horizontal_wrapped(|ui| {
                     ui.label("rule `{survive}/{birth}` (e.g. 23/3)");
                     ui.add(egui::TextEdit::singleline(&mut self.life_game_rule));
                 });
+                ui.push_id(3, |ui| {
+                    self.lifelike_square_neighbor_kind.combo_box(ui);
+                });
+                if self.lifelike_square_neighbor_kind == SquareNeighborKind::Moore {
+                    ui.checkbox(&mut self.use_hashlife, "HashLife");
+                }
             });
         });
     }
@@ -282,10 +374,8 @@ impl WrapApp {
                 {
                     self.focus = Some(self.apps.len());
                     if self.rock_paper_scissors_grid_kind == GridKind::Square {
-                        if self.rock_paper_scissors_square_neighbor_kind
-                            == SquareNeighborKind::Moore
-                        {
-                            self.apps.push((
+                        match self.rock_paper_scissors_square_neighbor_kind {
+                            SquareNeighborKind::Moore => self.apps.push((
                                 "Rock Paper Scissors".to_string(),
                                 Box::new(App::<
                                     World2D<
@@ -293,9 +383,8 @@ impl WrapApp {
                                         SquareGrid<RockPaperScissorsState>,
                                     >,
                                 >::default()),
-                            ));
-                        } else {
-                            self.apps.push((
+                            )),
+                            SquareNeighborKind::Neumann => self.apps.push((
                                 "Rock Paper Scissors".to_string(),
                                 Box::new(App::<
                                     World2D<
@@ -303,7 +392,16 @@ impl WrapApp {
                                         SquareGrid<RockPaperScissorsState>,
                                     >,
                                 >::default()),
-                            ));
+                            )),
+                            SquareNeighborKind::Configurable => self.apps.push((
+                                "Rock Paper Scissors".to_string(),
+                                Box::new(App::<
+                                    World2D<
+                                        RockPaperScissorsRule<ConfigurableNeighborhood>,
+                                        SquareGrid<RockPaperScissorsState>,
+                                    >,
+                                >::default()),
+                            )),
                         }
                     } else {
                         self.apps.push((
@@ -335,23 +433,7 @@ impl WrapApp {
                             );
                         });
                     if self.rock_paper_scissors_grid_kind == GridKind::Square {
-                        egui::ComboBox::from_label("Select Neighborhood")
-                            .selected_text(format!(
-                                "{:?}",
-                                self.rock_paper_scissors_square_neighbor_kind
-                            ))
-                            .show_ui(ui, |ui| {
-                                ui.selectable_value(
-                                    &mut self.rock_paper_scissors_square_neighbor_kind,
-                                    SquareNeighborKind::Moore,
-                                    "Moore neighborhood",
-                                );
-                                ui.selectable_value(
-                                    &mut self.rock_paper_scissors_square_neighbor_kind,
-                                    SquareNeighborKind::Neumann,
-                                    "Von Neumann Neighborhood",
-                                );
-                            });
+                        self.rock_paper_scissors_square_neighbor_kind.combo_box(ui);
                     }
                 });
             });
@@ -375,25 +457,43 @@ impl WrapApp {
                 {
                     self.focus = Some(self.apps.len());
                     if self.dynamic_grid_kind == GridKind::Square {
-                        if self.dynamic_square_neighbor_kind == SquareNeighborKind::Moore {
-                            let app = App::<
-                                World2D<DynamicRule<MooreNeighborhood>, SquareGrid<DynamicState>>,
-                            > {
-                                fix_board_size: true,
-                                ..Default::default()
-                            };
-                            self.apps.push(("User Defined".to_string(), Box::new(app)));
-                        } else {
-                            let app = App::<
-                                World2D<
-                                    DynamicRule<VonNeumannNeighborhood>,
-                                    SquareGrid<DynamicState>,
-                                >,
-                            > {
-                                fix_board_size: true,
-                                ..Default::default()
-                            };
-                            self.apps.push(("User Defined".to_string(), Box::new(app)));
+                        match self.dynamic_square_neighbor_kind {
+                            SquareNeighborKind::Moore => {
+                                let app = App::<
+                                    World2D<
+                                        DynamicRule<MooreNeighborhood>,
+                                        SquareGrid<DynamicState>,
+                                    >,
+                                > {
+                                    fix_board_size: true,
+                                    ..Default::default()
+                                };
+                                self.apps.push(("User Defined".to_string(), Box::new(app)));
+                            }
+                            SquareNeighborKind::Neumann => {
+                                let app = App::<
+                                    World2D<
+                                        DynamicRule<VonNeumannNeighborhood>,
+                                        SquareGrid<DynamicState>,
+                                    >,
+                                > {
+                                    fix_board_size: true,
+                                    ..Default::default()
+                                };
+                                self.apps.push(("User Defined".to_string(), Box::new(app)));
+                            }
+                            SquareNeighborKind::Configurable => {
+                                let app = App::<
+                                    World2D<
+                                        DynamicRule<ConfigurableNeighborhood>,
+                                        SquareGrid<DynamicState>,
+                                    >,
+                                > {
+                                    fix_board_size: true,
+                                    ..Default::default()
+                                };
+                                self.apps.push(("User Defined".to_string(), Box::new(app)));
+                            }
                         }
                     } else {
                         let app = App::<
@@ -423,26 +523,42 @@ impl WrapApp {
                             );
                         });
                     if self.dynamic_grid_kind == GridKind::Square {
-                        egui::ComboBox::from_label("Select Neighborhood")
-                            .selected_text(format!("{:?}", self.dynamic_square_neighbor_kind))
-                            .show_ui(ui, |ui| {
-                                ui.selectable_value(
-                                    &mut self.dynamic_square_neighbor_kind,
-                                    SquareNeighborKind::Moore,
-                                    "Moore neighborhood",
-                                );
-                                ui.selectable_value(
-                                    &mut self.dynamic_square_neighbor_kind,
-                                    SquareNeighborKind::Neumann,
-                                    "Von Neumann Neighborhood",
-                                );
-                            });
+                        self.dynamic_square_neighbor_kind.combo_box(ui);
                     }
                 });
             });
         });
     }
 
+    fn draw_life3d_card(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        egui::Frame::group(ui.style()).show(ui, |ui| {
+            ui.set_width(self.card_width);
+            ui.set_height(self.card_height);
+            ui.vertical_centered(|ui| {
+                if ui
+                    .add(egui::ImageButton::new(
+                        self.thumbnail_lifelike.texture_id(ctx),
+                        self.thumbnail_lifelike.size_vec2(),
+                    ))
+                    .clicked()
+                {
+                    if let Some(rule) = Life3DRule::from_rule(&self.life3d_rule) {
+                        self.focus = Some(self.apps.len());
+                        self.apps.push((
+                            format!("{} (3D)", self.life3d_rule),
+                            Box::new(App::<World3D<Life3DRule>>::new(rule)),
+                        ));
+                    }
+                }
+                ui.label(egui::RichText::new("3D Life").size(20.0));
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("rule `{El}{Eu}{Fl}{Fu}` (e.g. 4555, 5766)");
+                    ui.add(egui::TextEdit::singleline(&mut self.life3d_rule));
+                });
+            });
+        });
+    }
+
     fn draw_card(&mut self, idx: usize, ctx: &egui::Context, ui: &mut egui::Ui) {
         match idx {
             0 => self.draw_dynamic_card(ctx, ui),
@@ -453,6 +569,7 @@ impl WrapApp {
             5 => self.draw_wireworld_card(ctx, ui),
             6 => self.draw_grayscott_card(ctx, ui),
             7 => self.draw_rock_paper_scissors(ctx, ui),
+            8 => self.draw_life3d_card(ctx, ui),
             _ => (),
         }
     }
@@ -541,10 +658,10 @@ impl eframe::App for WrapApp {
 
                 egui::ScrollArea::vertical().show(ui, |ui| {
                     let mut idx = 0;
-                    while idx < 8 {
+                    while idx < 9 {
                         ui.horizontal(|ui| {
                             for _ in 0..n_card_x {
-                                if 8 <= idx {
+                                if 9 <= idx {
                                     break;
                                 }
                                 self.draw_card(idx, ctx, ui);